@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use axum::Router;
+use coaxial::{
+    attrs,
+    config::Config,
+    context::Context,
+    html::{a, body, button, div, head, html, li, nav, p, ul, Content, Element},
+    live::live,
+    CoaxialResponse,
+};
+
+#[tokio::main]
+async fn main() {
+    let app = Router::new()
+        .route("/", live(counter))
+        .route("/other", live(counter))
+        .layer(
+            // the layout runs after the handler, and wraps the content it returned
+            Config::with_async_layout(|content, coaxial_adapter, mut context| async move {
+                let links = load_links().await;
+
+                html(
+                    Content::List(vec![
+                        head(Content::List(context.take_head()), Default::default()).into(),
+                        body(
+                            Content::List(vec![
+                                menu(links).into(),
+                                content.into(),
+                                coaxial_adapter.into(),
+                            ]),
+                            Default::default(),
+                        )
+                        .into(),
+                    ]),
+                    Default::default(),
+                )
+            })
+            .layer(),
+        );
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
+
+/// Stands in for loading the links from a database
+async fn load_links() -> Vec<(&'static str, &'static str)> {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    vec![("/", "home"), ("/other", "other page")]
+}
+
+fn menu(links: Vec<(&'static str, &'static str)>) -> Element {
+    nav(
+        ul(
+            Content::List(
+                links
+                    .into_iter()
+                    .map(|(href, label)| {
+                        li(a(label, attrs!("href" => href)), Default::default()).into()
+                    })
+                    .collect(),
+            ),
+            Default::default(),
+        ),
+        Default::default(),
+    )
+}
+
+async fn counter(mut ctx: Context) -> CoaxialResponse {
+    let counter = ctx.use_state(0i32);
+
+    let add = ctx.use_closure(move || async move {
+        counter.modify(|counter| counter + 1);
+    });
+
+    ctx.with(div(
+        Content::List(vec![
+            p(counter, Default::default()).into(),
+            button("+", attrs!("onclick" => add)).into(),
+        ]),
+        Default::default(),
+    ))
+}
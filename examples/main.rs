@@ -14,7 +14,7 @@ async fn main() {
         .route("/", live(counter))
         // this following layer call is optional since this is the default, i'm adding it for documentation purposes
         .layer(
-            Config::with_layout(|content, coaxial_adapter_script| {
+            Config::with_layout(|content, coaxial_adapter_script, _context| {
                 html(
                     Content::List(vec![
                         head(
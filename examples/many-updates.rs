@@ -0,0 +1,46 @@
+use axum::Router;
+use coaxial::{
+    attrs,
+    context::Context,
+    html::{button, div, span, Content},
+    live::live,
+    CoaxialResponse,
+};
+
+/// Updates a lot of states at once, to compare how the client applies them.
+///
+/// Record the page in the performance panel of the browser's dev tools while clicking the button.
+#[tokio::main]
+async fn main() {
+    let app = Router::new().route("/", live(counters));
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn counters(mut ctx: Context) -> CoaxialResponse {
+    let counters = (0..500).map(|_| ctx.use_state(0u32)).collect::<Vec<_>>();
+
+    let all = counters.clone();
+    let update = ctx.use_closure(move || {
+        let all = all.clone();
+        async move {
+            for counter in all {
+                counter.modify(|value| value + 1);
+            }
+        }
+    });
+
+    ctx.with(div(
+        Content::List(
+            std::iter::once(button("update all", attrs!("onclick" => update)).into())
+                .chain(
+                    counters
+                        .into_iter()
+                        .map(|counter| span(counter, Default::default()).into()),
+                )
+                .collect(),
+        ),
+        Default::default(),
+    ))
+}
@@ -0,0 +1,52 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::Router;
+use coaxial::{
+    attrs,
+    context::Context,
+    html::{button, div, p, Content},
+    live::live,
+    CoaxialResponse,
+};
+
+#[tokio::main]
+async fn main() {
+    let app = Router::new().route("/", live(relative_time));
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn relative_time(mut ctx: Context) -> CoaxialResponse {
+    let updated = ctx.use_state(now());
+
+    // recomputed every second, even though `updated` doesn't change
+    let label = ctx.use_timed(Duration::from_secs(1), move || {
+        let seconds = now().saturating_sub(*updated.get());
+        match seconds {
+            0 => "last updated just now".to_string(),
+            1 => "last updated 1 second ago".to_string(),
+            seconds => format!("last updated {seconds} seconds ago"),
+        }
+    });
+
+    let refresh = ctx.use_closure(move || async move {
+        updated.set(now());
+        label.invalidate();
+    });
+
+    ctx.with(div(
+        Content::List(vec![
+            p(label, Default::default()).into(),
+            button("refresh", attrs!("onclick" => refresh)).into(),
+        ]),
+        Default::default(),
+    ))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
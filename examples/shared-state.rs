@@ -36,12 +36,14 @@ async fn counter(
     });
 
     let state = state.clone();
-    tokio::spawn(async move {
+    let task = tokio::spawn(async move {
         let mut rx = state.tx.subscribe();
         while let Ok(()) = rx.recv().await {
             counter.set(state.counter.load(std::sync::atomic::Ordering::SeqCst));
         }
     });
+    // otherwise the task keeps running after the client leaves
+    ctx.on_disconnect(move || task.abort());
 
     ctx.with(div(
         Content::List(vec![
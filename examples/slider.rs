@@ -0,0 +1,45 @@
+use axum::Router;
+use coaxial::{
+    context::Context,
+    html::{b, div, p, range_input, style, Content, ContentValue},
+    live::live,
+    CoaxialResponse,
+};
+
+#[tokio::main]
+async fn main() {
+    let app = Router::new().route("/", live(slider));
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn slider(mut ctx: Context) -> CoaxialResponse {
+    let celsius = ctx.use_state(20i32);
+
+    let fahrenheit = ctx.use_computed(celsius, |celsius| *celsius as f64 * 9.0 / 5.0 + 32.0);
+
+    ctx.with(div(
+        Content::List(vec![
+            style(
+                ContentValue::Raw(
+                    html_escape::encode_style(include_str!("styles.css")).to_string(),
+                ),
+                Default::default(),
+            )
+            .into(),
+            range_input(celsius, -40, 100, 1).into(),
+            p(
+                Content::List(vec![
+                    b(celsius, Default::default()).into(),
+                    "°C = ".into(),
+                    b(fahrenheit, Default::default()).into(),
+                    "°F".into(),
+                ]),
+                Default::default(),
+            )
+            .into(),
+        ]),
+        Default::default(),
+    ))
+}
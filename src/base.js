@@ -1,63 +1,404 @@
 class Coaxial {
-    constructor(seed = null) {
+    /**
+     * @param {string|null} seed seed the page was rendered with
+     * @param {string|null} path path the websocket connects to, defaults to the page's
+     * @param {{seedParam?: string, reconnectDelays?: number[], nonce?: string|null}} options
+     */
+    constructor(seed = null, path = null, options = {}) {
         this.state = {};
+        this.values = {};
+        // states whose value is JSON, which getValue parses
+        this.json = new Set();
+        // last value received for each state, which patches are applied to
+        this.received = {};
         this.stateChangeListeners = {};
+        // scope that new listeners are tagged with, while registering the listeners of a computed element
+        this.scope = null;
+        this.refreshing = new Set();
+        // state id -> { delay, timeout }
+        this.debounces = {};
+        // state id -> { last, timeout, value }
+        this.throttles = {};
+        // state id -> values sent with setState that the server hasn't echoed back yet, oldest first
+        this.inFlight = {};
+        // updates held between a Begin and a Commit
+        this.batch = null;
+        // messages waiting for the next animation frame, in the order they arrived
+        this.queue = [];
+        this.frame = null;
+
+        this.seed = seed;
+        this.path = path;
+        this.seedParam = options.seedParam ?? 'coaxial-seed';
+        // milliseconds to wait before each reconnect attempt. attempts after the last one wait the last delay
+        this.reconnectDelays = options.reconnectDelays ?? [];
+        // the scripts of computed elements need it to run on pages with a `Content-Security-Policy`
+        this.nonce = options.nonce ?? null;
+        this.attempt = 0;
+        this.reloading = false;
+
+        this.connect(false);
+    }
 
+    /**
+     * Opens the websocket.
+     *
+     * @param {boolean} reconnect whether the page was already connected, so the server resumes its context
+     */
+    connect(reconnect) {
         const url = new URL(window.location);
-        if (seed) url.searchParams.append('coaxial-seed', seed);
+        if (this.path) url.pathname = this.path;
+        if (this.seed) url.searchParams.append(this.seedParam, this.seed);
+        if (reconnect) url.searchParams.append('coaxial-reconnect', '1');
 
         this.conn = new WebSocket(url);
         this.conn.onopen = () => {
             console.log('Connected.');
+            this.attempt = 0;
             /* this.send({t: 'init'}); */
         };
         this.conn.onmessage = async (e) => {
             const msg = JSON.parse(e.data);
 
-            if (msg.t === 'Update') {
-                for (const [field, value] of msg.fields) {
-                    this.state[field] = value;
-
-                    // TODO delete this
-                    document.querySelectorAll(`[coax-change-${field}]`).forEach(el => {
-                        let name = el.getAttribute(`coax-change-${field}`);
-                        el.setAttribute(name, value);
-                    });
+            if (msg.t === 'Update' || msg.t === 'ListUpdate') {
+                if (this.batch) this.batch.updates.push(msg);
+                else this.queueMessage(msg);
+            } else if (msg.t === 'Begin') {
+                // a Begin without a Commit is applied as is before starting the new batch
+                this.commitBatch();
+                this.batch = {
+                    updates: [],
+                    // in case the Commit never arrives
+                    timeout: setTimeout(() => this.commitBatch(), 1000),
+                };
+            } else if (msg.t === 'Commit') {
+                // a Commit without a Begin has nothing to apply.
+                // the batch is applied right away, so messages after the Commit are always applied after it
+                this.commitBatch();
+            } else if (msg.t === 'Error') {
+                console.error('Coaxial:', msg.message);
+            } else if (msg.t === 'Reload') {
+                // the new page connects again, so this one doesn't reconnect
+                this.reloading = true;
+                location.reload();
+            } else if (msg.t === 'Navigate') {
+                this.reloading = true;
+                location.assign(msg.url);
+            } else if (msg.t === 'ReplaceState') {
+                history.replaceState(history.state, '', msg.url);
+            }
+        };
+        this.conn.onclose = (e) => {
+            this.commitBatch();
+            if (this.reloading) return;
 
-                    this.callOnChange(field, value);
-                }
+            // the server doesn't have the context of this page anymore, like after a restart
+            if (e.code === 4000) {
+                location.reload();
+                return;
             }
+            this.reconnect();
+        };
+    }
+
+    /**
+     * Opens the websocket again after the delay for the current attempt.
+     */
+    reconnect() {
+        if (this.reconnectDelays.length === 0) return;
+
+        // values sent through the closed connection might never be echoed back
+        this.inFlight = {};
+
+        const delays = this.reconnectDelays;
+        const delay = delays[Math.min(this.attempt, delays.length - 1)];
+        this.attempt += 1;
+        setTimeout(() => this.connect(true), delay);
+    }
+
+    /**
+     * Applies the updates of a batch immediately, if there is one.
+     */
+    commitBatch() {
+        if (!this.batch) return;
+
+        clearTimeout(this.batch.timeout);
+        const updates = this.batch.updates;
+        this.batch = null;
+        updates.forEach(update => this.queueMessage(update));
+    }
+
+    /**
+     * Queues a message to be applied on the next animation frame.
+     *
+     * All the messages that arrive within a frame are applied together, so the browser can lay out
+     * the page once per frame instead of once per message. `examples/many-updates.rs` sends a lot of
+     * updates at once, to compare them in the performance panel of the dev tools.
+     * Frames don't run in background tabs, so their messages are applied when the tab is shown.
+     */
+    queueMessage(msg) {
+        this.queue.push(msg);
+        this.frame ??= requestAnimationFrame(() => this.flushQueue());
+    }
+
+    flushQueue() {
+        this.frame = null;
+        const queue = this.queue;
+        this.queue = [];
+        queue.forEach(msg => this.applyMessage(msg));
+    }
+
+    applyMessage(msg) {
+        if (msg.t === 'ListUpdate') this.applyListUpdate(msg);
+        else this.applyUpdate(msg);
+    }
+
+    /**
+     * Applies the operations of a keyed list to its children, in order.
+     */
+    applyListUpdate(msg) {
+        const list = document.querySelector(`[coax-id="${msg.element_id}"]`);
+        if (!list) return;
+
+        const item = (key) => key === null
+            ? null
+            : Array.from(list.children).find(child => child.getAttribute('coax-key') === key);
+        const parse = (html) => {
+            const template = document.createElement('template');
+            template.innerHTML = html;
+            return template.content;
         };
+
+        for (const op of msg.ops) {
+            if (op.op === 'Remove') {
+                item(op.key)?.remove();
+            } else if (op.op === 'Replace') {
+                item(op.key)?.replaceWith(parse(op.html));
+            } else if (op.op === 'Insert') {
+                list.insertBefore(parse(op.html), item(op.before));
+            } else if (op.op === 'Move') {
+                const el = item(op.key);
+                if (el) list.insertBefore(el, item(op.before));
+            }
+        }
+    }
+
+    applyUpdate(msg) {
+        const values = Object.fromEntries(msg.values ?? []);
+
+        for (let [field, value] of msg.fields) {
+            // states created with `use_state_diffed` can send a range of the previous value to replace
+            if (Array.isArray(value)) {
+                const [start, end, text] = value;
+                const previous = this.received[field] ?? '';
+                value = previous.slice(0, start) + text + previous.slice(end);
+            }
+            this.received[field] = value;
+
+            if (this.isStaleEcho(field, value)) continue;
+
+            this.state[field] = value;
+            if (field in values) this.values[field] = values[field];
+            else delete this.values[field];
+
+            // TODO delete this
+            document.querySelectorAll(`[coax-change-${field}]`).forEach(el => {
+                let name = el.getAttribute(`coax-change-${field}`);
+                el.setAttribute(name, value);
+            });
+
+            this.callOnChange(field, value);
+        }
+    }
+
+    /**
+     * Returns whether `value` is the echo of a value sent with setState, which has been replaced
+     * by a newer one that is still on its way.
+     *
+     * Applying it would reset inputs bound to the state to an older value while the user types.
+     *
+     * @param {string} id
+     * @param {string} value
+     */
+    isStaleEcho(id, value) {
+        const sent = this.inFlight[id];
+        if (!sent) return false;
+
+        const idx = sent.indexOf(value);
+        if (idx === -1) return false;
+
+        sent.splice(0, idx + 1);
+        if (sent.length === 0) delete this.inFlight[id];
+        return sent.length > 0;
+    }
+
+    /**
+     * Returns the typed value of a state, falling back to the display string.
+     *
+     * @param {string} id
+     */
+    getValue(id) {
+        if (id in this.values) return this.values[id];
+        if (this.json.has(id) && id in this.state) return JSON.parse(this.state[id]);
+        return this.state[id];
     }
 
-    callClosure(closure) {
+    /**
+     * Marks a state as holding JSON, so getValue parses it.
+     *
+     * @param {string} id
+     */
+    jsonState(id) {
+        this.json.add(id);
+    }
+
+    /**
+     * Calls a closure on the server.
+     *
+     * @param {string} closure id of the closure
+     * @param {any} args arguments for closures created with `use_closure_with_args`
+     */
+    callClosure(closure, args = null) {
         this.send({
             t: 'Closure',
-            closure
+            closure,
+            args,
         });
     }
 
+    /**
+     * Sets a state on the server. The server sends the change back, like any other change.
+     *
+     * @param {string} id id of the state
+     * @param {any} value new value, which is deserialized into the type of the state
+     */
     setState(id, value) {
+        const debounce = this.debounces[id];
+        if (debounce) {
+            // only the last value set during the delay is sent
+            clearTimeout(debounce.timeout);
+            debounce.timeout = setTimeout(() => {
+                debounce.timeout = null;
+                this.sendState(id, value);
+            }, debounce.delay);
+            return;
+        }
+
+        this.sendState(id, value);
+        // if we want the setState to be "predictive", we can set the state here and run the listeners
+    }
+
+    sendState(id, value) {
+        (this.inFlight[id] ??= []).push(String(value));
         this.send({
             t: 'SetState',
             id,
             value
         });
-        // if we want the setState to be "predictive", we can set the state here and run the listeners
     }
 
-    onEvent(name, params) {
+    /**
+     * Makes setState wait until no new value has been set for `delay` milliseconds before sending it.
+     *
+     * @param {string} id
+     * @param {number} delay
+     */
+    debounceState(id, delay) {
+        this.debounces[id] = { delay, timeout: null };
+    }
+
+    /**
+     * Sets a state once no new value has been set for `delay` milliseconds.
+     *
+     * @param {string} id
+     * @param {any} value
+     * @param {number} delay
+     */
+    setStateDebounced(id, value, delay) {
+        this.debounces[id] ??= { delay, timeout: null };
+        this.setState(id, value);
+    }
+
+    /**
+     * Sets a state at most once every `interval` milliseconds.
+     *
+     * Values set in between replace each other, and the last one is sent once the interval is over,
+     * so the server always ends up with the latest value.
+     *
+     * @param {string} id
+     * @param {any} value
+     * @param {number} interval
+     */
+    setStateThrottled(id, value, interval) {
+        const throttle = (this.throttles[id] ??= { last: 0, timeout: null, value: null });
+        throttle.value = value;
+        if (throttle.timeout) return;
+
+        const send = () => {
+            throttle.timeout = null;
+            throttle.last = Date.now();
+            this.setState(id, throttle.value);
+        };
+
+        const wait = throttle.last + interval - Date.now();
+        if (wait <= 0) send();
+        else throttle.timeout = setTimeout(send, wait);
+    }
+
+    onEvent(name, params, element = null) {
         this.send({
             t: 'Event',
             name,
-            params
+            params,
+            element
         });
     }
 
+    /**
+     * Collects the fields of a form into an object, with the name of each field as its key.
+     *
+     * Checkboxes are sent as booleans, unless several share a name, in which case the values of the
+     * checked ones are sent as a list. Selects with `multiple` are always sent as a list.
+     *
+     * @param {HTMLFormElement} form
+     */
+    formData(form) {
+        const data = {};
+        for (const el of form.elements) {
+            if (!el.name || el.disabled) continue;
+
+            if (el.type === 'checkbox') {
+                const group = form.querySelectorAll(`input[type="checkbox"][name="${CSS.escape(el.name)}"]`);
+                if (group.length > 1) {
+                    data[el.name] ??= [];
+                    if (el.checked) data[el.name].push(el.value);
+                } else {
+                    data[el.name] = el.checked;
+                }
+            } else if (el.type === 'radio') {
+                if (el.checked) data[el.name] = el.value;
+            } else if (el instanceof HTMLSelectElement && el.multiple) {
+                data[el.name] = Array.from(el.selectedOptions, (option) => option.value);
+            } else if (!['submit', 'button', 'reset', 'image', 'file'].includes(el.type)) {
+                data[el.name] = el.value;
+            }
+        }
+        return data;
+    }
+
     send(body) {
         this.conn.send(JSON.stringify(body));
     }
 
+    /**
+     * Send a frame as is, to be received by the handlers set with `on_binary` or `on_text`.
+     *
+     * @param {string|ArrayBuffer|Blob|ArrayBufferView} data
+     */
+    sendFrame(data) {
+        this.conn.send(data);
+    }
+
     /**
      * Add a listener for a state.
      * The listener will be called when the state is updated.
@@ -79,6 +420,7 @@ class Coaxial {
             return;
         }
 
+        if (this.scope) closure.scope = this.scope;
         if (this.stateChangeListeners[id] === undefined) {
             this.stateChangeListeners[id] = [closure];
         } else {
@@ -86,6 +428,99 @@ class Coaxial {
         }
     }
 
+    /**
+     * Runs the listeners of the provided states again with their current values.
+     *
+     * States that are already being refreshed are skipped, so listeners that refresh each other don't loop.
+     *
+     * @param {string[]} ids
+     */
+    refresh(ids) {
+        for (const id of ids) {
+            if (this.refreshing.has(id) || !(id in this.state)) continue;
+
+            this.refreshing.add(id);
+            this.callOnChange(id, this.state[id]);
+            this.refreshing.delete(id);
+        }
+    }
+
+    /**
+     * Calls `register`, tagging the listeners it adds with `scope`.
+     *
+     * @param {string} scope
+     * @param {() => void} register
+     */
+    scoped(scope, register) {
+        const previous = this.scope;
+        this.scope = scope;
+        try {
+            register();
+        } finally {
+            this.scope = previous;
+        }
+    }
+
+    /**
+     * Replaces the children of an element with a computed element,
+     * and the listeners of the previous one with the ones of the new one.
+     *
+     * @param {Element} el
+     * @param {string} value JSON with the `html` of the computed element, and the `script` that makes it reactive
+     * @param {string} scope tag of the listeners of the computed element
+     */
+    setHtml(el, value, scope) {
+        const { html, script } = JSON.parse(value);
+
+        for (const id in this.stateChangeListeners) {
+            this.stateChangeListeners[id] = this.stateChangeListeners[id].filter(closure => closure.scope !== scope);
+        }
+        el.innerHTML = html;
+
+        // a script element, unlike eval, can run on pages whose `Content-Security-Policy` has a nonce
+        const tag = document.createElement('script');
+        if (this.nonce) tag.nonce = this.nonce;
+        tag.textContent = `window.Coaxial.scoped('${scope}', () => { ${script} });`;
+        document.head.appendChild(tag).remove();
+    }
+
+    /**
+     * Swaps an element with a placeholder `<template>` with the same id, or the other way around.
+     *
+     * @param {Element} el the element, or its placeholder
+     * @param {boolean} visible
+     * @param {string} html the element's html, used when adding it back
+     * @param {string[]} refresh states to re-apply after adding it back
+     */
+    show(el, visible, html, refresh) {
+        const hidden = el.tagName === 'TEMPLATE';
+
+        if (visible && hidden) {
+            const template = document.createElement('template');
+            template.innerHTML = html;
+            el.replaceWith(template.content);
+            this.refresh(refresh);
+        } else if (!visible && !hidden) {
+            const placeholder = document.createElement('template');
+            placeholder.setAttribute('coax-id', el.getAttribute('coax-id'));
+            el.replaceWith(placeholder);
+        }
+    }
+
+    /**
+     * Formats a number with `Intl.NumberFormat`. Values that aren't numbers are returned as they are.
+     *
+     * @param {string} value
+     * @param {string|null} locale the browser's locale is used if null
+     * @param {object} options
+     */
+    formatNumber(value, locale, options) {
+        const number = Number(value);
+        if (value === '' || Number.isNaN(number)) return value;
+
+        return new Intl.NumberFormat(locale ?? undefined, options).format(number);
+    }
+
     callOnChange(id, value) {
         if (this.stateChangeListeners[id] === undefined) {
             return;
@@ -97,10 +532,6 @@ class Coaxial {
     }
 }
 
-document.addEventListener("DOMContentLoaded", () => {
-    window.Coaxial = new Coaxial('__internal__coaxialSeed');
-});
-
 // https://stackoverflow.com/a/34519193
 function stringifyEvent(e) {
     const obj = {};
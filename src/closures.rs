@@ -6,25 +6,36 @@ use tokio::{
     task::JoinSet,
 };
 
-use crate::random_id::RandomId;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    computed::ComputedState, events::EventHandler, html::ClosureDescriptor, random_id::RandomId,
+};
 
 pub(crate) struct Closures<S> {
     closures: HashMap<RandomId, Arc<dyn ClosureTrait<S>>>,
 
-    pub(crate) call_rx: UnboundedReceiver<RandomId>,
-    pub(crate) call_tx: UnboundedSender<RandomId>,
+    /// Calls to closures, with the arguments sent by the client
+    pub(crate) call_rx: UnboundedReceiver<(RandomId, Value)>,
+    pub(crate) call_tx: UnboundedSender<(RandomId, Value)>,
 
-    join_set: JoinSet<()>,
+    pub(crate) join_set: JoinSet<()>,
 }
 
 impl<S> Closures<S> {
     pub(crate) fn insert(&mut self, id: RandomId, closure: Arc<dyn ClosureTrait<S>>) {
         self.closures.insert(id, closure);
     }
+
+    #[cfg(any(test, feature = "test"))]
+    pub(crate) fn get(&self, id: RandomId) -> Option<Arc<dyn ClosureTrait<S>>> {
+        self.closures.get(&id).cloned()
+    }
 }
 
 impl<S: Clone + Send + 'static> Closures<S> {
-    pub(crate) fn run(&mut self, id: RandomId, parts: &Parts, state: &S) {
+    pub(crate) fn run(&mut self, id: RandomId, args: Value, parts: &Parts, state: &S) {
         let Some(closure) = self.closures.get(&id) else {
             // this is a fatal error
             return;
@@ -35,7 +46,7 @@ impl<S: Clone + Send + 'static> Closures<S> {
         let state = state.clone();
 
         self.join_set
-            .spawn(async move { closure.call(parts, state).await });
+            .spawn(async move { closure.call(args, parts, state).await });
     }
 }
 
@@ -59,7 +70,7 @@ pub struct Closure {
 }
 
 pub(crate) struct ClosureInner {
-    pub(crate) closure_call_tx: UnboundedSender<RandomId>,
+    pub(crate) closure_call_tx: UnboundedSender<(RandomId, Value)>,
 }
 
 impl Closure {
@@ -68,13 +79,42 @@ impl Closure {
     /// Note: this doesn't call the closure immediately.
     /// Keep in mind, the closure will not be run until the websocket connection has been established.
     pub fn call(&self) {
-        self.inner.read().closure_call_tx.send(self.id).unwrap();
+        self.inner
+            .read()
+            .closure_call_tx
+            .send((self.id, Value::Null))
+            .unwrap();
+    }
+
+    /// Returns an attribute value that calls the closure with `args` when run on the client,
+    /// like `onclick="window.Coaxial.callClosure('id', {\"itemId\":5})"`.
+    ///
+    /// The closure needs to be created with `Context::use_closure_with_args` to receive them.
+    pub fn with_args<A: Serialize>(&self, args: A) -> ClosureDescriptor {
+        ClosureDescriptor {
+            closure_id: self.id,
+            args: Some(serde_json::to_string(&args).unwrap()),
+        }
     }
 }
 
+/// A closure that changes depending on some states.
+///
+/// Can be used as an attribute like a [`Closure`], and the attribute will call the current closure.
+/// Created with `Context::use_closure_switch`.
+#[derive(Clone, Copy)]
+pub struct ClosureSwitch {
+    pub(crate) active: ComputedState<RandomId>,
+}
+
 /// Trait used to type-erase all closures, so they can be stored in the same HashMap
 pub trait ClosureTrait<S>: Send + Sync {
-    fn call<'a>(&'a self, parts: Parts, state: S) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+    fn call<'a>(
+        &'a self,
+        args: Value,
+        parts: Parts,
+        state: S,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
 }
 
 impl<S, F, Fut> ClosureTrait<S> for ClosureWrapper<F, ()>
@@ -82,11 +122,33 @@ where
     F: Fn() -> Fut + Send + Sync,
     Fut: Future<Output = ()> + Send + Sync + 'static,
 {
-    fn call(&self, _parts: Parts, _state: S) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
+    fn call(
+        &self,
+        _args: Value,
+        _parts: Parts,
+        _state: S,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
         Box::pin((self.func)())
     }
 }
 
+/// Closure that receives the arguments sent by the client, deserialized like the fields of an event
+pub(crate) struct ArgsClosure<H>(pub(crate) H);
+
+impl<S, H> ClosureTrait<S> for ArgsClosure<H>
+where
+    H: EventHandler<S>,
+{
+    fn call<'a>(
+        &'a self,
+        args: Value,
+        parts: Parts,
+        state: S,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        self.0.call(args, parts, state)
+    }
+}
+
 macro_rules! impl_closure_trait {
     (
         $($ty:ident),*
@@ -101,6 +163,7 @@ macro_rules! impl_closure_trait {
         {
             fn call<'a>(
                 &'a self,
+                _args: Value,
                 mut parts: Parts,
                 state: S,
             ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
@@ -108,7 +171,15 @@ macro_rules! impl_closure_trait {
                     $(
                         let $ty = match $ty::from_request_parts(&mut parts, &state).await {
                             Ok(value) => value,
-                            Err(_rejection) => todo!("rejections aren't handled yet"),
+                            Err(rejection) => {
+                                let response = axum::response::IntoResponse::into_response(rejection);
+                                tracing::warn!(
+                                    extractor = std::any::type_name::<$ty>(),
+                                    status = %response.status(),
+                                    "extractor rejected the request, the handler was not run"
+                                );
+                                return;
+                            }
                         };
                     )*
 
@@ -183,6 +254,7 @@ all_the_tuples!(impl_into_closure);
 #[cfg(test)]
 mod tests {
     use axum::http::{request::Parts, Request};
+    use serde_json::Value;
 
     use crate::context::Context;
 
@@ -204,12 +276,74 @@ mod tests {
 
         // we run the closure manually, not by calling call
         // call relies on the websocket loop to be running
-        ctx.closures.run(closure.id, &parts(), &());
+        ctx.closures.run(closure.id, Value::Null, &parts(), &());
         ctx.closures.join_set.join_next().await.unwrap().unwrap();
 
         assert_eq!(1, *state.get());
     }
 
+    #[tokio::test]
+    async fn test_closure_can_extract_path_params() {
+        use axum::{
+            body::Body,
+            extract::{Path, Request as AxumRequest},
+            routing::get,
+            Router,
+        };
+        use tokio::sync::mpsc::unbounded_channel;
+        use tower::ServiceExt;
+
+        // run a request through a router, so the path params get extracted
+        let (parts_tx, mut parts_rx) = unbounded_channel();
+        let app = Router::new().route(
+            "/:id",
+            get(move |request: AxumRequest| async move {
+                parts_tx.send(request.into_parts().0).unwrap();
+            }),
+        );
+        app.oneshot(Request::get("/42").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let parts = parts_rx.recv().await.unwrap();
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(0u32);
+
+        let closure = ctx.use_closure(move |Path(id): Path<u32>| async move {
+            state.set(id);
+        });
+
+        ctx.closures.run(closure.id, Value::Null, &parts, &());
+        ctx.closures.join_set.join_next().await.unwrap().unwrap();
+
+        assert_eq!(42, *state.get());
+    }
+
+    #[tokio::test]
+    async fn test_closure_is_skipped_when_extractor_rejects() {
+        use axum::extract::Query;
+
+        #[derive(Deserialize)]
+        struct Params {
+            amount: u32,
+        }
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(0u32);
+
+        let closure = ctx.use_closure(move |Query(params): Query<Params>| async move {
+            state.set(params.amount);
+        });
+
+        // the request has no query, so the extractor fails
+        ctx.closures.run(closure.id, Value::Null, &parts(), &());
+        ctx.closures.join_set.join_next().await.unwrap().unwrap();
+
+        assert_eq!(0, *state.get());
+    }
+
     #[tokio::test]
     async fn test_update_string_state_in_closure() {
         let mut ctx = Context::<()>::new(0, true);
@@ -222,9 +356,47 @@ mod tests {
 
         // we run the closure manually, not by calling call
         // call relies on the websocket loop to be running
-        ctx.closures.run(closure.id, &parts(), &());
+        ctx.closures.run(closure.id, Value::Null, &parts(), &());
         ctx.closures.join_set.join_next().await.unwrap().unwrap();
 
         assert_eq!("other string", *state.get());
     }
+
+    #[tokio::test]
+    async fn test_closure_with_typed_args() {
+        use serde_json::json;
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Item {
+            item_id: u32,
+        }
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(0u32);
+
+        let closure = ctx.use_closure_with_args(move |item: Item| async move {
+            state.set(item.item_id);
+        });
+
+        // the client sends the arguments rendered in the attribute
+        let button = crate::html::button(
+            "remove",
+            crate::attrs!("onclick" => closure.with_args(json!({ "itemId": 5 }))),
+        );
+        assert_eq!(
+            format!(
+                "<button onclick=\"window.Coaxial.callClosure('{}', {{&quot;itemId&quot;:5}})\">remove</button>",
+                closure.id
+            ),
+            button.render_to_string()
+        );
+
+        ctx.closures
+            .run(closure.id, json!({ "itemId": 5 }), &parts(), &());
+        ctx.closures.join_set.join_next().await.unwrap().unwrap();
+
+        assert_eq!(5, *state.get());
+    }
 }
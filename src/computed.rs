@@ -1,9 +1,26 @@
-use std::{collections::HashMap, fmt::Display, future::Future, pin::Pin, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
 
+use generational_box::{GenerationalBox, SyncStorage};
 use serde::de::DeserializeOwned;
-use tokio::task::JoinSet;
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Semaphore,
+    },
+    task::{AbortHandle, JoinSet},
+};
 
 use crate::{
+    context::Context,
+    html::StateDescriptor,
     random_id::RandomId,
     states::{State, StateGet},
 };
@@ -12,19 +29,159 @@ pub(crate) type OnChangeHandler = Arc<dyn Fn() + 'static + Send + Sync>;
 pub(crate) type OnChangeHandlerAsync =
     Arc<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send + Sync>> + Send + Sync>;
 
-#[derive(Default)]
+/// An async handler, and the id of the computed state it recomputes if only
+/// its latest run should be kept
+#[derive(Clone)]
+pub(crate) struct AsyncListener {
+    handler: OnChangeHandlerAsync,
+    latest_only: Option<RandomId>,
+    /// Time to wait before running, without holding a slot of the concurrency limit
+    debounce: Option<Duration>,
+}
+
 pub(crate) struct ComputedStates {
     on_change_handler: HashMap<RandomId, Vec<OnChangeHandler>>,
-    on_change_handler_async: HashMap<RandomId, Vec<OnChangeHandlerAsync>>,
+    on_change_handler_async: HashMap<RandomId, Vec<AsyncListener>>,
+
+    /// recompute handlers, indexed by the id of the computed state
+    recompute_handler: HashMap<RandomId, OnChangeHandler>,
+    recompute_handler_async: HashMap<RandomId, AsyncListener>,
+
+    /// the running recompute of the computed states that only keep their latest run
+    running: HashMap<RandomId, AbortHandle>,
+
+    pub(crate) invalidate_rx: UnboundedReceiver<RandomId>,
+    pub(crate) invalidate_tx: UnboundedSender<RandomId>,
 
     /// to track async tasks for recomputing async computed states
-    join_set: JoinSet<()>,
+    pub(crate) join_set: JoinSet<()>,
+    /// limits how many async recomputes can run at the same time
+    semaphore: Option<Arc<Semaphore>>,
+
+    /// Whether the websocket is connected. Until it is, recomputes that should run right away
+    /// are kept in `deferred`, since there's no one to send their results to
+    connected: bool,
+    deferred: Vec<AsyncListener>,
+    /// Tick loops of the timed computed states and intervals, which run while the websocket is connected
+    timers: Vec<OnChangeHandlerAsync>,
+    /// The running tick loops, which are aborted when the websocket disconnects
+    running_timers: Vec<AbortHandle>,
+}
+
+impl Default for ComputedStates {
+    fn default() -> Self {
+        let (invalidate_tx, invalidate_rx) = unbounded_channel();
+
+        Self {
+            on_change_handler: Default::default(),
+            on_change_handler_async: Default::default(),
+            recompute_handler: Default::default(),
+            recompute_handler_async: Default::default(),
+            running: Default::default(),
+            invalidate_rx,
+            invalidate_tx,
+            join_set: Default::default(),
+            semaphore: None,
+            connected: false,
+            deferred: Vec::new(),
+            timers: Vec::new(),
+            running_timers: Vec::new(),
+        }
+    }
 }
 
 impl ComputedStates {
+    /// Limits the amount of async recomputes that run at the same time.
+    /// The rest wait until one of the running ones finishes.
+    pub(crate) fn set_concurrency_limit(&mut self, limit: Option<NonZeroUsize>) {
+        self.semaphore = limit.map(|limit| Arc::new(Semaphore::new(limit.get())));
+    }
+
+    fn spawn(
+        &mut self,
+        future: Pin<Box<dyn Future<Output = ()> + Send + Sync>>,
+        delay: Option<Duration>,
+    ) -> AbortHandle {
+        let semaphore = self.semaphore.clone();
+
+        self.join_set.spawn(async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+
+            let _permit = match semaphore {
+                Some(semaphore) => Some(semaphore.acquire_owned().await),
+                None => None,
+            };
+
+            future.await
+        })
+    }
+
+    /// Marks the websocket as connected, and runs the recomputes that were deferred until it was
+    pub(crate) fn connect(&mut self) {
+        self.connected = true;
+
+        for listener in std::mem::take(&mut self.deferred) {
+            self.run_async(&listener);
+        }
+        for timer in self.timers.clone() {
+            self.start_timer(&timer);
+        }
+    }
+
+    /// Marks the websocket as disconnected, and stops the tick loops until it connects again.
+    ///
+    /// The context can be kept for the client to reconnect to, so nothing would read their changes
+    pub(crate) fn disconnect(&mut self) {
+        self.connected = false;
+
+        for timer in self.running_timers.drain(..) {
+            timer.abort();
+        }
+    }
+
+    fn start_timer(&mut self, timer: &OnChangeHandlerAsync) {
+        // timers never finish, so they don't take a slot of the concurrency limit
+        let handle = self.join_set.spawn(timer());
+        self.running_timers.push(handle);
+    }
+
+    /// Runs an async listener now if the websocket is connected, or once it connects otherwise
+    fn run_when_connected(&mut self, listener: &AsyncListener) {
+        if self.connected {
+            self.run_async(listener);
+        } else {
+            self.deferred.push(listener.clone());
+        }
+    }
+
+    /// Runs an async listener, aborting its previous run if it only keeps the latest one
+    fn run_async(&mut self, listener: &AsyncListener) {
+        let Some(id) = listener.latest_only else {
+            self.spawn((listener.handler)(), listener.debounce);
+            return;
+        };
+
+        if let Some(previous) = self.running.remove(&id) {
+            previous.abort();
+        }
+        // only the runs that haven't finished need to be kept around to be aborted
+        self.running.retain(|_, handle| !handle.is_finished());
+        let handle = self.spawn((listener.handler)(), listener.debounce);
+        self.running.insert(id, handle);
+    }
+
+    /// Returns whether any computed state or listener depends on the state `id`
+    #[cfg(debug_assertions)]
+    pub(crate) fn has_dependents(&self, id: RandomId) -> bool {
+        self.on_change_handler.contains_key(&id) || self.on_change_handler_async.contains_key(&id)
+    }
+
     pub(crate) fn add_computed<O, I, F>(
         &mut self,
         state: State<O>,
+        inner: GenerationalBox<ComputedInner, SyncStorage>,
         states: I,
         compute: F,
     ) -> ComputedState<O>
@@ -33,31 +190,119 @@ impl ComputedStates {
         I: StateGetter + Send + Sync + 'static,
         F: Fn(<I as StateGetter>::Output<'_>) -> O + Send + Sync + 'static,
     {
-        let compute = Arc::new(compute);
-        for id in states.id_list() {
-            let compute = compute.clone();
-            let states = states.clone();
-            let on_change_listener = move || {
-                state.set(compute(states.get()));
-            };
+        let _states = states.clone();
+        let on_change_listener: OnChangeHandler = Arc::new(move || {
+            state.set(compute(_states.get()));
+        });
 
+        for id in states.id_list() {
             if let Some(value) = self.on_change_handler.get_mut(&id) {
-                value.push(Arc::new(on_change_listener));
+                value.push(on_change_listener.clone());
             } else {
                 self.on_change_handler
-                    .insert(id, vec![Arc::new(on_change_listener)]);
+                    .insert(id, vec![on_change_listener.clone()]);
             }
         }
 
-        ComputedState(state)
+        self.recompute_handler.insert(state.id, on_change_listener);
+
+        ComputedState(state, inner)
+    }
+
+    /// Adds a computed state that is recomputed every `period` while the websocket is connected.
+    ///
+    /// The tick loop runs in `join_set`, so it stops when the context is dropped
+    pub(crate) fn add_timed<O, F>(
+        &mut self,
+        state: State<O>,
+        inner: GenerationalBox<ComputedInner, SyncStorage>,
+        period: Duration,
+        compute: F,
+    ) -> ComputedState<O>
+    where
+        O: DeserializeOwned + Display + Send + Sync + 'static,
+        F: Fn() -> O + Send + Sync + 'static,
+    {
+        let recompute: OnChangeHandler = Arc::new(move || state.set(compute()));
+
+        // the value was just computed, so the first tick is skipped
+        let tick = recompute.clone();
+        self.add_interval(period, move || tick());
+
+        self.recompute_handler.insert(state.id, recompute);
+
+        ComputedState(state, inner)
+    }
+
+    /// Runs `tick` every `period` while the websocket is connected, starting one `period` after it connects.
+    ///
+    /// The tick loop runs in `join_set`, so it stops when the websocket disconnects or the context is dropped
+    pub(crate) fn add_interval(
+        &mut self,
+        period: Duration,
+        tick: impl Fn() + Send + Sync + 'static,
+    ) {
+        let tick = Arc::new(tick);
+        let timer: OnChangeHandlerAsync = Arc::new(move || {
+            let tick = tick.clone();
+            Box::pin(async move {
+                let start = tokio::time::Instant::now() + period;
+                let mut interval = tokio::time::interval_at(start, period);
+                interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                loop {
+                    interval.tick().await;
+                    tick();
+                }
+            })
+        });
+        if self.connected {
+            self.start_timer(&timer);
+        }
+        self.timers.push(timer);
+    }
+
+    /// Like `add_computed`, but `compute` returns the values of all the states in `outputs` at once
+    pub(crate) fn add_computed_multi<O, I, F>(
+        &mut self,
+        outputs: O::States,
+        inner: GenerationalBox<ComputedInner, SyncStorage>,
+        states: I,
+        compute: F,
+    ) -> O::Computed
+    where
+        O: ComputedOutputs,
+        I: StateGetter + Send + Sync + 'static,
+        F: Fn(<I as StateGetter>::Output<'_>) -> O + Send + Sync + 'static,
+    {
+        let _states = states.clone();
+        let on_change_listener: OnChangeHandler = Arc::new(move || {
+            compute(_states.get()).set(&outputs);
+        });
+
+        for id in states.id_list() {
+            self.on_change_handler
+                .entry(id)
+                .or_default()
+                .push(on_change_listener.clone());
+        }
+
+        // recomputing any of the outputs recomputes all of them
+        for id in O::ids(&outputs) {
+            self.recompute_handler
+                .insert(id, on_change_listener.clone());
+        }
+
+        O::into_computed(outputs, inner)
     }
 
     pub(crate) fn add_computed_async<O, I, F, FUT>(
         &mut self,
         state: State<O>,
+        inner: GenerationalBox<ComputedInner, SyncStorage>,
         states: I,
         compute: F,
         immediately_recompute: bool,
+        debounce: Option<Duration>,
     ) -> ComputedState<O>
     where
         O: DeserializeOwned + Display + Send + Sync + 'static,
@@ -67,13 +312,22 @@ impl ComputedStates {
     {
         let compute = Arc::new(compute);
         let _states = states.clone();
-        let on_change_listener: OnChangeHandlerAsync = Arc::new(move || {
+        let handler: OnChangeHandlerAsync = Arc::new(move || {
             let compute = compute.clone();
             let states = _states.clone();
             Box::pin(async move {
-                state.set(compute(states.get()).await);
+                // the states are only borrowed while starting the computation,
+                // so they can be set while it runs
+                let future = compute(states.get());
+                state.set(future.await);
             })
         });
+        let on_change_listener = AsyncListener {
+            handler,
+            // debounced states abort the previous run, so they only keep the latest one
+            latest_only: debounce.map(|_| state.id),
+            debounce,
+        };
 
         for id in states.id_list() {
             if let Some(value) = self.on_change_handler_async.get_mut(&id) {
@@ -85,24 +339,84 @@ impl ComputedStates {
         }
 
         if immediately_recompute {
-            self.join_set.spawn(on_change_listener());
+            self.run_when_connected(&on_change_listener);
         }
 
-        ComputedState(state)
+        self.recompute_handler_async
+            .insert(state.id, on_change_listener);
+
+        ComputedState(state, inner)
+    }
+
+    /// Runs `listener` in the background every time one of the states in `ids` changes,
+    /// and as soon as the websocket connects if `run_now` is set
+    pub(crate) fn add_async_listener(
+        &mut self,
+        ids: impl Iterator<Item = RandomId>,
+        listener: OnChangeHandlerAsync,
+        run_now: bool,
+    ) {
+        let listener = AsyncListener {
+            handler: listener,
+            latest_only: None,
+            debounce: None,
+        };
+
+        for id in ids {
+            self.on_change_handler_async
+                .entry(id)
+                .or_default()
+                .push(listener.clone());
+        }
+
+        if run_now {
+            self.run_when_connected(&listener);
+        }
     }
 
     /// Recompute sync ComputedStates that depend on the state with id `id`
+    #[cfg(test)]
     pub(crate) fn recompute_dependents(&mut self, id: RandomId) {
-        if let Some(funcs) = self.on_change_handler.get(&id) {
-            for func in funcs {
-                (*func)();
-            }
+        self.recompute_dependents_of_all(&[id]);
+    }
+
+    /// Recompute the ComputedStates that depend on any of the states in `ids`.
+    ///
+    /// Each one is recomputed once, even if it depends on several of them.
+    pub(crate) fn recompute_dependents_of_all(&mut self, ids: &[RandomId]) {
+        let mut seen = HashSet::new();
+        let funcs = ids
+            .iter()
+            .filter_map(|id| self.on_change_handler.get(id))
+            .flatten()
+            .filter(|func| seen.insert(Arc::as_ptr(func) as *const ()))
+            .cloned()
+            .collect::<Vec<_>>();
+        for func in funcs {
+            (*func)();
         }
 
-        if let Some(async_funcs) = self.on_change_handler_async.get(&id) {
-            for func in async_funcs {
-                self.join_set.spawn((*func)());
-            }
+        let mut seen = HashSet::new();
+        let async_funcs = ids
+            .iter()
+            .filter_map(|id| self.on_change_handler_async.get(id))
+            .flatten()
+            .filter(|listener| seen.insert(Arc::as_ptr(&listener.handler) as *const ()))
+            .cloned()
+            .collect::<Vec<_>>();
+        for listener in async_funcs {
+            self.run_async(&listener);
+        }
+    }
+
+    /// Recompute the ComputedState with id `id`, regardless of whether its dependencies changed
+    pub(crate) fn recompute(&mut self, id: RandomId) {
+        if let Some(func) = self.recompute_handler.get(&id) {
+            (*func)();
+        }
+
+        if let Some(listener) = self.recompute_handler_async.get(&id).cloned() {
+            self.run_async(&listener);
         }
     }
 }
@@ -116,7 +430,15 @@ pub enum InitialValue<O> {
 
 // States
 
-pub struct ComputedState<T: 'static>(pub(crate) State<T>);
+pub struct ComputedState<T: 'static>(
+    pub(crate) State<T>,
+    pub(crate) GenerationalBox<ComputedInner, SyncStorage>,
+);
+
+#[doc(hidden)]
+pub struct ComputedInner {
+    pub(crate) invalidate_tx: UnboundedSender<RandomId>,
+}
 
 // we implement Copy and Clone instead of deriving them, cause we dont need the
 // `T: Clone` bound
@@ -133,12 +455,156 @@ impl<T: Clone + Send + Sync + 'static> ComputedState<T> {
     }
 }
 
+impl<T: 'static> ComputedState<T> {
+    /// Queues a recompute of this state, even if none of its dependencies changed.
+    ///
+    /// Useful when the computation depends on something that isn't a `State`, like a cache or a file.
+    ///
+    /// Note: this doesn't recompute immediately.
+    /// Keep in mind, the state will not be recomputed until the websocket connection has been established.
+    pub fn invalidate(&self) {
+        self.1.read().invalidate_tx.send(self.0.id).unwrap();
+    }
+}
+
+/// Tuples of values that can be the output of [`Context::use_computed_multi`](crate::context::Context::use_computed_multi).
+///
+/// Each value is stored in its own state.
+pub trait ComputedOutputs: Send + Sync + 'static {
+    type States: Copy + Send + Sync + 'static;
+    type Computed;
+
+    #[doc(hidden)]
+    fn use_states<S>(self, ctx: &mut Context<S>) -> Self::States;
+    #[doc(hidden)]
+    fn set(self, states: &Self::States);
+    #[doc(hidden)]
+    fn ids(states: &Self::States) -> Vec<RandomId>;
+    #[doc(hidden)]
+    fn into_computed(
+        states: Self::States,
+        inner: GenerationalBox<ComputedInner, SyncStorage>,
+    ) -> Self::Computed;
+}
+
+macro_rules! impl_computed_outputs {
+    (
+        $(($ty:ident, $state:ident)),*
+    ) => {
+        #[allow(non_snake_case)]
+        impl<$($ty,)*> ComputedOutputs for ($($ty,)*)
+        where
+            $( $ty: DeserializeOwned + Display + Send + Sync + 'static, )*
+        {
+            type States = ($(State<$ty>,)*);
+            type Computed = ($(ComputedState<$ty>,)*);
+
+            #[track_caller]
+            fn use_states<S>(self, ctx: &mut Context<S>) -> Self::States {
+                let ($($ty,)*) = self;
+                ($(ctx.use_state($ty),)*)
+            }
+
+            fn set(self, states: &Self::States) {
+                let ($($ty,)*) = self;
+                let ($($state,)*) = states;
+                $( $state.set($ty); )*
+            }
+
+            fn ids(states: &Self::States) -> Vec<RandomId> {
+                let ($($state,)*) = states;
+                vec![$($state.id,)*]
+            }
+
+            fn into_computed(
+                states: Self::States,
+                inner: GenerationalBox<ComputedInner, SyncStorage>,
+            ) -> Self::Computed {
+                let ($($state,)*) = states;
+                ($(ComputedState($state, inner),)*)
+            }
+        }
+    };
+}
+
+impl_computed_outputs!((O1, s1), (O2, s2));
+impl_computed_outputs!((O1, s1), (O2, s2), (O3, s3));
+impl_computed_outputs!((O1, s1), (O2, s2), (O3, s3), (O4, s4));
+impl_computed_outputs!((O1, s1), (O2, s2), (O3, s3), (O4, s4), (O5, s5));
+impl_computed_outputs!((O1, s1), (O2, s2), (O3, s3), (O4, s4), (O5, s5), (O6, s6));
+impl_computed_outputs!(
+    (O1, s1),
+    (O2, s2),
+    (O3, s3),
+    (O4, s4),
+    (O5, s5),
+    (O6, s6),
+    (O7, s7)
+);
+impl_computed_outputs!(
+    (O1, s1),
+    (O2, s2),
+    (O3, s3),
+    (O4, s4),
+    (O5, s5),
+    (O6, s6),
+    (O7, s7),
+    (O8, s8)
+);
+
+/// Data that is loaded in the background, with its loading and error states.
+///
+/// Created with [`Context::use_resource`](crate::context::Context::use_resource).
+pub struct Resource<T: 'static> {
+    pub(crate) value: State<Option<T>>,
+    pub(crate) loading: State<bool>,
+    pub(crate) error: State<Option<String>>,
+}
+
+impl<T: 'static> Clone for Resource<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T: 'static> Copy for Resource<T> {}
+
+impl<T: 'static> Resource<T> {
+    /// The value of the last fetch that succeeded, or `None` until the first one does.
+    ///
+    /// It's kept when a later fetch fails. Renders as the value, or as nothing while it's `None`.
+    pub fn value(&self) -> State<Option<T>> {
+        self.value
+    }
+
+    /// Whether a fetch is running.
+    ///
+    /// It's true from the start, until the first fetch finishes.
+    pub fn loading(&self) -> State<bool> {
+        self.loading
+    }
+
+    /// The error of the last fetch, or `None` if it succeeded.
+    ///
+    /// Renders as the error message, or as nothing while it's `None`.
+    pub fn error(&self) -> State<Option<String>> {
+        self.error
+    }
+}
+
+/// Displays the value, or nothing if it's `None`
+pub(crate) fn display_option<T: Display>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
 pub trait StateGetter: Clone + Send + Sync + 'static {
     type Output<'a>;
 
     fn get(&self) -> Self::Output<'_>;
 
     fn id_list(&self) -> impl Iterator<Item = RandomId>;
+
+    #[doc(hidden)]
+    fn descriptors(&self) -> Vec<StateDescriptor>;
 }
 
 impl<T: Clone + Send + Sync + 'static> StateGetter for State<T> {
@@ -151,25 +617,65 @@ impl<T: Clone + Send + Sync + 'static> StateGetter for State<T> {
     fn id_list(&self) -> impl Iterator<Item = RandomId> {
         [self.id].into_iter()
     }
+
+    fn descriptors(&self) -> Vec<StateDescriptor> {
+        vec![(*self).into()]
+    }
 }
 
-// TODO add more tuples
-impl<T, U> StateGetter for (State<T>, State<U>)
-where
-    T: Clone + Send + Sync + 'static,
-    U: Clone + Send + Sync + 'static,
-{
-    type Output<'a> = (StateGet<'a, T>, StateGet<'a, U>);
+macro_rules! impl_state_getter_tuple {
+    (
+        $($ty:ident),*
+    ) => {
+        #[allow(non_snake_case)]
+        impl<$($ty,)*> StateGetter for ($(State<$ty>,)*)
+        where
+            $( $ty: Clone + Send + Sync + 'static, )*
+        {
+            type Output<'a> = ($(StateGet<'a, $ty>,)*);
 
-    fn get(&self) -> Self::Output<'_> {
-        (State::get(&self.0), State::get(&self.1))
-    }
+            fn get(&self) -> Self::Output<'_> {
+                let ($($ty,)*) = self;
+                ($(State::get($ty),)*)
+            }
 
-    fn id_list(&self) -> impl Iterator<Item = RandomId> {
-        [self.0.id, self.1.id].into_iter()
-    }
+            fn id_list(&self) -> impl Iterator<Item = RandomId> {
+                let ($($ty,)*) = self;
+                [$($ty.id,)*].into_iter()
+            }
+
+            fn descriptors(&self) -> Vec<StateDescriptor> {
+                let ($($ty,)*) = self;
+                vec![$((*$ty).into(),)*]
+            }
+        }
+    };
 }
 
+#[rustfmt::skip]
+macro_rules! all_the_tuples {
+    ($name:ident) => {
+        $name!(T1);
+        $name!(T1, T2);
+        $name!(T1, T2, T3);
+        $name!(T1, T2, T3, T4);
+        $name!(T1, T2, T3, T4, T5);
+        $name!(T1, T2, T3, T4, T5, T6);
+        $name!(T1, T2, T3, T4, T5, T6, T7);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
+    };
+}
+
+all_the_tuples!(impl_state_getter_tuple);
+
 #[cfg(test)]
 mod tests {
     use crate::{computed::InitialValue, context::Context};
@@ -241,6 +747,34 @@ mod tests {
         assert_eq!("0", *computed.get());
     }
 
+    /// Before the websocket connects, the recompute waits, and it starts once the context connects
+    #[tokio::test]
+    async fn test_async_computed_state_recompute_waits_for_connection() {
+        let mut ctx = Context::<()>::new(0, false);
+
+        let state = ctx.use_state(0u32);
+        let computed = ctx.use_computed_async_with(
+            state,
+            |value| {
+                let value = value.to_string();
+                async move { value }
+            },
+            InitialValue::ValueAndCompute("initial".to_string()),
+        );
+
+        assert!(ctx.computed_states.join_set.is_empty());
+
+        ctx.connect();
+        ctx.computed_states
+            .join_set
+            .join_next()
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!("0", *computed.get());
+    }
+
     #[tokio::test]
     async fn test_async_computed_state() {
         let mut ctx = Context::<()>::new(0, true);
@@ -272,6 +806,82 @@ mod tests {
         assert_eq!("1", *computed.get());
     }
 
+    #[test]
+    fn test_invalidate_recomputes() {
+        use std::sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        };
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let external = Arc::new(AtomicU32::new(0));
+        let state = ctx.use_state(1u32);
+        let computed = ctx.use_computed(state, {
+            let external = external.clone();
+            move |value| *value + external.load(Ordering::SeqCst)
+        });
+
+        assert_eq!(1, *computed.get());
+
+        external.store(10, Ordering::SeqCst);
+        computed.invalidate();
+
+        // the invalidation is queued until the websocket loop handles it
+        assert_eq!(1, *computed.get());
+
+        let id = ctx.computed_states.invalidate_rx.try_recv().unwrap();
+        ctx.computed_states.recompute(id);
+
+        assert_eq!(11, *computed.get());
+    }
+
+    #[tokio::test]
+    async fn test_async_concurrency_limit() {
+        use std::sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        };
+
+        let mut ctx = Context::<()>::new(0, true);
+        ctx.computed_states
+            .set_concurrency_limit(std::num::NonZeroUsize::new(2));
+
+        let running = Arc::new(AtomicU32::new(0));
+        let max_running = Arc::new(AtomicU32::new(0));
+
+        let state = ctx.use_state(0u32);
+        for _ in 0..5 {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            ctx.use_computed_async_with(
+                state,
+                move |value| {
+                    let value = *value;
+                    let running = running.clone();
+                    let max_running = max_running.clone();
+                    async move {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_running.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        value
+                    }
+                },
+                InitialValue::Value(0),
+            );
+        }
+
+        state.set(1);
+        ctx.computed_states.recompute_dependents(state.id);
+
+        while let Some(res) = ctx.computed_states.join_set.join_next().await {
+            res.unwrap();
+        }
+
+        assert_eq!(2, max_running.load(Ordering::SeqCst));
+    }
+
     #[tokio::test]
     async fn test_async_gets_recomputed() {
         let mut ctx = Context::<()>::new(0, true);
@@ -299,4 +909,333 @@ mod tests {
 
         assert_eq!("1", *computed.get());
     }
+
+    async fn join_all(ctx: &mut Context<()>) {
+        while let Some(res) = ctx.computed_states.join_set.join_next().await {
+            res.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resource_loads_value() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let id = ctx.use_state(1u32);
+        let resource = ctx.use_resource(id, |id| {
+            let id = *id;
+            async move { Ok::<_, String>(id * 10) }
+        });
+
+        assert!(*resource.loading().get());
+        assert_eq!(None, *resource.value().get());
+
+        join_all(&mut ctx).await;
+        assert!(!*resource.loading().get());
+        assert_eq!(Some(10), *resource.value().get());
+        assert_eq!(None, *resource.error().get());
+    }
+
+    #[tokio::test]
+    async fn test_resource_captures_errors() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let id = ctx.use_state(1u32);
+        let resource = ctx.use_resource(id, |id| {
+            let id = *id;
+            async move {
+                if id == 1 {
+                    Ok(10)
+                } else {
+                    Err(format!("{id} not found"))
+                }
+            }
+        });
+        join_all(&mut ctx).await;
+
+        id.set(2);
+        ctx.computed_states.recompute_dependents(id.id);
+        join_all(&mut ctx).await;
+
+        assert_eq!(Some("2 not found".to_string()), *resource.error().get());
+        // the last value is kept
+        assert_eq!(Some(10), *resource.value().get());
+        assert!(!*resource.loading().get());
+        assert_eq!("2 not found", resource.error().display());
+    }
+
+    #[tokio::test]
+    async fn test_resource_latest_fetch_wins() {
+        use tokio::time::{sleep, Duration};
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let id = ctx.use_state(0u32);
+        let resource = ctx.use_resource(id, |id| {
+            let id = *id;
+            async move {
+                // the first fetch takes longer than the ones after it
+                sleep(Duration::from_millis(if id == 0 { 50 } else { 1 })).await;
+                Ok::<_, String>(id)
+            }
+        });
+
+        // let the first fetch start before changing the source
+        sleep(Duration::from_millis(5)).await;
+        id.set(1);
+        ctx.computed_states.recompute_dependents(id.id);
+        join_all(&mut ctx).await;
+
+        assert_eq!(Some(1), *resource.value().get());
+        assert!(!*resource.loading().get());
+    }
+
+    #[test]
+    fn test_three_states_computed() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let a = ctx.use_state(1u32);
+        let b = ctx.use_state(2u32);
+        let c = ctx.use_state(3u32);
+        let sum = ctx.use_computed((a, b, c), |(a, b, c)| *a + *b + *c);
+        assert_eq!(6, *sum.get());
+
+        // setting any of the sources recomputes it
+        for (state, value, expected) in [(a, 10, 15), (b, 20, 33), (c, 30, 60)] {
+            state.set(value);
+            ctx.computed_states.recompute_dependents(state.id);
+            assert_eq!(expected, *sum.get());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_debounced_keeps_latest_value() {
+        use tokio::time::{sleep, Duration};
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(0u32);
+        let computed = ctx
+            .use_computed_async_debounced(
+                state,
+                |value| {
+                    let value = *value;
+                    async move {
+                        // the first change takes longer than the one after it
+                        sleep(Duration::from_millis(if value == 1 { 100 } else { 1 })).await;
+                        value
+                    }
+                },
+                Duration::from_millis(5),
+            )
+            .await;
+
+        state.set(1);
+        ctx.computed_states.recompute_dependents(state.id);
+        // wait until the slow computation is running
+        sleep(Duration::from_millis(20)).await;
+
+        state.set(2);
+        ctx.computed_states.recompute_dependents(state.id);
+
+        while let Some(res) = ctx.computed_states.join_set.join_next().await {
+            // the slow computation is aborted
+            if let Err(err) = res {
+                assert!(err.is_cancelled());
+            }
+        }
+
+        assert_eq!(2, *computed.get());
+    }
+
+    #[tokio::test]
+    async fn test_debounced_computes_once_for_rapid_changes() {
+        use std::sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        };
+        use tokio::time::Duration;
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let runs = Arc::new(AtomicU32::new(0));
+        let state = ctx.use_state(0u32);
+        let computed = ctx
+            .use_computed_async_debounced(
+                state,
+                {
+                    let runs = runs.clone();
+                    move |value| {
+                        let value = *value;
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        async move { value }
+                    }
+                },
+                Duration::from_millis(10),
+            )
+            .await;
+
+        for value in 1..=3 {
+            state.set(value);
+            ctx.computed_states.recompute_dependents(state.id);
+        }
+        while ctx.computed_states.join_set.join_next().await.is_some() {}
+
+        assert_eq!(3, *computed.get());
+        // once for the initial value, and once after the changes
+        assert_eq!(2, runs.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_debounce_waits_outside_the_concurrency_limit() {
+        use tokio::time::Duration;
+
+        let mut ctx = Context::<()>::new(0, true);
+        ctx.computed_states
+            .set_concurrency_limit(std::num::NonZeroUsize::new(1));
+
+        let state = ctx.use_state(0u32);
+        let debounced = ctx
+            .use_computed_async_debounced(
+                state,
+                |value| {
+                    let value = *value;
+                    async move { value }
+                },
+                Duration::from_millis(300),
+            )
+            .await;
+        let fast = ctx.use_computed_async_with(
+            state,
+            |value| {
+                let value = *value;
+                async move { value * 10 }
+            },
+            InitialValue::Value(0),
+        );
+
+        state.set(1);
+        ctx.computed_states.recompute_dependents(state.id);
+
+        // the pending debounce doesn't hold the only slot, so the other recompute finishes first
+        tokio::time::timeout(
+            Duration::from_millis(100),
+            ctx.computed_states.join_set.join_next(),
+        )
+        .await
+        .expect("the recompute should not wait for the debounce")
+        .unwrap()
+        .unwrap();
+        assert_eq!(10, *fast.get());
+        assert_eq!(0, *debounced.get());
+
+        while ctx.computed_states.join_set.join_next().await.is_some() {}
+        assert_eq!(1, *debounced.get());
+    }
+
+    #[tokio::test]
+    async fn test_timed_state_recomputes_while_connected() {
+        use std::sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        };
+        use tokio::time::Duration;
+
+        let mut ctx = Context::<()>::new(0, false);
+
+        let ticks = Arc::new(AtomicU32::new(0));
+        let counter = ticks.clone();
+        let timed = ctx.use_timed(Duration::from_millis(10), move || {
+            counter.fetch_add(1, Ordering::SeqCst)
+        });
+        assert_eq!(0, *timed.get());
+
+        // nothing ticks until the websocket connects
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(ctx.computed_states.join_set.is_empty());
+        assert_eq!(1, ticks.load(Ordering::SeqCst));
+
+        ctx.connect();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while *timed.get() < 2 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the timed state should be recomputed on each tick");
+        assert_eq!(
+            timed.0.id,
+            ctx.states.changes_rx.try_recv().unwrap().0,
+            "recomputing sends the change"
+        );
+
+        // the join set is dropped with the context, which stops the timer
+        let join_set = std::mem::take(&mut ctx.computed_states.join_set);
+        drop(join_set);
+        let after_drop = ticks.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(after_drop, ticks.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_finished_runs_are_not_kept() {
+        use tokio::time::Duration;
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let first = ctx.use_state(0u32);
+        let second = ctx.use_state(0u32);
+        for state in [first, second] {
+            ctx.use_computed_async_debounced(
+                state,
+                |value| {
+                    let value = *value;
+                    async move { value }
+                },
+                Duration::from_millis(1),
+            )
+            .await;
+        }
+        while ctx.computed_states.join_set.join_next().await.is_some() {}
+
+        first.set(1);
+        ctx.computed_states.recompute_dependents(first.id);
+        while ctx.computed_states.join_set.join_next().await.is_some() {}
+
+        // the finished run of the first computed is dropped when the second one starts
+        second.set(1);
+        ctx.computed_states.recompute_dependents(second.id);
+        assert_eq!(1, ctx.computed_states.running.len());
+        while ctx.computed_states.join_set.join_next().await.is_some() {}
+    }
+
+    #[test]
+    fn test_multi_computes_once() {
+        use std::sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        };
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let runs = Arc::new(AtomicU32::new(0));
+        let state = ctx.use_state(3u32);
+        let (double, square) = ctx.use_computed_multi(state, {
+            let runs = runs.clone();
+            move |value| {
+                runs.fetch_add(1, Ordering::SeqCst);
+                (*value * 2, *value * *value)
+            }
+        });
+
+        assert_eq!(6, *double.get());
+        assert_eq!(9, *square.get());
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+
+        state.set(4);
+        ctx.computed_states.recompute_dependents(state.id);
+
+        assert_eq!(8, *double.get());
+        assert_eq!(16, *square.get());
+        assert_eq!(2, runs.load(Ordering::SeqCst));
+    }
 }
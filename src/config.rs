@@ -1,27 +1,348 @@
-use std::sync::Arc;
+use std::{future::Future, num::NonZeroUsize, pin::Pin, sync::Arc, time::Duration};
 
-use axum::Extension;
+use axum::{http::request::Parts, Extension};
+use tokio::sync::broadcast;
 
-use crate::html::{Content, Element};
+use crate::{
+    html::{Attributes, Content, ContentValue, Element},
+    live::{LiveStats, Sessions},
+    presence::Presence,
+};
+
+type ServeStatic = dyn Fn(&Parts) -> bool + Send + Sync + 'static;
+type NonceSource = dyn Fn(&Parts) -> Option<String> + Send + Sync + 'static;
+type PanicHandler = dyn Fn(&str) -> Option<String> + Send + Sync + 'static;
+type UserSource = dyn Fn(&Parts) -> Option<String> + Send + Sync + 'static;
 
 /// Configuration for Coaxial.
 ///
 /// Should be added as a layer for the routes.
 #[derive(Clone)]
 pub struct Config {
-    pub(crate) layout: Arc<dyn Layout + Send + Sync + 'static>,
+    pub(crate) layout: Arc<dyn AsyncLayout + Send + Sync + 'static>,
+    pub(crate) script_loading: ScriptLoading,
+    pub(crate) wait_for_dom: bool,
+    pub(crate) base_path: Option<String>,
+    pub(crate) async_computed_limit: Option<NonZeroUsize>,
+    pub(crate) allowed_origins: Option<Vec<String>>,
+    pub(crate) live_stats: LiveStats,
+    pub(crate) sessions: Sessions,
+    pub(crate) serve_static: Option<Arc<ServeStatic>>,
+    pub(crate) nonce: Option<Arc<NonceSource>>,
+    pub(crate) panic_handler: Option<Arc<PanicHandler>>,
+    pub(crate) seed_param: String,
+    pub(crate) reconnect_initial: Duration,
+    pub(crate) reconnect_max: Duration,
+    pub(crate) reloads: broadcast::Sender<()>,
+    pub(crate) heartbeat_interval: Duration,
+    pub(crate) heartbeat_timeout: Duration,
+    pub(crate) update_throttle: Option<Duration>,
+    pub(crate) presence: Presence,
+    pub(crate) presence_user: Option<Arc<UserSource>>,
 }
 
 impl Config {
+    /// Creates a config with a custom layout.
+    ///
+    /// The layout gets the content returned by the handler, the adapter script element,
+    /// and a [`LayoutContext`] with the rest of the data the handler provided.
     pub fn with_layout<F>(layout: F) -> Self
     where
-        F: Fn(Element, Element) -> Element + Send + Sync + 'static,
+        F: Fn(Element, Element, LayoutContext) -> Element + Send + Sync + 'static,
     {
+        Self::from_layout(Arc::new(layout))
+    }
+
+    /// Creates a config with a layout that can do I/O, like loading the links of a menu from a database.
+    ///
+    /// It runs after the handler, and gets the same arguments as the one in [`Config::with_layout`]:
+    ///
+    /// ```ignore
+    /// Config::with_async_layout(|content, adapter, context| async move {
+    ///     let links = load_links().await;
+    ///     html(Content::List(vec![nav(links).into(), content.into(), adapter.into()]), Default::default())
+    /// })
+    /// ```
+    pub fn with_async_layout<F, Fut>(layout: F) -> Self
+    where
+        F: Fn(Element, Element, LayoutContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Element> + Send + 'static,
+    {
+        Self::from_layout(Arc::new(AsyncFnLayout(layout)))
+    }
+
+    fn from_layout(layout: Arc<dyn AsyncLayout + Send + Sync + 'static>) -> Self {
         Config {
-            layout: Arc::new(layout),
+            layout,
+            script_loading: Default::default(),
+            wait_for_dom: true,
+            base_path: None,
+            async_computed_limit: None,
+            allowed_origins: None,
+            live_stats: Default::default(),
+            sessions: Default::default(),
+            serve_static: None,
+            nonce: None,
+            panic_handler: None,
+            seed_param: "coaxial-seed".to_string(),
+            reconnect_initial: Duration::from_millis(500),
+            reconnect_max: Duration::from_secs(10),
+            reloads: broadcast::channel(1).0,
+            heartbeat_interval: Duration::from_secs(30),
+            heartbeat_timeout: Duration::from_secs(10),
+            update_throttle: None,
+            presence: Default::default(),
+            presence_user: None,
+        }
+    }
+
+    /// Limits how many async computed states can be recomputing at the same time, per connection.
+    ///
+    /// Recomputes over the limit wait until a running one finishes. There is no limit by default.
+    pub fn with_async_computed_limit(mut self, limit: NonZeroUsize) -> Self {
+        self.async_computed_limit = Some(limit);
+        self
+    }
+
+    /// Sets how long the context of a served page is kept, waiting for its websocket to connect.
+    ///
+    /// When the websocket connects in time, it uses the same context the page was rendered with,
+    /// so the handler only runs once. Otherwise, the handler is run again to create a new one,
+    /// which also happens when the websocket connects to a different server.
+    /// Contexts that are never connected to are dropped once they expire.
+    /// They are also kept for this long after their websocket disconnects, so the client can
+    /// reconnect to them.
+    ///
+    /// Defaults to 60 seconds. A duration of zero never keeps them, always running the handler again.
+    pub fn with_session_ttl(mut self, ttl: Duration) -> Self {
+        self.sessions.set_ttl(ttl);
+        self
+    }
+
+    /// Sets the name of the query param the websocket sends the seed of the page in.
+    ///
+    /// Useful when `coaxial-seed` clashes with a param the app uses. Defaults to `coaxial-seed`.
+    pub fn with_seed_param(mut self, name: impl ToString) -> Self {
+        self.seed_param = name.to_string();
+        self
+    }
+
+    /// Sets how long the client waits before trying to reconnect a dropped websocket.
+    ///
+    /// The first attempt waits `initial`, and each one after it waits twice as long as the
+    /// previous one, up to `max`. Defaults to 500 milliseconds, up to 10 seconds.
+    ///
+    /// Contexts are kept for the session TTL after their websocket disconnects, so a client that
+    /// reconnects in time picks up where it left off. Otherwise, like after a server restart,
+    /// the client reloads the page. See [`Config::with_session_ttl`].
+    pub fn with_reconnect_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_initial = initial;
+        self.reconnect_max = max;
+        self
+    }
+
+    /// Returns the milliseconds the client waits before each reconnect attempt.
+    ///
+    /// Attempts after the last one keep waiting the last delay.
+    pub(crate) fn reconnect_delays(&self) -> Vec<u64> {
+        let max = self.reconnect_max.as_millis() as u64;
+        let mut delay = (self.reconnect_initial.as_millis() as u64).min(max);
+
+        let mut delays = vec![delay];
+        while delay < max {
+            delay = delay.saturating_mul(2).clamp(1, max);
+            delays.push(delay);
+        }
+        delays
+    }
+
+    /// Sets how often the server pings each client, and how long it waits for the pong.
+    ///
+    /// Connections that don't answer in time are closed and their context is dropped, so
+    /// connections that died without closing, like when a laptop goes to sleep, don't keep it
+    /// around forever. Defaults to a ping every 30 seconds, with 10 seconds to answer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        assert!(!interval.is_zero(), "interval must be greater than 0");
+
+        self.heartbeat_interval = interval;
+        self.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Sends state updates to each client at most once every `window`.
+    ///
+    /// Changes made while waiting are sent together once the window is over, with only the last
+    /// value of each state, so states that change very often, like one bound to a slider, don't make
+    /// the client update the page for every value. Updates are sent as soon as they happen by default.
+    pub fn with_update_throttle(mut self, window: Duration) -> Self {
+        self.update_throttle = Some(window);
+        self
+    }
+
+    /// Sets the prefix the app is mounted under, for example when it's served behind a reverse proxy.
+    ///
+    /// The websocket connects to this prefix followed by the path the page was served from.
+    /// If not set, the `X-Forwarded-Prefix` header is used when present.
+    pub fn with_base_path(mut self, base_path: impl ToString) -> Self {
+        self.base_path = Some(base_path.to_string());
+        self
+    }
+
+    /// Sets the origins that are allowed to open the websocket, like `https://example.com`.
+    ///
+    /// By default, only the origin the page is served from is allowed. This is checked by
+    /// comparing the `Origin` header with the `X-Forwarded-Host` or `Host` headers.
+    /// Connections from other origins are rejected with a `403 Forbidden`.
+    /// Requests without an `Origin` header, which browsers always send, are allowed.
+    pub fn with_allowed_origins<I>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        self.allowed_origins = Some(origins.into_iter().map(|o| o.to_string()).collect());
+        self
+    }
+
+    /// Sets how the adapter `<script>` tag is loaded.
+    pub fn with_script_loading(mut self, script_loading: ScriptLoading) -> Self {
+        self.script_loading = script_loading;
+        self
+    }
+
+    /// Sets whether the reactivity of classic adapter scripts is set up on `DOMContentLoaded`.
+    ///
+    /// Scripts added to a page that has already loaded, like when navigating without a full page
+    /// load, never get `DOMContentLoaded`, so they need to set it up immediately.
+    /// The script then has to come after the elements it updates. Enabled by default.
+    pub fn with_wait_for_dom(mut self, wait: bool) -> Self {
+        self.wait_for_dom = wait;
+        self
+    }
+
+    /// Sets a predicate that decides which requests get a static page, like requests from crawlers.
+    ///
+    /// Static pages are rendered with the current values of the states, without the adapter script,
+    /// so they never connect and are never updated. By default, every request gets the live page.
+    ///
+    /// ```ignore
+    /// Config::default().with_serve_static(|parts| {
+    ///     parts
+    ///         .headers
+    ///         .get(USER_AGENT)
+    ///         .and_then(|agent| agent.to_str().ok())
+    ///         .is_some_and(|agent| agent.contains("Googlebot"))
+    /// })
+    /// ```
+    pub fn with_serve_static<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Parts) -> bool + Send + Sync + 'static,
+    {
+        self.serve_static = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Returns whether the request with `parts` should get a static page
+    pub(crate) fn should_serve_static(&self, parts: &Parts) -> bool {
+        self.serve_static
+            .as_ref()
+            .is_some_and(|predicate| predicate(parts))
+    }
+
+    /// Sets where the nonce for a request's `Content-Security-Policy` comes from.
+    ///
+    /// It's usually generated by a middleware, which sets the header and stores the nonce in the
+    /// request's extensions:
+    ///
+    /// ```ignore
+    /// Config::default().with_nonce(|parts| parts.extensions.get::<CspNonce>().map(|n| n.0.clone()))
+    /// ```
+    ///
+    /// The nonce is added to the adapter script, and passed to the layout in [`LayoutContext::nonce`]
+    /// for its own inline scripts and styles. Pages are rendered without a nonce if this returns `None`.
+    pub fn with_nonce<F>(mut self, source: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.nonce = Some(Arc::new(source));
+        self
+    }
+
+    /// Returns the nonce for the request with `parts`, if there is one
+    pub(crate) fn nonce(&self, parts: &Parts) -> Option<String> {
+        self.nonce.as_ref().and_then(|source| source(parts))
+    }
+
+    /// Sets the error the client gets when a closure, event handler or async computed state panics.
+    ///
+    /// `handler` gets the panic message, and returns the error sent to the client, or `None` to
+    /// not send one. Either way, the panic is logged and the connection stays open, so the rest of
+    /// the page keeps working. By default, the client gets a generic error, so panic messages,
+    /// which can have details about the server, aren't shown to users.
+    ///
+    /// ```ignore
+    /// Config::default().with_panic_handler(|message| Some(format!("something went wrong: {message}")))
+    /// ```
+    pub fn with_panic_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Returns the error to send to the client for a panic with `message`, if any
+    pub(crate) fn panic_error(&self, message: &str) -> Option<String> {
+        match &self.panic_handler {
+            Some(handler) => handler(message),
+            None => Some("internal error".to_string()),
         }
     }
 
+    /// Returns a handle to the connection stats of the routes using this config.
+    ///
+    /// Clones of the config share the same stats.
+    pub fn live_stats(&self) -> LiveStats {
+        self.live_stats.clone()
+    }
+
+    /// Tracks which users are connected, with `user` returning the id of the user a request is from.
+    ///
+    /// The user is usually found by a middleware, like an auth layer storing it in the extensions:
+    ///
+    /// ```ignore
+    /// Config::default().with_presence(|parts| parts.extensions.get::<User>().map(|user| user.id.to_string()))
+    /// ```
+    ///
+    /// Each websocket of a user counts as a connection, until it closes. Connections for which
+    /// `user` returns `None` aren't tracked. See [`Presence`].
+    pub fn with_presence<F>(mut self, user: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.presence_user = Some(Arc::new(user));
+        self
+    }
+
+    /// Returns the users connected to the routes using this config, see [`Config::with_presence`].
+    ///
+    /// Clones of the config share the same users. Handlers can get it with `Context::presence`.
+    pub fn presence(&self) -> Presence {
+        self.presence.clone()
+    }
+
+    /// Makes every client connected to the routes using this config reload the page,
+    /// like after a deploy that changes how pages are rendered.
+    ///
+    /// Clones of the config share the same clients. Reloaded pages get new contexts and connect again.
+    pub fn reload_clients(&self) {
+        // there's no error to handle when no one is connected
+        let _ = self.reloads.send(());
+    }
+
     pub fn layer(self) -> Extension<Self> {
         Extension(self)
     }
@@ -31,10 +352,10 @@ impl Default for Config {
     fn default() -> Self {
         use crate::html::{body, head, html};
 
-        Config::with_layout(|content, coaxial_adapter| {
+        Config::with_layout(|content, coaxial_adapter, mut context| {
             html(
                 Content::List(vec![
-                    head(Content::Empty, Default::default()).into(),
+                    head(Content::List(context.take_head()), Default::default()).into(),
                     body(
                         Content::List(vec![content.into(), coaxial_adapter.into()]),
                         Default::default(),
@@ -47,14 +368,140 @@ impl Default for Config {
     }
 }
 
+/// Controls the attributes of the adapter `<script>` tag.
+///
+/// The adapter script is inlined, and browsers ignore `defer` and `async` on inline scripts,
+/// so those aren't options.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptLoading {
+    /// Plain `<script>`. Reactivity is set up on `DOMContentLoaded`, unless disabled
+    /// with [`Config::with_wait_for_dom`].
+    #[default]
+    Classic,
+    /// `<script type="module">`. Module scripts are deferred, so reactivity is set up immediately.
+    Module,
+}
+
+impl ScriptLoading {
+    /// Whether the browser defers the inline script until the document has been parsed
+    pub(crate) fn is_deferred(&self) -> bool {
+        matches!(self, Self::Module)
+    }
+
+    pub(crate) fn attributes(&self) -> Attributes {
+        match self {
+            Self::Classic => Default::default(),
+            Self::Module => crate::attrs!("type" => "module"),
+        }
+    }
+}
+
+/// Data passed to the layout, in addition to the content and the adapter script.
+///
+/// Attributes set with `Context::set_html_attrs` are not included,
+/// since they are added to the root element returned by the layout.
+#[non_exhaustive]
+pub struct LayoutContext {
+    /// Title of the page
+    pub title: Option<String>,
+    /// Extra elements to add to the `<head>`
+    pub head: Vec<Element>,
+    /// Nonce to use for inline scripts and styles, from [`Config::with_nonce`].
+    ///
+    /// The adapter script already has it.
+    pub nonce: Option<String>,
+    /// Parts of the request the page is being rendered for
+    pub parts: Parts,
+}
+
+impl LayoutContext {
+    /// Takes the title and extra head elements, ready to be used as the content of `<head>`
+    pub fn take_head(&mut self) -> Vec<ContentValue> {
+        let title = self
+            .title
+            .take()
+            .map(|title| crate::html::title(title, Default::default()).into());
+
+        title
+            .into_iter()
+            .chain(self.head.drain(..).map(ContentValue::from))
+            .collect()
+    }
+}
+
 pub trait Layout {
-    fn call(&self, content: Element, scripts: Element) -> Element;
+    fn call(&self, content: Element, scripts: Element, context: LayoutContext) -> Element;
 }
 impl<F> Layout for F
 where
-    F: Fn(Element, Element) -> Element,
+    F: Fn(Element, Element, LayoutContext) -> Element,
 {
-    fn call(&self, content: Element, scripts: Element) -> Element {
-        (self)(content, scripts)
+    fn call(&self, content: Element, scripts: Element, context: LayoutContext) -> Element {
+        (self)(content, scripts, context)
+    }
+}
+
+/// Future returned by [`AsyncLayout::call`]
+pub type LayoutFuture<'a> = Pin<Box<dyn Future<Output = Element> + Send + 'a>>;
+
+/// A layout that can await while wrapping the page, like to fetch data for it.
+///
+/// It's called once the handler has returned, with the content it rendered. Every [`Layout`]
+/// is also an `AsyncLayout`, which returns its element right away.
+pub trait AsyncLayout {
+    fn call(&self, content: Element, scripts: Element, context: LayoutContext) -> LayoutFuture<'_>;
+}
+impl<L> AsyncLayout for L
+where
+    L: Layout + Sync,
+{
+    fn call(&self, content: Element, scripts: Element, context: LayoutContext) -> LayoutFuture<'_> {
+        let element = Layout::call(self, content, scripts, context);
+        Box::pin(std::future::ready(element))
+    }
+}
+
+/// Layout created with [`Config::with_async_layout`]
+struct AsyncFnLayout<F>(F);
+impl<F, Fut> AsyncLayout for AsyncFnLayout<F>
+where
+    F: Fn(Element, Element, LayoutContext) -> Fut,
+    Fut: Future<Output = Element> + Send + 'static,
+{
+    fn call(&self, content: Element, scripts: Element, context: LayoutContext) -> LayoutFuture<'_> {
+        Box::pin((self.0)(content, scripts, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Config;
+
+    #[test]
+    fn test_reconnect_delays_double_up_to_max() {
+        assert_eq!(
+            vec![500, 1000, 2000, 4000, 8000, 10000],
+            Config::default().reconnect_delays()
+        );
+
+        let config = Config::default()
+            .with_reconnect_backoff(Duration::from_millis(100), Duration::from_millis(400));
+        assert_eq!(vec![100, 200, 400], config.reconnect_delays());
+
+        let config =
+            Config::default().with_reconnect_backoff(Duration::ZERO, Duration::from_millis(3));
+        assert_eq!(vec![0, 1, 2, 3], config.reconnect_delays());
+
+        let config = Config::default()
+            .with_reconnect_backoff(Duration::from_secs(5), Duration::from_secs(1));
+        assert_eq!(vec![1000], config.reconnect_delays());
+    }
+
+    #[test]
+    #[should_panic(expected = "interval must be greater than 0")]
+    fn test_zero_heartbeat_interval_panics() {
+        let _ = Config::default().with_heartbeat(Duration::ZERO, Duration::from_secs(1));
     }
 }
@@ -1,21 +1,48 @@
-use axum::response::Response;
-use generational_box::{AnyStorage, Owner, SyncStorage};
+use axum::{
+    http::{HeaderMap, HeaderName, HeaderValue},
+    response::Response,
+};
+use generational_box::{AnyStorage, GenerationalBox, Owner, SyncStorage};
 use rand::{rngs::StdRng, SeedableRng};
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
 use std::{
     fmt::{Display, Write},
     future::Future,
     panic::Location,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 use crate::{
-    closures::{Closure, ClosureInner, ClosureTrait, ClosureWrapper, Closures, IntoClosure},
-    computed::{ComputedState, ComputedStates, InitialValue, StateGetter},
-    events::Events,
-    html::{Content, ContentValue, Element},
-    random_id::RandomId,
-    states::{State, StateInner, States},
+    closures::{
+        ArgsClosure, Closure, ClosureInner, ClosureSwitch, ClosureTrait, ClosureWrapper, Closures,
+        IntoClosure,
+    },
+    computed::{
+        display_option, ComputedInner, ComputedOutputs, ComputedState, ComputedStates,
+        InitialValue, OnChangeHandlerAsync, Resource, StateGetter,
+    },
+    config::Config,
+    events::{
+        EventHandler, EventHandlerWrapper, EventOptions, Events, IntoEventHandler, KeyFilter,
+    },
+    frames::Frames,
+    html::{
+        Attributes, Content, ContentValue, Element, HtmlContent, IfContent, MethodToggle,
+        ShowContent, SkeletonContent,
+    },
+    lists::{List, Lists},
+    live::{Navigation, Navigator, ReloadClient},
+    model::Model,
+    pagination::{clamp_page, page_count, Pagination},
+    presence::Presence,
+    random_id::{IdPath, RandomId},
+    reactive_js::computed_element_json,
+    states::{NumberFormat, State, StateInner, States},
     CoaxialResponse, Output,
 };
 
@@ -23,32 +50,123 @@ pub struct Context<S = ()> {
     pub(crate) rng: StdRng,
     rng_seed: u64,
 
-    in_websocket: bool,
-
     state_owner: Owner<SyncStorage>,
 
     pub(crate) states: States,
-    pub(crate) events: Events,
+    pub(crate) events: Events<S>,
+    pub(crate) frames: Frames,
+    pub(crate) lists: Lists,
     pub(crate) closures: Closures<S>,
     pub(crate) computed_states: ComputedStates,
+
+    pub(crate) html_attributes: Attributes,
+    pub(crate) response_headers: HeaderMap,
+    pub(crate) title: Option<String>,
+    pub(crate) head: Vec<Element>,
+
+    /// States used by skeletons, which get sent as soon as the websocket connects
+    pub(crate) skeletons: Vec<RandomId>,
+    /// States whose updates from the client are debounced
+    debounced_states: Vec<(RandomId, Duration)>,
+    /// States whose text is their value as JSON
+    json_states: Vec<RandomId>,
+
+    reload_tx: UnboundedSender<()>,
+    pub(crate) reload_rx: UnboundedReceiver<()>,
+    navigation_tx: UnboundedSender<Navigation>,
+    pub(crate) navigation_rx: UnboundedReceiver<Navigation>,
+
+    presence: Presence,
+
+    /// Callbacks that run when the context is dropped
+    disconnect_callbacks: Vec<Box<dyn FnOnce() + Send>>,
+
+    /// Where each state was created, to point at them in warnings
+    #[cfg(debug_assertions)]
+    state_locations: std::collections::HashMap<RandomId, &'static Location<'static>>,
+}
+
+impl<S> Drop for Context<S> {
+    fn drop(&mut self) {
+        // the context is finished once it's dropped. it's before the fields are dropped,
+        // so the tasks they own are still running
+        for callback in self.disconnect_callbacks.drain(..) {
+            callback();
+        }
+    }
 }
 
 impl<S> Context<S> {
     pub(crate) fn new(seed: u64, in_websocket: bool) -> Self {
         let rng = StdRng::seed_from_u64(seed);
+        let (reload_tx, reload_rx) = unbounded_channel();
+        let (navigation_tx, navigation_rx) = unbounded_channel();
 
-        Self {
+        let mut context = Self {
             rng,
             rng_seed: seed,
-            in_websocket,
 
             state_owner: <SyncStorage as AnyStorage>::owner(),
 
             states: Default::default(),
             events: Default::default(),
+            frames: Default::default(),
+            lists: Default::default(),
             closures: Default::default(),
             computed_states: Default::default(),
+
+            html_attributes: Default::default(),
+            response_headers: Default::default(),
+            title: None,
+            head: Vec::new(),
+
+            skeletons: Vec::new(),
+            debounced_states: Vec::new(),
+            json_states: Vec::new(),
+
+            reload_tx,
+            reload_rx,
+            navigation_tx,
+            navigation_rx,
+
+            presence: Default::default(),
+
+            disconnect_callbacks: Vec::new(),
+
+            #[cfg(debug_assertions)]
+            state_locations: Default::default(),
+        };
+
+        if in_websocket {
+            context.connect();
         }
+
+        context
+    }
+
+    /// Starts the work that waits for the websocket, like computing initial values in the background
+    pub(crate) fn connect(&mut self) {
+        self.computed_states.connect();
+    }
+
+    /// Stops the work that needs the websocket, like timers, once its connection ends.
+    ///
+    /// The disconnect callbacks aren't run, since the context can still be resumed by a reconnect.
+    /// They run when it's dropped instead.
+    pub(crate) fn disconnect(&mut self) {
+        self.computed_states.disconnect();
+    }
+
+    /// Creates a context for a request, applying the options in `config`
+    pub(crate) fn with_config(seed: u64, in_websocket: bool, config: &Config) -> Self {
+        let mut context = Self::new(seed, in_websocket);
+
+        context
+            .computed_states
+            .set_concurrency_limit(config.async_computed_limit);
+        context.presence = config.presence.clone();
+
+        context
     }
 
     #[track_caller]
@@ -75,12 +193,84 @@ impl<S> Context<S> {
         }
     }
 
+    /// Returns a closure that calls the closure returned by `select` for the current value of `states`.
+    ///
+    /// Every closure that `select` can return needs to be created with [`Context::use_closure`].
+    ///
+    /// ```ignore
+    /// let save = ctx.use_closure(|| async move { /* ... */ });
+    /// let edit = ctx.use_closure(|| async move { /* ... */ });
+    /// let editing = ctx.use_state(false);
+    ///
+    /// let on_click = ctx.use_closure_switch(editing, move |editing| if *editing { save } else { edit });
+    /// button("click", ("onclick", on_click))
+    /// ```
+    pub fn use_closure_switch<I, F>(&mut self, states: I, select: F) -> ClosureSwitch
+    where
+        I: StateGetter + Send + Sync + 'static,
+        F: Fn(<I as StateGetter>::Output<'_>) -> Closure + Send + Sync + 'static,
+    {
+        ClosureSwitch {
+            active: self.use_computed(states, move |value| select(value).id),
+        }
+    }
+
+    /// Like [`Context::use_closure`], but the last argument of the closure is deserialized from
+    /// the arguments sent by the client.
+    ///
+    /// Use [`Closure::with_args`] to send them, or call `window.Coaxial.callClosure(id, args)`.
+    ///
+    /// ```ignore
+    /// let remove = ctx.use_closure_with_args(move |RemoveItem { item_id }| async move { ... });
+    /// button("remove", attrs!("onclick" => remove.with_args(RemoveItem { item_id: 5 })))
+    /// ```
+    #[track_caller]
+    pub fn use_closure_with_args<F, M>(&mut self, closure: F) -> Closure
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+        S: 'static,
+    {
+        let id = RandomId::from_rng(&mut self.rng);
+
+        let handler = <F as IntoEventHandler<M, S>>::wrap(closure);
+        self.closures.insert(id, Arc::new(ArgsClosure(handler)));
+
+        Closure {
+            id,
+            inner: self.state_owner.insert_with_caller(
+                ClosureInner {
+                    closure_call_tx: self.closures.call_tx.clone(),
+                },
+                #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+                std::panic::Location::caller(),
+            ),
+        }
+    }
+
     pub fn use_state_inner<T: DeserializeOwned + Display + Send + Sync + 'static>(
         &mut self,
         value: T,
         #[cfg(any(debug_assertions, feature = "debug_ownership"))] caller: &'static Location<
             'static,
         >,
+    ) -> State<T> {
+        self.insert_state(
+            value,
+            |value| value.to_string(),
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            caller,
+        )
+    }
+
+    fn insert_state<T: DeserializeOwned + Send + Sync + 'static>(
+        &mut self,
+        value: T,
+        display: fn(&T) -> String,
+        #[cfg(any(debug_assertions, feature = "debug_ownership"))] caller: &'static Location<
+            'static,
+        >,
     ) -> State<T> {
         let id = RandomId::from_rng(&mut self.rng);
         let state = State {
@@ -88,6 +278,12 @@ impl<S> Context<S> {
                 StateInner {
                     value,
                     changes_tx: self.states.changes_tx.clone(),
+                    serialize: None,
+                    format: None,
+                    display,
+                    diffed: false,
+                    last_display: None,
+                    eq: None,
                 },
                 #[cfg(any(debug_assertions, feature = "debug_ownership"))]
                 caller,
@@ -96,6 +292,8 @@ impl<S> Context<S> {
         };
 
         self.states.insert(state.id, Arc::new(state));
+        #[cfg(debug_assertions)]
+        self.state_locations.insert(state.id, caller);
 
         state
     }
@@ -112,6 +310,303 @@ impl<S> Context<S> {
         )
     }
 
+    /// Creates a state whose updates also carry the value serialized as JSON.
+    ///
+    /// Text and attribute bindings keep using the `Display` output, but client-side code can
+    /// read the typed value with `window.Coaxial.getValue(id)`.
+    #[track_caller]
+    pub fn use_typed_state<T: DeserializeOwned + Serialize + Display + Send + Sync + 'static>(
+        &mut self,
+        value: T,
+    ) -> State<T> {
+        let state = self.use_state_inner(
+            value,
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        );
+
+        state.inner.write().serialize = Some(|value| serde_json::to_value(value).ok());
+
+        state
+    }
+
+    /// Creates a state holding a number that the client formats with `Intl.NumberFormat`,
+    /// for things like thousands separators or currencies.
+    ///
+    /// ```ignore
+    /// let count = ctx.use_state_formatted(1234567, NumberFormat::new().with_locale("en-US"));
+    /// // shown as "1,234,567"
+    /// ```
+    ///
+    /// The page is rendered with the `Display` output of the value, which gets formatted as soon as
+    /// the script runs. Every later value is formatted by the client too, so the formatting always
+    /// comes from the browser, and doesn't depend on the server matching how it formats numbers.
+    #[track_caller]
+    pub fn use_state_formatted<T: DeserializeOwned + Display + Send + Sync + 'static>(
+        &mut self,
+        value: T,
+        format: NumberFormat,
+    ) -> State<T> {
+        let state = self.use_state_inner(
+            value,
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        );
+
+        state.inner.write().format = Some(format.script().into());
+
+        state
+    }
+
+    /// Creates a state whose updates only send the part of the text that changed.
+    ///
+    /// The server keeps the last text of the state, and sends each change as the range of it to
+    /// replace, which the client applies to its own copy. Useful for long text that changes a bit
+    /// at a time, like a log that's appended to. The first change, and changes that replace most
+    /// of the text, are sent whole.
+    #[track_caller]
+    pub fn use_state_diffed<T: DeserializeOwned + Display + Send + Sync + 'static>(
+        &mut self,
+        value: T,
+    ) -> State<T> {
+        let state = self.use_state_inner(
+            value,
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        );
+
+        state.inner.write().diffed = true;
+
+        state
+    }
+
+    /// Creates a state that ignores new values that `eq` considers equal to the current one.
+    ///
+    /// `eq` gets the current value and the new one. Ignored values aren't stored and don't notify
+    /// anything, so the state keeps the value it had. Useful to avoid updates that wouldn't change
+    /// anything meaningful, like floats within a tolerance:
+    ///
+    /// ```ignore
+    /// let temperature = ctx.use_state_with_eq(20.0, |old: &f64, new: &f64| (old - new).abs() < 0.1);
+    /// ```
+    #[track_caller]
+    pub fn use_state_with_eq<T, F>(&mut self, value: T, eq: F) -> State<T>
+    where
+        T: DeserializeOwned + Display + Send + Sync + 'static,
+        F: Fn(&T, &T) -> bool + Send + Sync + 'static,
+    {
+        let state = self.use_state_inner(
+            value,
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        );
+
+        state.inner.write().eq = Some(Box::new(eq));
+
+        state
+    }
+
+    /// Creates a state whose updates from the client are debounced.
+    ///
+    /// `window.Coaxial.setState` waits until no new value has been set for `delay` before sending
+    /// the last one, so the server only sees settled values. Useful for inputs like search boxes,
+    /// which would otherwise send a change on every keystroke.
+    #[track_caller]
+    pub fn use_debounced_state<T: DeserializeOwned + Display + Send + Sync + 'static>(
+        &mut self,
+        value: T,
+        delay: Duration,
+    ) -> State<T> {
+        let state = self.use_state_inner(
+            value,
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        );
+
+        self.debounced_states.push((state.id, delay));
+
+        state
+    }
+
+    /// Creates a state for a type without `Display`, like a struct or an enum, which is sent to
+    /// the client as JSON.
+    ///
+    /// The JSON is the text of the state, so it's what gets rendered and what `onStateChange`
+    /// listeners get. `window.Coaxial.getValue(id)` returns it parsed. Values set by the client
+    /// are parsed from JSON too.
+    #[track_caller]
+    pub fn use_json_state<T: DeserializeOwned + Serialize + Send + Sync + 'static>(
+        &mut self,
+        value: T,
+    ) -> State<T> {
+        let state = self.insert_state(
+            value,
+            |value| serde_json::to_string(value).unwrap_or_default(),
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        );
+
+        self.json_states.push(state.id);
+
+        state
+    }
+
+    /// Creates a state that holds the items of a list. See [`Context::use_list`].
+    ///
+    /// Since there's no single text for a list, the state displays as the amount of items.
+    #[track_caller]
+    pub fn use_list_state<T: DeserializeOwned + Send + Sync + 'static>(
+        &mut self,
+        items: Vec<T>,
+    ) -> State<Vec<T>> {
+        self.insert_state(
+            items,
+            |items| items.len().to_string(),
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        )
+    }
+
+    /// Fills `container` with an element for each item in `items`, created with `render`.
+    ///
+    /// When `items` changes, the client only updates the elements whose item was added, removed,
+    /// moved or changed, identifying each item with `key`. Keys must be unique.
+    /// Items are rendered again as plain HTML when they change, so use the items themselves
+    /// instead of other states inside of `render`.
+    ///
+    /// ```ignore
+    /// let todos = ctx.use_list_state(vec![Todo { id: 1, text: "write docs".into() }]);
+    /// let list = ctx.use_list(ul((), Default::default()), todos, |todo| todo.id, |todo| {
+    ///     li(todo.text.clone(), Default::default())
+    /// });
+    /// ```
+    pub fn use_list<T, KEY, K, R>(
+        &mut self,
+        mut container: Element,
+        items: State<Vec<T>>,
+        key: K,
+        render: R,
+    ) -> Element
+    where
+        T: Send + Sync + 'static,
+        KEY: Display,
+        K: Fn(&T) -> KEY + Send + Sync + 'static,
+        R: Fn(&T) -> Element + Send + Sync + 'static,
+    {
+        let element_id = *container
+            .id
+            .get_or_insert_with(|| RandomId::from_rng(&mut self.rng));
+
+        let mut list = List {
+            element_id,
+            state: items,
+            key,
+            render,
+            items: vec![],
+        };
+        list.items = list.render_items();
+
+        let mut html = String::new();
+        for (_, element) in &list.items {
+            element.render(&mut html);
+        }
+        container.content = Content::Value(ContentValue::Raw(html));
+
+        self.lists.insert(items.id, Box::new(list));
+
+        container
+    }
+
+    /// Turns every field of `model` into its own state.
+    ///
+    /// See [`model!`](crate::model!).
+    #[track_caller]
+    pub fn use_model<M: Model>(&mut self, model: M) -> M::States {
+        model.use_states(self)
+    }
+
+    /// Returns content that shows `true_content` when `state` is true, and `false_content` when it isn't.
+    ///
+    /// The branch matching the current value is rendered on the server, and the client swaps the
+    /// element's children when the value changes. Use it as the whole content of an element.
+    pub fn use_bool_content(
+        &mut self,
+        state: State<bool>,
+        true_content: impl Into<Content>,
+        false_content: impl Into<Content>,
+    ) -> Content {
+        Content::If(Box::new(IfContent {
+            condition: state.into(),
+            then: true_content.into(),
+            otherwise: false_content.into(),
+        }))
+    }
+
+    /// Returns an element that is only in the page while `state` is true.
+    ///
+    /// While `state` is false, an empty `<template>` with the same id takes its place, so the element
+    /// is added back in the same position. Its states are re-applied when it's added back.
+    pub fn use_show(&mut self, state: State<bool>, element: Element) -> Element {
+        crate::html::fragment(Content::Show(Box::new(ShowContent {
+            condition: state.into(),
+            element,
+        })))
+    }
+
+    /// Returns the attributes for a `<dialog>` that is shown as a modal while `open` is true.
+    ///
+    /// The dialog is opened with `showModal()` and closed with `close()`. When the user closes it,
+    /// for example by pressing Escape, `open` is set to false.
+    ///
+    /// ```ignore
+    /// dialog("hi", ctx.use_dialog(open))
+    /// ```
+    pub fn use_dialog(&mut self, open: State<bool>) -> Attributes {
+        let mut attributes = Attributes::default();
+
+        attributes.insert(
+            "onclose",
+            format!("window.Coaxial.setState('{}', false)", open.id),
+        );
+        attributes.set_method_toggle(MethodToggle {
+            state: open.into(),
+            then: "showModal",
+            otherwise: "close",
+        });
+
+        attributes
+    }
+
+    /// Returns attributes that add `class` to the element while `state` is true, and remove it
+    /// while it's false, without touching its other classes.
+    ///
+    /// To add other attributes too, use [`Attributes::with_class_toggle`].
+    pub fn toggle_class(&self, state: State<bool>, class: &str) -> Attributes {
+        Attributes::default().with_class_toggle(state, class)
+    }
+
+    /// Returns content that shows `placeholder` until the live connection is established,
+    /// and the value of `computed` after that.
+    ///
+    /// The placeholder is rendered on the server. The first frame sent through the websocket
+    /// contains the value of `computed`, which replaces all of the element's children as text,
+    /// regardless of the placeholder being text or elements. Use it as the whole content of an element.
+    pub fn use_skeleton<T>(
+        &mut self,
+        placeholder: impl Into<Content>,
+        computed: ComputedState<T>,
+    ) -> Content
+    where
+        T: Clone + Display + Send + Sync + 'static,
+    {
+        self.skeletons.push(computed.0.id);
+
+        Content::Skeleton(Box::new(SkeletonContent {
+            state: computed.into(),
+            placeholder: placeholder.into(),
+        }))
+    }
+
     #[track_caller]
     pub fn use_computed<O, I, F>(&mut self, states: I, compute: F) -> ComputedState<O>
     where
@@ -125,7 +620,136 @@ impl<S> Context<S> {
             std::panic::Location::caller(),
         );
 
-        self.computed_states.add_computed(state, states, compute)
+        self.computed_states
+            .add_computed(state, self.computed_inner(), states, compute)
+    }
+
+    /// Returns content that is the element returned by `compute`, which is rendered again on the server
+    /// every time `states` change. Use it as the whole content of an element, whose children are replaced
+    /// with the new HTML.
+    ///
+    /// ```ignore
+    /// let cart = ctx.use_computed_element(items, |items| {
+    ///     ul(Content::List(items.iter().map(|item| li(item, Default::default()).into()).collect()), Default::default())
+    /// });
+    /// div(cart, Default::default())
+    /// ```
+    ///
+    /// States and closures used inside the element stay reactive, and their listeners are
+    /// replaced along with the element every time it's recomputed.
+    #[track_caller]
+    pub fn use_computed_element<I, F>(&mut self, states: I, compute: F) -> Content
+    where
+        I: StateGetter + Send + Sync + 'static,
+        F: Fn(<I as StateGetter>::Output<'_>) -> Element + Send + Sync + 'static,
+    {
+        let scope: RandomId = RandomId::from_rng(&mut self.rng);
+        let scope = scope.to_string();
+        // every computed element gets the same ids, so the page and the socket agree on them
+        let path = IdPath::root(self.rng_seed, &scope);
+
+        let mut element = compute(states.get());
+        let json = computed_element_json(&mut element, path);
+        let state = self.use_state_inner(
+            json,
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        );
+        let state = self.computed_states.add_computed(
+            state,
+            self.computed_inner(),
+            states,
+            move |values| computed_element_json(&mut compute(values), path),
+        );
+
+        Content::Html(Box::new(HtmlContent {
+            state: state.into(),
+            element,
+            scope,
+        }))
+    }
+
+    /// Like [`Context::use_computed`], but `compute` returns a tuple of values, each of which
+    /// is stored in its own computed state.
+    ///
+    /// `compute` runs once per change, so expensive work can be shared between the outputs.
+    ///
+    /// ```ignore
+    /// let (min, max) = ctx.use_computed_multi(values, |values| {
+    ///     (values.iter().min().copied(), values.iter().max().copied())
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn use_computed_multi<O, I, F>(&mut self, states: I, compute: F) -> O::Computed
+    where
+        O: ComputedOutputs,
+        I: StateGetter + Send + Sync + 'static,
+        F: Fn(<I as StateGetter>::Output<'_>) -> O + Send + Sync + 'static,
+    {
+        let outputs = compute(states.get()).use_states(self);
+
+        let inner = self.computed_inner();
+        self.computed_states
+            .add_computed_multi(outputs, inner, states, compute)
+    }
+
+    /// Creates a computed state that is recomputed every `period` instead of when a state
+    /// changes, for values that depend on the time, like how long ago something happened:
+    ///
+    /// ```ignore
+    /// let opened = Instant::now();
+    /// let label = ctx.use_timed(Duration::from_secs(1), move || {
+    ///     format!("opened {} seconds ago", opened.elapsed().as_secs())
+    /// });
+    /// ```
+    ///
+    /// `compute` runs once right away for the initial value, and then on every tick while the
+    /// websocket is connected. It stops once the connection is gone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    #[track_caller]
+    pub fn use_timed<O, F>(&mut self, period: Duration, compute: F) -> ComputedState<O>
+    where
+        O: DeserializeOwned + Display + Send + Sync + 'static,
+        F: Fn() -> O + Send + Sync + 'static,
+    {
+        assert!(!period.is_zero(), "period must be greater than 0");
+
+        let state = self.use_state_inner(
+            compute(),
+            #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+            std::panic::Location::caller(),
+        );
+
+        let inner = self.computed_inner();
+        self.computed_states
+            .add_timed(state, inner, period, compute)
+    }
+
+    /// Runs `tick` every `period` while the websocket is connected, for work that repeats on the
+    /// server, like polling something and setting a state with the result:
+    ///
+    /// ```ignore
+    /// let now = ctx.use_state(now_label());
+    /// ctx.use_interval(Duration::from_secs(1), move || now.set(now_label()));
+    /// ```
+    ///
+    /// The first tick is one `period` after the websocket connects. It stops when the connection
+    /// ends, and starts again if the client reconnects to the same context.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    #[track_caller]
+    pub fn use_interval<F>(&mut self, period: Duration, tick: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(!period.is_zero(), "period must be greater than 0");
+
+        self.computed_states.add_interval(period, tick);
     }
 
     #[track_caller]
@@ -153,7 +777,8 @@ impl<S> Context<S> {
             std::panic::Location::caller(),
         );
 
-        self.computed_states.add_computed(state, states, compute)
+        self.computed_states
+            .add_computed(state, self.computed_inner(), states, compute)
     }
 
     pub async fn use_computed_async<O, I, F, FUT>(
@@ -171,8 +796,34 @@ impl<S> Context<S> {
         // https://github.com/rust-lang/rust/issues/110011
         let state = self.use_state(compute(states.get()).await);
 
+        let inner = self.computed_inner();
         self.computed_states
-            .add_computed_async(state, states, compute, false)
+            .add_computed_async(state, inner, states, compute, false, None)
+    }
+
+    /// Like [`Context::use_computed_async`], but recomputes only after `states` haven't changed
+    /// for `delay`.
+    ///
+    /// When `states` change while a recompute is waiting or running, it's aborted and a new one
+    /// is started, so the value always comes from the latest change, even if an older
+    /// computation would have finished later.
+    pub async fn use_computed_async_debounced<O, I, F, FUT>(
+        &mut self,
+        states: I,
+        compute: F,
+        delay: Duration,
+    ) -> ComputedState<O>
+    where
+        O: DeserializeOwned + Display + Send + Sync + 'static,
+        I: StateGetter,
+        F: Fn(<I as StateGetter>::Output<'_>) -> FUT + Send + Sync + 'static,
+        FUT: Future<Output = O> + Send + Sync + 'static,
+    {
+        let state = self.use_state(compute(states.get()).await);
+
+        let inner = self.computed_inner();
+        self.computed_states
+            .add_computed_async(state, inner, states, compute, false, Some(delay))
     }
 
     #[track_caller]
@@ -203,21 +854,402 @@ impl<S> Context<S> {
             std::panic::Location::caller(),
         );
 
+        let inner = self.computed_inner();
         self.computed_states.add_computed_async(
             state,
+            inner,
             states,
             compute,
-            needs_recompute && self.in_websocket,
+            needs_recompute,
+            None,
         )
     }
 
-    pub fn on_client_event<F, Fut, P>(&mut self, name: impl ToString, closure: F)
+    /// Paginates a list of `total` items, showing `page_size` items per page.
+    ///
+    /// Returns a [`Pagination`], with the current `page` starting at 0, the amount of pages, the
+    /// offset of the first item in the page, and `prev` and `next` closures that don't go past the
+    /// first or last page:
+    ///
+    /// ```ignore
+    /// let pagination = ctx.use_pagination(total, 20);
+    /// let rows = ctx.use_computed(pagination.offset(), |offset| load_rows(*offset, 20));
+    /// button("next", attrs!("onclick" => pagination.next()))
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_size` is 0.
+    #[track_caller]
+    pub fn use_pagination(&mut self, total: State<usize>, page_size: usize) -> Pagination {
+        assert!(page_size > 0, "page_size must be greater than 0");
+
+        let page = self.use_state(0usize);
+        let total_pages = self.use_computed(total, move |total| page_count(*total, page_size));
+        let offset = self.use_computed((page, total), move |(page, total)| {
+            clamp_page(*page, *total, page_size) * page_size
+        });
+
+        let prev = self.use_closure(move || async move {
+            let total = *total.get();
+            page.modify(|page| clamp_page(*page, total, page_size).saturating_sub(1));
+        });
+        let next = self.use_closure(move || async move {
+            let total = *total.get();
+            page.modify(|page| clamp_page(*page + 1, total, page_size));
+        });
+
+        Pagination {
+            page,
+            total_pages,
+            offset,
+            prev,
+            next,
+        }
+    }
+
+    /// Loads data in the background with `fetch`, and again every time `source` changes.
+    ///
+    /// Returns a [`Resource`], which has three states:
+    /// - `value`: the value of the last fetch that succeeded, or `None` until the first one does
+    /// - `loading`: whether a fetch is running, starting as true
+    /// - `error`: the error of the last fetch, or `None` if it succeeded
+    ///
+    /// If `source` changes while a fetch is running, only the newest fetch sets the states,
+    /// and the results of the older ones are ignored.
+    /// The first fetch starts once the websocket connects, so the page is rendered while loading.
+    #[track_caller]
+    pub fn use_resource<T, E, I, F, FUT>(&mut self, source: I, fetch: F) -> Resource<T>
     where
-        F: Fn(P) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = ()> + Send + Sync + 'static,
-        P: serde::de::DeserializeOwned + Send + Sync + 'static,
+        T: DeserializeOwned + Display + Send + Sync + 'static,
+        E: Display + 'static,
+        I: StateGetter,
+        F: Fn(<I as StateGetter>::Output<'_>) -> FUT + Send + Sync + 'static,
+        FUT: Future<Output = Result<T, E>> + Send + Sync + 'static,
     {
-        self.events.add(name.to_string(), closure);
+        let resource = Resource {
+            value: self.insert_state(
+                None,
+                display_option::<T>,
+                #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+                std::panic::Location::caller(),
+            ),
+            loading: self.use_state_inner(
+                true,
+                #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+                std::panic::Location::caller(),
+            ),
+            error: self.insert_state(
+                None,
+                display_option::<String>,
+                #[cfg(any(debug_assertions, feature = "debug_ownership"))]
+                std::panic::Location::caller(),
+            ),
+        };
+
+        let fetch = Arc::new(fetch);
+        let latest = Arc::new(AtomicUsize::new(0));
+        let ids = source.id_list().collect::<Vec<_>>();
+        let listener: OnChangeHandlerAsync = Arc::new(move || {
+            let fetch = fetch.clone();
+            let source = source.clone();
+            let latest = latest.clone();
+
+            Box::pin(async move {
+                let this = latest.fetch_add(1, Ordering::SeqCst) + 1;
+                if !*resource.loading.get() {
+                    resource.loading.set(true);
+                }
+
+                // the source is only borrowed while starting the fetch, so it can change while it runs
+                let future = fetch(source.get());
+                let result = future.await;
+
+                // a newer fetch started while this one was running
+                if latest.load(Ordering::SeqCst) != this {
+                    return;
+                }
+
+                match result {
+                    Ok(value) => {
+                        resource.value.set(Some(value));
+                        resource.error.set(None);
+                    }
+                    Err(error) => resource.error.set(Some(error.to_string())),
+                }
+                resource.loading.set(false);
+            })
+        });
+
+        self.computed_states
+            .add_async_listener(ids.into_iter(), listener, true);
+
+        resource
+    }
+
+    #[track_caller]
+    fn computed_inner(&self) -> GenerationalBox<ComputedInner, SyncStorage> {
+        self.state_owner.insert(ComputedInner {
+            invalidate_tx: self.computed_states.invalidate_tx.clone(),
+        })
+    }
+
+    /// Runs `handler` every time the client fires the event `name` on the document.
+    ///
+    /// The last argument of the handler is the event. The arguments before it can be extractors,
+    /// like `Extension<T>`, which get the parts of the request the page was loaded with.
+    pub fn on_client_event<F, M>(&mut self, name: impl ToString, handler: F)
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        self.events.add(name.to_string(), handler, false);
+    }
+
+    /// Like [`Context::on_client_event`], but with `options` for the listener, like calling
+    /// `preventDefault()` on the event:
+    ///
+    /// ```ignore
+    /// ctx.on_client_event_with(
+    ///     "click",
+    ///     EventOptions { prevent_default: true, ..Default::default() },
+    ///     |event: Value| async move { /* ... */ },
+    /// );
+    /// ```
+    ///
+    /// There is a single listener for each event, so if any of the handlers for an event sets an option,
+    /// it applies to all of them.
+    pub fn on_client_event_with<F, M>(
+        &mut self,
+        name: impl ToString,
+        options: EventOptions,
+        handler: F,
+    ) where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        self.events
+            .add_with(name.to_string(), options, handler, false);
+    }
+
+    /// Like [`Context::on_client_event`], but handlers added with this function run one after the
+    /// other, in the order they were added, instead of concurrently.
+    ///
+    /// Handlers added with `on_client_event` for the same event still run concurrently with them.
+    pub fn on_client_event_seq<F, M>(&mut self, name: impl ToString, handler: F)
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        self.events.add(name.to_string(), handler, true);
+    }
+
+    /// Like [`Context::on_client_event`], but only runs `handler` for the key and modifiers in `filter`,
+    /// for keyboard events like `keydown`:
+    ///
+    /// ```ignore
+    /// ctx.on_key("keydown", "Escape", |_event: Value| async move { /* close the dialog */ });
+    /// ctx.on_key("keydown", KeyFilter::new("k").meta(), |_event: Value| async move { /* search */ });
+    /// ```
+    ///
+    /// Events are filtered by the client, so other keystrokes aren't sent to the server.
+    /// The `key` field and the modifier fields (`ctrlKey`, `metaKey`, `altKey` and `shiftKey`) are always sent.
+    pub fn on_key<F, M>(&mut self, name: impl ToString, filter: impl Into<KeyFilter>, handler: F)
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        self.events
+            .add_key(name.to_string(), filter.into(), handler);
+    }
+
+    /// Runs `handler` every time the client fires the event `name` on `element`, and returns the element.
+    ///
+    /// Unlike [`Context::on_client_event`], the listener is added to the element itself, so events
+    /// fired on other elements don't run the handler. The element is given an id if it doesn't have one.
+    pub fn on_element_event<F, M>(
+        &mut self,
+        mut element: Element,
+        name: impl ToString,
+        handler: F,
+    ) -> Element
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        let element_id = *element
+            .id
+            .get_or_insert_with(|| RandomId::from_rng(&mut self.rng));
+        self.events
+            .add_to_element(element_id, name.to_string(), handler);
+
+        element
+    }
+
+    /// Runs `handler` every time `form` is submitted, and returns the form.
+    ///
+    /// The submission is prevented, and the fields of the form are sent instead, as an object with the
+    /// name of each field as its key. The last argument of the handler is deserialized from it:
+    ///
+    /// ```ignore
+    /// #[derive(serde::Deserialize)]
+    /// struct Signup {
+    ///     name: String,
+    ///     email: String,
+    ///     newsletter: bool,
+    /// }
+    ///
+    /// let form = ctx.on_submit(form, |data: Signup| async move { /* ... */ });
+    /// ```
+    ///
+    /// Values are sent as strings, except for:
+    /// - checkboxes, which are sent as `true` or `false`. If several checkboxes share a name, the values
+    ///   of the checked ones are sent as a list instead
+    /// - selects with `multiple`, which are always sent as a list of the selected values
+    ///
+    /// Buttons, file inputs, disabled fields and fields without a name are not sent.
+    pub fn on_submit<F, M>(&mut self, mut form: Element, handler: F) -> Element
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        let element_id = *form
+            .id
+            .get_or_insert_with(|| RandomId::from_rng(&mut self.rng));
+        self.events.add_form_submit(element_id, handler);
+
+        form
+    }
+
+    /// Sets the handler for binary websocket frames, which aren't used by the built-in protocol.
+    ///
+    /// Frames are only received after the websocket connects. Calling this again replaces the previous handler.
+    pub fn on_binary<F, Fut>(&mut self, handler: F)
+    where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.frames.set_binary(handler);
+    }
+
+    /// Sets the handler for text websocket frames that aren't messages of the built-in protocol.
+    ///
+    /// Built-in messages take precedence, so this only receives text that isn't a JSON object with
+    /// one of their tags. Calling this again replaces the previous handler.
+    pub fn on_text<F, Fut>(&mut self, handler: F)
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.frames.set_text(handler);
+    }
+
+    /// Returns a handle that makes this client reload the page, like to recover when it can't
+    /// be kept in sync anymore.
+    ///
+    /// To reload every client, use [`Config::reload_clients`].
+    pub fn use_reload_client(&self) -> ReloadClient {
+        ReloadClient {
+            tx: self.reload_tx.clone(),
+        }
+    }
+
+    /// Returns a handle that sends this client to another page, or changes its URL,
+    /// for closures to use, like after a form is submitted:
+    ///
+    /// ```ignore
+    /// let navigator = ctx.use_navigator();
+    /// let save = ctx.use_closure(move || {
+    ///     let navigator = navigator.clone();
+    ///     async move {
+    ///         save_post().await;
+    ///         navigator.navigate("/posts");
+    ///     }
+    /// });
+    /// ```
+    pub fn use_navigator(&self) -> Navigator {
+        Navigator {
+            tx: self.navigation_tx.clone(),
+        }
+    }
+
+    /// Sends the client to `url` once it connects. See [`Context::use_navigator`].
+    pub fn navigate(&self, url: impl ToString) {
+        self.use_navigator().navigate(url);
+    }
+
+    /// Changes the URL of the client to `url` once it connects, without loading it.
+    /// See [`Context::use_navigator`].
+    pub fn replace_state(&self, url: impl ToString) {
+        self.use_navigator().replace_state(url);
+    }
+
+    /// Returns the users that are connected, and a way to subscribe to them coming and going.
+    ///
+    /// Users are only tracked if the config sets how to find them, with
+    /// [`Config::with_presence`]. To keep a state in sync with them:
+    ///
+    /// ```ignore
+    /// let presence = ctx.presence();
+    /// let mut changes = presence.subscribe();
+    /// let online = ctx.use_state(presence.users().len());
+    /// let task = tokio::spawn(async move {
+    ///     while changes.recv().await.is_ok() {
+    ///         online.set(presence.users().len());
+    ///     }
+    /// });
+    /// ctx.on_disconnect(move || task.abort());
+    /// ```
+    pub fn presence(&self) -> Presence {
+        self.presence.clone()
+    }
+
+    /// Registers `callback` to run once the client is gone, to clean up what the handler started,
+    /// like tasks spawned with `tokio::spawn`.
+    ///
+    /// It runs once, when the context is finished: when the connection ends without the client
+    /// being able to reconnect, like when it doesn't answer the heartbeat or is reloaded, or when a
+    /// context kept for it to reconnect to expires (see [`Config::with_session_ttl`]) or is replaced.
+    /// A connection that ends but can still be resumed doesn't run it.
+    pub fn on_disconnect(&mut self, callback: impl FnOnce() + Send + 'static) {
+        self.disconnect_callbacks.push(Box::new(callback));
+    }
+
+    /// Sets the attributes of the root `<html>` element created by the layout, like `lang` or `dir`.
+    ///
+    /// Attributes can be reactive. Calling this again replaces the previous attributes.
+    /// They replace the attributes with the same key set by the layout.
+    pub fn set_html_attrs(&mut self, attributes: Attributes) {
+        self.html_attributes = attributes;
+    }
+
+    /// Sets the `<title>` of the page. Calling this again replaces the previous title.
+    ///
+    /// The layout adds it to the `<head>`, with [`LayoutContext::take_head`](crate::config::LayoutContext::take_head).
+    pub fn set_title(&mut self, title: impl ToString) {
+        self.title = Some(title.to_string());
+    }
+
+    /// Adds an element to the `<head>` of the page, like a `<meta>` tag or a stylesheet.
+    ///
+    /// Elements are added after the title, in the order they were pushed.
+    /// The layout adds them to the `<head>`, with [`LayoutContext::take_head`](crate::config::LayoutContext::take_head).
+    pub fn push_head(&mut self, element: Element) {
+        self.head.push(element);
+    }
+
+    /// Adds a header to the response of the initial page load, like `Cache-Control` or `Set-Cookie`.
+    ///
+    /// Headers are appended, so setting the same header more than once sends all the values.
+    /// They are ignored when the handler runs again for the websocket connection.
+    pub fn response_header(&mut self, name: HeaderName, value: HeaderValue) {
+        self.response_headers.append(name, value);
     }
 
     pub fn with(self, element: Element) -> CoaxialResponse<S> {
@@ -228,43 +1260,336 @@ impl<S> Context<S> {
     }
 
     /// Returns an Element containing an HTML `<script>` tag containing the adapter JS code.
-    pub(crate) fn adapter_script_element(&self, reactive_scripts: &str) -> Element {
-        let mut script = include_str!("base.js")
-            .to_string()
-            .replace("__internal__coaxialSeed", &self.rng_seed.to_string());
-
-        for (name, fields) in self.events.list() {
-            script.push_str("document.addEventListener('");
-            script.push_str(name);
-            script.push_str("', params=>{params={");
-
-            // NOTE: this serves two puposes:
-            // 1. events are big objects with lots of fields, so we only wanna send the ones we care about over the wire
-            // 2. serialization of events is wonky, and a lot of times fields are not set correctly
-            for field in fields {
-                script.push_str(field);
-                script.push_str(": params.");
-                script.push_str(field);
-                script.push(',');
-            }
+    ///
+    /// `socket_path` is the path the websocket will connect to.
+    /// `nonce` is added to the tag, for pages with a `Content-Security-Policy`.
+    pub(crate) fn adapter_script_element(
+        &self,
+        reactive_scripts: &str,
+        config: &Config,
+        socket_path: &str,
+        nonce: Option<&str>,
+    ) -> Element {
+        let mut script = include_str!("base.js").to_string();
 
-            script.push_str("};if (window.Coaxial) window.Coaxial.onEvent('");
-            script.push_str(name);
-            script.push_str("', params);});");
+        for (name, options, key_filter, fields) in self.events.list() {
+            // names are written as JSON strings, since they can have quotes or newlines
+            script.push_str("document.addEventListener(");
+            let listen_to = key_filter.map_or(name, |(listen_to, _)| listen_to);
+            write!(script, "{}", serde_json::Value::from(listen_to)).unwrap();
+            script.push_str(", params=>{");
+            // other keys are ignored before anything else, so they can still do their default action
+            if let Some((_, filter)) = key_filter {
+                filter.push_guard(&mut script);
+            }
+            // these have to be called on the event itself, before it's replaced by its fields
+            if options.prevent_default {
+                script.push_str("params.preventDefault();");
+            }
+            if options.stop_propagation {
+                script.push_str("params.stopPropagation();");
+            }
+            script.push_str("params={");
+            push_event_fields(&mut script, fields);
+            script.push_str("};if (window.Coaxial) window.Coaxial.onEvent(");
+            write!(script, "{}", serde_json::Value::from(name)).unwrap();
+            script.push_str(", params);});");
         }
 
-        script
-            .write_fmt(format_args!(
-                "document.addEventListener(\"DOMContentLoaded\", () => {{ {} }});",
-                reactive_scripts
-            ))
+        let options = serde_json::json!({
+            "seedParam": config.seed_param,
+            "reconnectDelays": config.reconnect_delays(),
+            "nonce": nonce,
+        });
+        let mut init = format!(
+            "window.Coaxial = new Coaxial('{}', '{}', {options}); ",
+            self.rng_seed,
+            html_escape::encode_script_single_quoted_text(socket_path),
+        );
+        for (id, delay) in &self.debounced_states {
+            write!(
+                init,
+                "window.Coaxial.debounceState('{id}', {});",
+                delay.as_millis()
+            )
             .unwrap();
+        }
+        for id in &self.json_states {
+            write!(init, "window.Coaxial.jsonState('{id}');").unwrap();
+        }
+        // elements only exist once the document is parsed, so these go with the rest of the init
+        for (id, name, fields) in self.events.list_elements() {
+            let name = serde_json::Value::from(name);
+            write!(
+                init,
+                "document.querySelector('[coax-id=\"{id}\"]')?.addEventListener({name}, params=>{{params={{"
+            )
+            .unwrap();
+            push_event_fields(&mut init, fields);
+            write!(
+                init,
+                "}};window.Coaxial.onEvent({name}, params, '{id}');}});"
+            )
+            .unwrap();
+        }
+        for id in self.events.list_forms() {
+            write!(
+                init,
+                "document.querySelector('[coax-id=\"{id}\"]')?.addEventListener('submit', e=>{{e.preventDefault();window.Coaxial.onEvent('submit', window.Coaxial.formData(e.currentTarget), '{id}');}});"
+            )
+            .unwrap();
+        }
+        init.push_str(reactive_scripts);
+
+        // module scripts are deferred, so the document has already been parsed when they run
+        if config.script_loading.is_deferred() || !config.wait_for_dom {
+            script.push_str(&init);
+        } else {
+            script
+                .write_fmt(format_args!(
+                    "document.addEventListener(\"DOMContentLoaded\", () => {{ {} }});",
+                    init
+                ))
+                .unwrap();
+        }
+
+        let mut attributes = config.script_loading.attributes();
+        if let Some(nonce) = nonce {
+            attributes.insert("nonce", nonce);
+        }
 
         crate::html::script(
             Content::Value(ContentValue::Raw(
                 html_escape::encode_script(&script).to_string(),
             )),
-            Default::default(),
+            attributes,
         )
     }
+
+    /// Returns the states that nothing in the page uses, with the place they were created in.
+    ///
+    /// A state is used if it appears in `used_states`, the states that elements depend on,
+    /// or if a computed state, listener or list depends on it.
+    #[cfg(debug_assertions)]
+    pub(crate) fn orphaned_states(
+        &self,
+        used_states: &std::collections::HashSet<String>,
+    ) -> Vec<(RandomId, &'static Location<'static>)> {
+        let mut orphaned = self
+            .states
+            .ids()
+            .filter(|id| {
+                !used_states.contains(&id.to_string())
+                    && !self.computed_states.has_dependents(*id)
+                    && !self.lists.contains(*id)
+            })
+            .map(|id| (id, self.state_locations[&id]))
+            .collect::<Vec<_>>();
+        orphaned.sort_by_key(|(_, location)| (location.file(), location.line(), location.column()));
+
+        orphaned
+    }
+}
+
+/// Writes the fields of an event that get sent to the server, as `field: params.field,`
+fn push_event_fields<'a>(script: &mut String, fields: impl Iterator<Item = &'a str>) {
+    // NOTE: this serves two puposes:
+    // 1. events are big objects with lots of fields, so we only wanna send the ones we care about over the wire
+    // 2. serialization of events is wonky, and a lot of times fields are not set correctly
+    for field in fields {
+        script.push_str(field);
+        script.push_str(": params.");
+        script.push_str(field);
+        script.push(',');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, ScriptLoading};
+
+    use super::*;
+
+    fn render_adapter(config: &Config) -> String {
+        let ctx = Context::<()>::new(0, false);
+
+        let mut output = String::new();
+        ctx.adapter_script_element("", config, "/", None)
+            .render(&mut output);
+        output
+    }
+
+    #[test]
+    fn test_classic_adapter_waits_for_dom() {
+        let output = render_adapter(&Config::default());
+
+        assert!(output.starts_with("<script>"));
+        assert!(output.contains("document.addEventListener(\"DOMContentLoaded\""));
+    }
+
+    #[test]
+    fn test_module_adapter_runs_immediately() {
+        let output = render_adapter(&Config::default().with_script_loading(ScriptLoading::Module));
+
+        assert!(output.starts_with("<script type=\"module\">"));
+        assert!(!output.contains("DOMContentLoaded"));
+        assert!(output.contains("window.Coaxial = new Coaxial('0', '/', {"));
+    }
+
+    #[test]
+    fn test_adapter_without_dom_wait_runs_immediately() {
+        let output = render_adapter(&Config::default().with_wait_for_dom(false));
+
+        assert!(output.starts_with("<script>"));
+        assert!(!output.contains("DOMContentLoaded"));
+        assert!(output.contains("window.Coaxial = new Coaxial('0', '/', {"));
+    }
+
+    #[test]
+    fn test_adapter_has_nonce() {
+        let ctx = Context::<()>::new(0, false);
+
+        let mut output = String::new();
+        ctx.adapter_script_element("", &Config::default(), "/", Some("r4nd0m"))
+            .render(&mut output);
+
+        assert!(output.starts_with("<script nonce=\"r4nd0m\">"));
+    }
+
+    #[test]
+    fn test_debounced_state_is_registered() {
+        let mut ctx = Context::<()>::new(0, false);
+        let state = ctx.use_debounced_state(String::new(), Duration::from_millis(300));
+
+        let mut output = String::new();
+        ctx.adapter_script_element("", &Config::default(), "/", None)
+            .render(&mut output);
+
+        assert!(output.contains(&format!(
+            "window.Coaxial.debounceState('{}', 300);",
+            state.id
+        )));
+    }
+
+    #[test]
+    fn test_json_state_is_registered() {
+        let mut ctx = Context::<()>::new(0, false);
+        let state = ctx.use_json_state(vec![1, 2]);
+
+        let mut output = String::new();
+        ctx.adapter_script_element("", &Config::default(), "/", None)
+            .render(&mut output);
+
+        assert!(output.contains(&format!("window.Coaxial.jsonState('{}');", state.id)));
+    }
+
+    #[test]
+    fn test_event_options_are_applied_to_the_listener() {
+        let mut ctx = Context::<()>::new(0, false);
+        ctx.on_client_event("keydown", |_event: serde_json::Value| async move {});
+        ctx.on_client_event_with(
+            "click",
+            EventOptions {
+                prevent_default: true,
+                ..Default::default()
+            },
+            |_event: serde_json::Value| async move {},
+        );
+
+        let mut output = String::new();
+        ctx.adapter_script_element("", &Config::default(), "/", None)
+            .render(&mut output);
+
+        assert!(output.contains(
+            "document.addEventListener(\"click\", params=>{params.preventDefault();params={"
+        ));
+        assert!(output.contains("document.addEventListener(\"keydown\", params=>{params={"));
+        assert!(!output.contains("stopPropagation"));
+    }
+
+    #[test]
+    fn test_event_names_are_escaped() {
+        use crate::html::button;
+
+        let mut ctx = Context::<()>::new(0, false);
+        ctx.on_client_event("it's\nodd", |_event: serde_json::Value| async move {});
+        let el = ctx.on_element_event(
+            button("go", Default::default()),
+            "it's\nodd",
+            |_event: serde_json::Value| async move {},
+        );
+        let id = el.id.unwrap();
+
+        let mut output = String::new();
+        ctx.adapter_script_element("", &Config::default(), "/", None)
+            .render(&mut output);
+
+        assert!(output.contains("document.addEventListener(\"it's\\nodd\", params=>{"));
+        assert!(output.contains(&format!(
+            "document.querySelector('[coax-id=\"{id}\"]')?.addEventListener(\"it's\\nodd\", params=>{{"
+        )));
+        assert!(output.contains(&format!("onEvent(\"it's\\nodd\", params, '{id}')")));
+        assert!(!output.contains("it's\nodd"));
+    }
+
+    #[test]
+    fn test_key_listener_has_guard() {
+        let mut ctx = Context::<()>::new(0, false);
+        ctx.on_key(
+            "keydown",
+            "Enter",
+            |_event: serde_json::Value| async move {},
+        );
+        ctx.on_key(
+            "keydown",
+            KeyFilter::new("s").ctrl().shift(),
+            |_event: serde_json::Value| async move {},
+        );
+
+        let mut output = String::new();
+        ctx.adapter_script_element("", &Config::default(), "/", None)
+            .render(&mut output);
+
+        assert!(output.contains(
+            "document.addEventListener(\"keydown\", params=>{if(params.key!==\"Enter\"||params.ctrlKey!==false||params.metaKey!==false||params.altKey!==false)return;params={"
+        ));
+        assert!(output.contains(
+            "if(params.key!==\"s\"||params.ctrlKey!==true||params.metaKey!==false||params.altKey!==false||!params.shiftKey)return;"
+        ));
+        assert!(output.contains("key: params.key,"));
+        assert!(output.contains("window.Coaxial.onEvent(\"keydown:Enter\", params);"));
+        assert!(output.contains("window.Coaxial.onEvent(\"keydown:Ctrl+Shift+s\", params);"));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_orphaned_states_are_found() {
+        use crate::{html::p, reactive_js::element_reactivity_script_with_states};
+        use std::collections::HashSet;
+
+        let mut ctx = Context::<()>::new(0, false);
+        let rendered = ctx.use_state(1u32);
+        let source = ctx.use_state(2u32);
+        let doubled = ctx.use_computed(source, |source| *source * 2);
+        let orphan_line = line!() + 1;
+        let orphan = ctx.use_state(3u32);
+
+        let mut el = p(
+            vec![ContentValue::from(rendered), ContentValue::from(doubled)],
+            Default::default(),
+        );
+        let mut used_states = HashSet::new();
+        element_reactivity_script_with_states(
+            &mut el,
+            crate::random_id::IdPath::root(0, ""),
+            &mut used_states,
+        );
+
+        // `source` is not rendered, but `doubled` depends on it
+        let orphaned = ctx.orphaned_states(&used_states);
+        assert_eq!(1, orphaned.len());
+        assert_eq!(orphan.id, orphaned[0].0);
+        assert_eq!(file!(), orphaned[0].1.file());
+        assert_eq!(orphan_line, orphaned[0].1.line());
+    }
 }
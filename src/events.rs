@@ -6,118 +6,489 @@ use std::{
     sync::Arc,
 };
 
+use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use tokio::task::JoinSet;
 
-use crate::helpers;
+use crate::{helpers, random_id::RandomId};
 
-#[derive(Default)]
-pub(crate) struct Events {
-    events: HashMap<String, Event>,
+pub(crate) struct Events<S> {
+    events: HashMap<String, Event<S>>,
+    /// Events listened to on a single element, by the element's id and the event name
+    element_events: HashMap<(RandomId, String), Event<S>>,
+    /// Forms whose `submit` event sends the data of the form, instead of fields of the event
+    forms: HashSet<RandomId>,
 
-    join_set: JoinSet<()>,
+    pub(crate) join_set: JoinSet<()>,
 }
 
-impl Events {
-    pub(crate) fn add<F, Fut, P>(&mut self, name: String, closure: F)
+impl<S> Events<S> {
+    /// Adds a handler for the event `name`.
+    ///
+    /// Sequential handlers run one after the other, in the order they were added.
+    pub(crate) fn add<F, M>(&mut self, name: String, handler: F, sequential: bool)
     where
-        F: Fn(P) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = ()> + Send + Sync + 'static,
-        P: serde::de::DeserializeOwned + Send + Sync + 'static,
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
     {
-        if let Some(event) = self.events.get_mut(&name) {
-            let wrapper = EventHandlerWrapper::new(closure);
+        self.add_with(name, EventOptions::default(), handler, sequential);
+    }
 
-            if let Some(params) = helpers::struct_fields::<'_, P>() {
-                for param in params {
-                    event.params.insert(param);
-                }
-            }
+    /// Like [`Events::add`], but also applies `options` to the listener of the event.
+    ///
+    /// There is a single listener for each event, so the options of all of its handlers are combined.
+    pub(crate) fn add_with<F, M>(
+        &mut self,
+        name: String,
+        options: EventOptions,
+        handler: F,
+        sequential: bool,
+    ) where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        let event = self.events.entry(name).or_default();
+        event.options.prevent_default |= options.prevent_default;
+        event.options.stop_propagation |= options.stop_propagation;
+        event.add(handler, sequential);
+    }
 
-            event.handlers.push(Arc::new(wrapper));
-        } else {
-            let wrapper = EventHandlerWrapper::new(closure);
+    /// Adds a handler for the event `name` that only runs for the keys matched by `filter`.
+    ///
+    /// The events are filtered by the client, and sent with their own name, so each filter gets its own listener.
+    pub(crate) fn add_key<F, M>(&mut self, name: String, filter: KeyFilter, handler: F)
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        let event = self.events.entry(filter.event_name(&name)).or_default();
+        // handlers that take the whole event would get nothing otherwise
+        event.params.extend(KEY_FIELDS);
+        event.key_filter = Some((name, filter));
+        event.add(handler, false);
+    }
+
+    /// Adds a handler for the event `name` fired on the element with id `element_id`.
+    pub(crate) fn add_to_element<F, M>(&mut self, element_id: RandomId, name: String, handler: F)
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        self.element_events
+            .entry((element_id, name))
+            .or_default()
+            .add(handler, false);
+    }
+
+    /// Adds a handler for the `submit` event of the form with id `element_id`, which gets the data of the form.
+    pub(crate) fn add_form_submit<F, M>(&mut self, element_id: RandomId, handler: F)
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        self.forms.insert(element_id);
+        self.add_to_element(element_id, "submit".to_string(), handler);
+    }
+
+    /// Returns a descriptor of the events that are listened to, with their options, the event and keys
+    /// they're filtered by if they were added with [`Events::add_key`], and the fields each have
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn list(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            &str,
+            EventOptions,
+            Option<&(String, KeyFilter)>,
+            impl Iterator<Item = &str>,
+        ),
+    > {
+        self.events.iter().map(|(name, event)| {
+            (
+                name.as_str(),
+                event.options,
+                event.key_filter.as_ref(),
+                event.params.iter().cloned(),
+            )
+        })
+    }
+
+    /// Like [`Events::list`], but for the events listened to on a single element.
+    ///
+    /// The `submit` event of forms added with [`Events::add_form_submit`] is not included.
+    pub(crate) fn list_elements(
+        &self,
+    ) -> impl Iterator<Item = (RandomId, &str, impl Iterator<Item = &str>)> {
+        self.element_events
+            .iter()
+            .filter(|((id, name), _)| !(name == "submit" && self.forms.contains(id)))
+            .map(|((id, name), event)| (*id, name.as_str(), event.params.iter().cloned()))
+    }
 
-            let params = helpers::struct_fields::<'_, P>().unwrap_or_default();
-            let params = HashSet::from_iter(params.iter().cloned());
+    /// Returns the ids of the forms whose data is sent when they are submitted
+    pub(crate) fn list_forms(&self) -> impl Iterator<Item = RandomId> + '_ {
+        self.forms.iter().copied()
+    }
+}
 
-            let event = Event {
-                handlers: vec![Arc::new(wrapper)],
-                params,
-            };
+impl<S: Clone + Send + 'static> Events<S> {
+    /// Runs the handlers for the event `name`
+    ///
+    /// Extractors used by the handlers get the `parts` of the request and the router's `state`,
+    /// the same way closures do.
+    pub(crate) fn handle(&mut self, name: String, params: Value, parts: &Parts, state: &S) {
+        if let Some(event) = self.events.get(&name) {
+            event.run(&mut self.join_set, params, parts, state);
+        }
+    }
 
-            self.events.insert(name, event);
+    /// Runs the handlers for the event `name` fired on the element with id `element_id`
+    pub(crate) fn handle_element(
+        &mut self,
+        element_id: RandomId,
+        name: String,
+        params: Value,
+        parts: &Parts,
+        state: &S,
+    ) {
+        if let Some(event) = self.element_events.get(&(element_id, name)) {
+            event.run(&mut self.join_set, params, parts, state);
         }
     }
+}
 
-    pub(crate) fn handle(&mut self, name: String, params: Value) {
-        let Some(event) = self.events.get(&name) else {
-            return;
-        };
+impl<S> Default for Events<S> {
+    fn default() -> Self {
+        Self {
+            events: Default::default(),
+            element_events: Default::default(),
+            forms: Default::default(),
+            join_set: Default::default(),
+        }
+    }
+}
+
+struct Event<S> {
+    /// Handlers that run concurrently
+    handlers: Vec<Arc<dyn EventHandler<S>>>,
+    /// Handlers that run one after the other, in registration order
+    sequential_handlers: Vec<Arc<dyn EventHandler<S>>>,
+    params: HashSet<&'static str>,
+    options: EventOptions,
+    /// The event that is listened to and the keys it's filtered by, for events added with [`Events::add_key`]
+    key_filter: Option<(String, KeyFilter)>,
+}
+
+/// Options for the listener of a client event.
+///
+/// See [`Context::on_client_event_with`](crate::context::Context::on_client_event_with).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventOptions {
+    /// Calls `preventDefault()` on the event, so the browser doesn't run its default action,
+    /// like following a link or submitting a form
+    pub prevent_default: bool,
+    /// Calls `stopPropagation()` on the event, so it isn't passed on to other elements
+    pub stop_propagation: bool,
+}
+
+/// Fields of keyboard events that are always sent for handlers added with
+/// [`Context::on_key`](crate::context::Context::on_key)
+const KEY_FIELDS: [&str; 5] = ["key", "ctrlKey", "metaKey", "altKey", "shiftKey"];
+
+/// The key and modifiers a handler added with [`Context::on_key`](crate::context::Context::on_key) runs for:
+///
+/// ```ignore
+/// ctx.on_key("keydown", KeyFilter::new("s").ctrl(), |_event: Value| async move { /* save */ });
+/// ```
+///
+/// Ctrl, Meta and Alt have to be pressed if they're required, and not pressed otherwise.
+/// Shift is only checked if it's required, since it already changes the key, like `?` instead of `/`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct KeyFilter {
+    /// Compared with the `key` field of the event, like `Enter`, `Escape` or `a`
+    key: String,
+    ctrl: bool,
+    meta: bool,
+    alt: bool,
+    shift: bool,
+}
 
-        for handler in &event.handlers {
+impl KeyFilter {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Requires Ctrl to be pressed
+    pub fn ctrl(mut self) -> Self {
+        self.ctrl = true;
+        self
+    }
+
+    /// Requires Meta to be pressed, which is Cmd on macOS
+    pub fn meta(mut self) -> Self {
+        self.meta = true;
+        self
+    }
+
+    /// Requires Alt to be pressed, which is Option on macOS
+    pub fn alt(mut self) -> Self {
+        self.alt = true;
+        self
+    }
+
+    /// Requires Shift to be pressed
+    pub fn shift(mut self) -> Self {
+        self.shift = true;
+        self
+    }
+
+    /// The name the events that match are sent with, like `keydown:Ctrl+Enter`
+    fn event_name(&self, name: &str) -> String {
+        let mut event_name = format!("{name}:");
+        for (required, modifier) in [
+            (self.ctrl, "Ctrl+"),
+            (self.meta, "Meta+"),
+            (self.alt, "Alt+"),
+            (self.shift, "Shift+"),
+        ] {
+            if required {
+                event_name.push_str(modifier);
+            }
+        }
+        event_name.push_str(&self.key);
+        event_name
+    }
+
+    /// Pushes the JS that returns from the listener if the event in `params` doesn't match
+    pub(crate) fn push_guard(&self, script: &mut String) {
+        use std::fmt::Write;
+
+        write!(
+            script,
+            "if(params.key!=={}||params.ctrlKey!=={}||params.metaKey!=={}||params.altKey!=={}",
+            Value::from(self.key.as_str()),
+            self.ctrl,
+            self.meta,
+            self.alt,
+        )
+        .unwrap();
+        if self.shift {
+            script.push_str("||!params.shiftKey");
+        }
+        script.push_str(")return;");
+    }
+}
+
+impl From<&str> for KeyFilter {
+    fn from(key: &str) -> Self {
+        Self::new(key)
+    }
+}
+
+impl<S> Event<S> {
+    fn add<F, M>(&mut self, handler: F, sequential: bool)
+    where
+        F: IntoEventHandler<M, S> + Send + Sync + 'static,
+        M: Send + Sync + 'static,
+        EventHandlerWrapper<F, M>: EventHandler<S>,
+    {
+        let wrapper = Arc::new(<F as IntoEventHandler<M, S>>::wrap(handler));
+        let params = <F as IntoEventHandler<M, S>>::fields().unwrap_or_default();
+
+        for param in params {
+            self.params.insert(param);
+        }
+
+        if sequential {
+            self.sequential_handlers.push(wrapper);
+        } else {
+            self.handlers.push(wrapper);
+        }
+    }
+}
+
+impl<S: Clone + Send + 'static> Event<S> {
+    fn run(&self, join_set: &mut JoinSet<()>, params: Value, parts: &Parts, state: &S) {
+        for handler in &self.handlers {
             let handler = handler.clone();
             let params = params.clone();
-            self.join_set
-                .spawn(async move { handler.call(params).await });
+            let parts = parts.clone();
+            let state = state.clone();
+            join_set.spawn(async move { handler.call(params, parts, state).await });
         }
-    }
 
-    /// Returns a descriptor of the events that are listened to and the fields each have
-    pub(crate) fn list(&self) -> impl Iterator<Item = (&str, impl Iterator<Item = &str>)> {
-        self.events
-            .iter()
-            .map(|(name, event)| (name.as_str(), event.params.iter().cloned()))
+        if !self.sequential_handlers.is_empty() {
+            let handlers = self.sequential_handlers.clone();
+            let parts = parts.clone();
+            let state = state.clone();
+            join_set.spawn(async move {
+                for handler in handlers {
+                    handler
+                        .call(params.clone(), parts.clone(), state.clone())
+                        .await;
+                }
+            });
+        }
     }
 }
 
-struct Event {
-    handlers: Vec<Arc<dyn EventHandler>>,
-    params: HashSet<&'static str>,
+impl<S> Default for Event<S> {
+    fn default() -> Self {
+        Self {
+            handlers: Vec::new(),
+            sequential_handlers: Vec::new(),
+            params: HashSet::new(),
+            options: EventOptions::default(),
+            key_filter: None,
+        }
+    }
 }
 
-trait EventHandler: Send + Sync {
-    fn call(&self, params: serde_json::Value)
-        -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+/// Trait used to type-erase all event handlers, so they can be stored in the same Vec
+pub trait EventHandler<S>: Send + Sync {
+    fn call<'a>(
+        &'a self,
+        params: Value,
+        parts: Parts,
+        state: S,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
 }
 
-struct EventHandlerWrapper<P, F, T> {
+/// Wrapper type that actually implements `EventHandler`
+pub struct EventHandlerWrapper<T, M> {
     func: T,
-    _phantom: PhantomData<(P, F)>,
+    _phantom: PhantomData<M>,
 }
 
-impl<P, F, T> EventHandlerWrapper<P, F, T> {
-    pub(crate) fn new(func: T) -> Self {
-        Self {
+/// Functions that can handle client events.
+///
+/// The last argument is the event, deserialized from the fields sent by the client.
+/// Any arguments before it are extracted from the request, like in closures.
+pub trait IntoEventHandler<M, S> {
+    /// Fields of the event that need to be sent by the client
+    fn fields() -> Option<&'static [&'static str]>;
+
+    fn wrap<F>(func: F) -> EventHandlerWrapper<F, M> {
+        EventHandlerWrapper {
             func,
-            _phantom: PhantomData,
+            _phantom: Default::default(),
         }
     }
 }
 
-impl<T: Send + Sync, F, P> EventHandler for EventHandlerWrapper<P, F, T>
-where
-    T: Fn(P) -> F,
-    F: Future<Output = ()> + 'static + Send + Sync,
-    P: serde::de::DeserializeOwned + Send + Sync,
-{
-    fn call(
-        &self,
-        params: serde_json::Value,
-    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
-        let p: P = serde_json::from_value(params).unwrap();
-        Box::pin((self.func)(p))
-    }
+macro_rules! impl_event_handler {
+    (
+        $($ty:ident),*
+    ) => {
+        #[allow(non_snake_case, unused_mut, unused_variables)]
+        impl<S, F, Fut, E, $($ty,)*> EventHandler<S> for EventHandlerWrapper<F, (($($ty,)*), E)>
+        where
+            F: Fn($($ty,)* E) -> Fut + Send + Sync,
+            Fut: Future<Output = ()> + Send + Sync + 'static,
+            E: DeserializeOwned + Send + Sync,
+            $( $ty: FromRequestParts<S> + Send + Sync, )*
+            S: Send + Sync + 'static
+        {
+            fn call<'a>(
+                &'a self,
+                params: Value,
+                mut parts: Parts,
+                state: S,
+            ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+                Box::pin(async move {
+                    let event: E = match serde_json::from_value(params) {
+                        Ok(event) => event,
+                        Err(error) => {
+                            tracing::warn!(
+                                event = std::any::type_name::<E>(),
+                                %error,
+                                "event couldn't be deserialized, the handler was not run"
+                            );
+                            return;
+                        }
+                    };
+
+                    $(
+                        let $ty = match $ty::from_request_parts(&mut parts, &state).await {
+                            Ok(value) => value,
+                            Err(rejection) => {
+                                let response = axum::response::IntoResponse::into_response(rejection);
+                                tracing::warn!(
+                                    extractor = std::any::type_name::<$ty>(),
+                                    status = %response.status(),
+                                    "extractor rejected the request, the handler was not run"
+                                );
+                                return;
+                            }
+                        };
+                    )*
+
+                    (self.func)($($ty,)* event).await
+                })
+            }
+        }
+
+        impl<S, T, Fut, E, $($ty,)*> IntoEventHandler<(($($ty,)*), E), S> for T
+        where
+            T: Fn($($ty,)* E) -> Fut,
+            Fut: Future<Output = ()> + 'static,
+            E: DeserializeOwned,
+            $( $ty: FromRequestParts<S>, )*
+        {
+            fn fields() -> Option<&'static [&'static str]> {
+                helpers::struct_fields::<E>()
+            }
+        }
+    };
+}
+
+#[rustfmt::skip]
+macro_rules! all_the_tuples {
+    ($name:ident) => {
+        $name!();
+        $name!(T1);
+        $name!(T1, T2);
+        $name!(T1, T2, T3);
+        $name!(T1, T2, T3, T4);
+        $name!(T1, T2, T3, T4, T5);
+        $name!(T1, T2, T3, T4, T5, T6);
+        $name!(T1, T2, T3, T4, T5, T6, T7);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+        $name!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+    };
 }
 
+all_the_tuples!(impl_event_handler);
+
 // TODO add tests
 
 #[cfg(test)]
 mod tests {
+    use axum::http::{request::Parts, Request};
     use serde_json::Value;
 
     use crate::context::Context;
 
+    fn parts() -> Parts {
+        let (parts, _) = Request::new(()).into_parts();
+        parts
+    }
+
     #[tokio::test]
     async fn test_can_handle_events() {
         let mut ctx = Context::<()>::new(0, true);
@@ -128,12 +499,237 @@ mod tests {
             state.set("clicked :D".to_string());
         });
 
-        ctx.events.handle("click".to_string(), Value::Null);
+        ctx.events
+            .handle("click".to_string(), Value::Null, &parts(), &());
         ctx.events.join_set.join_next().await.unwrap().unwrap();
 
         assert_eq!("clicked :D", *state.get());
     }
 
+    #[tokio::test]
+    async fn test_event_handler_can_extract_extension() {
+        use axum::Extension;
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(0u32);
+
+        ctx.on_client_event(
+            "click",
+            move |Extension(amount): Extension<u32>, _event: Value| async move {
+                state.set(amount);
+            },
+        );
+
+        let mut parts = parts();
+        parts.extensions.insert(7u32);
+
+        ctx.events
+            .handle("click".to_string(), Value::Null, &parts, &());
+        ctx.events.join_set.join_next().await.unwrap().unwrap();
+
+        assert_eq!(7, *state.get());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_extractor_skips_the_handler() {
+        use axum::Extension;
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(0u32);
+
+        ctx.on_client_event(
+            "click",
+            move |Extension(amount): Extension<u32>, _event: Value| async move {
+                state.set(amount);
+            },
+        );
+
+        // there is no `u32` extension, so the extractor rejects the request
+        ctx.events
+            .handle("click".to_string(), Value::Null, &parts(), &());
+        ctx.events.join_set.join_next().await.unwrap().unwrap();
+
+        assert_eq!(0, *state.get());
+    }
+
+    #[tokio::test]
+    async fn test_sequential_handlers_run_in_order() {
+        use std::{sync::Arc, time::Duration};
+        use tokio::sync::Mutex;
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for (i, delay) in [(1, 30), (2, 0), (3, 10)] {
+            let order = order.clone();
+            ctx.on_client_event_seq("click", move |_event: Value| {
+                let order = order.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    order.lock().await.push(i);
+                }
+            });
+        }
+
+        ctx.events
+            .handle("click".to_string(), Value::Null, &parts(), &());
+        while let Some(res) = ctx.events.join_set.join_next().await {
+            res.unwrap();
+        }
+
+        assert_eq!(vec![1, 2, 3], *order.lock().await);
+    }
+
+    #[tokio::test]
+    async fn test_element_events_are_independent() {
+        use crate::html::{input, Element};
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let first = ctx.use_state(0u32);
+        let second = ctx.use_state(0u32);
+
+        let first_input = ctx.on_element_event(
+            input(Default::default()),
+            "input",
+            move |_event: Value| async move { first.set(1) },
+        );
+        let second_input = ctx.on_element_event(
+            input(Default::default()),
+            "input",
+            move |_event: Value| async move { second.set(2) },
+        );
+
+        let ids = |element: &Element| element.id.unwrap();
+        assert_ne!(ids(&first_input), ids(&second_input));
+
+        ctx.events.handle_element(
+            ids(&second_input),
+            "input".to_string(),
+            Value::Null,
+            &parts(),
+            &(),
+        );
+        ctx.events.join_set.join_next().await.unwrap().unwrap();
+        assert_eq!(0, *first.get());
+        assert_eq!(2, *second.get());
+
+        // document level handlers don't run for element events
+        ctx.events
+            .handle("input".to_string(), Value::Null, &parts(), &());
+        assert!(ctx.events.join_set.is_empty());
+
+        let mut script = String::new();
+        ctx.adapter_script_element("", &Default::default(), "/", None)
+            .render(&mut script);
+        for element in [&first_input, &second_input] {
+            let id = ids(element);
+            assert!(script.contains(&format!(
+                "document.querySelector('[coax-id=\"{id}\"]')?.addEventListener(\"input\""
+            )));
+            assert!(script.contains(&format!("onEvent(\"input\", params, '{id}')")));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_form_submit_deserializes_data() {
+        use crate::html::form;
+
+        #[derive(serde::Deserialize)]
+        struct Signup {
+            name: String,
+            email: String,
+        }
+
+        let mut ctx = Context::<()>::new(0, true);
+        let submitted = ctx.use_state(String::new());
+
+        let form = ctx.on_submit(
+            form((), Default::default()),
+            move |data: Signup| async move {
+                submitted.set(format!("{} <{}>", data.name, data.email));
+            },
+        );
+        let id = form.id.unwrap();
+
+        ctx.events.handle_element(
+            id,
+            "submit".to_string(),
+            serde_json::json!({ "name": "annie", "email": "annie@example.com" }),
+            &parts(),
+            &(),
+        );
+        ctx.events.join_set.join_next().await.unwrap().unwrap();
+        assert_eq!("annie <annie@example.com>", *submitted.get());
+
+        // the form data is sent instead of the fields of the event
+        let mut script = String::new();
+        ctx.adapter_script_element("", &Default::default(), "/", None)
+            .render(&mut script);
+        assert!(script.contains(&format!(
+            "window.Coaxial.onEvent('submit', window.Coaxial.formData(e.currentTarget), '{id}')"
+        )));
+        assert!(!script.contains("addEventListener('submit', params=>"));
+    }
+
+    #[test]
+    fn test_listener_projects_fields_of_all_handlers() {
+        let mut ctx = Context::<()>::new(0, false);
+
+        #[derive(serde::Deserialize)]
+        struct Position {
+            _x: i32,
+            _y: i32,
+        }
+        ctx.on_client_event("click", move |_event: Position| async move {});
+
+        #[derive(serde::Deserialize)]
+        struct Modifiers {
+            _x: i32,
+            _shift_key: bool,
+        }
+        ctx.on_client_event("click", move |_event: Modifiers| async move {});
+
+        let mut script = String::new();
+        ctx.adapter_script_element("", &Default::default(), "/", None)
+            .render(&mut script);
+
+        // a single listener, sending each field once
+        assert_eq!(1, script.matches("addEventListener(\"click\"").count());
+        for field in ["_x", "_y", "_shift_key"] {
+            assert_eq!(
+                1,
+                script.matches(&format!("{field}: params.{field},")).count()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_event_skips_handler() {
+        let mut ctx = Context::<()>::new(0, true);
+        let state = ctx.use_state(0i32);
+
+        #[derive(serde::Deserialize)]
+        struct Click {
+            x: i32,
+        }
+        ctx.on_client_event("click", move |event: Click| async move {
+            state.set(event.x);
+        });
+
+        ctx.events.handle(
+            "click".to_string(),
+            serde_json::json!({ "x": "not a number" }),
+            &parts(),
+            &(),
+        );
+        // the task ends without panicking
+        ctx.events.join_set.join_next().await.unwrap().unwrap();
+        assert_eq!(0, *state.get());
+    }
+
     #[tokio::test]
     async fn test_can_list_events() {
         let mut ctx = Context::<()>::new(0, true);
@@ -159,7 +755,7 @@ mod tests {
         let mut list = ctx
             .events
             .list()
-            .map(|(event, params)| {
+            .map(|(event, _, _, params)| {
                 (event, {
                     let mut params = params.collect::<Vec<_>>();
                     params.sort();
@@ -0,0 +1,51 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use tokio::task::JoinSet;
+
+type FrameHandler<T> = Arc<dyn Fn(T) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Handlers for websocket frames that aren't part of the built-in protocol
+#[derive(Default)]
+pub(crate) struct Frames {
+    binary: Option<FrameHandler<Vec<u8>>>,
+    text: Option<FrameHandler<String>>,
+
+    /// to track the running handlers, and report the ones that panic
+    pub(crate) join_set: JoinSet<()>,
+}
+
+impl Frames {
+    pub(crate) fn set_binary<F, Fut>(&mut self, handler: F)
+    where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.binary = Some(Arc::new(move |bytes| Box::pin(handler(bytes))));
+    }
+
+    pub(crate) fn set_text<F, Fut>(&mut self, handler: F)
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.text = Some(Arc::new(move |text| Box::pin(handler(text))));
+    }
+
+    /// Runs the binary handler, if there is one
+    pub(crate) fn handle_binary(&mut self, bytes: Vec<u8>) {
+        let Some(handler) = &self.binary else {
+            return;
+        };
+
+        self.join_set.spawn(handler(bytes));
+    }
+
+    /// Runs the text handler, if there is one
+    pub(crate) fn handle_text(&mut self, text: String) {
+        let Some(handler) = &self.text else {
+            return;
+        };
+
+        self.join_set.spawn(handler(text));
+    }
+}
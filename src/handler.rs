@@ -1,10 +1,14 @@
-use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::{
+    extract::{FromRequest, FromRequestParts, Request},
+    response::{IntoResponse, Response},
+};
 use std::{future::Future, pin::Pin};
 
 use crate::{context::Context, CoaxialResponse};
 
 pub trait CoaxialHandler<T, S>: Clone + Send + Sized + 'static {
-    type Future: Future<Output = CoaxialResponse<S>> + Send + 'static;
+    /// Resolves to the rejection of the first extractor that failed, if any
+    type Future: Future<Output = Result<CoaxialResponse<S>, Response>> + Send + 'static;
     fn call(self, req: Request, state: S, context: Context<S>) -> Self::Future;
 }
 
@@ -15,10 +19,10 @@ where
     Fut: Future<Output = CoaxialResponse<S>> + Send,
     S: Send + Sync + 'static,
 {
-    type Future = Pin<Box<dyn Future<Output = CoaxialResponse<S>> + Send>>;
+    type Future = Pin<Box<dyn Future<Output = Result<CoaxialResponse<S>, Response>> + Send>>;
 
     fn call(self, _req: Request, _state: S, context: Context<S>) -> Self::Future {
-        Box::pin(async move { self(context).await })
+        Box::pin(async move { Ok(self(context).await) })
     }
 }
 
@@ -35,7 +39,7 @@ macro_rules! impl_handler {
             $( $ty: FromRequestParts<S> + Send, )*
             $last: FromRequest<S, M> + Send,
         {
-            type Future = Pin<Box<dyn Future<Output = CoaxialResponse<S>> + Send>>;
+            type Future = Pin<Box<dyn Future<Output = Result<CoaxialResponse<S>, Response>> + Send>>;
 
             fn call(self, req: Request, state: S, context: Context<S>) -> Self::Future {
                 Box::pin(async move {
@@ -45,7 +49,7 @@ macro_rules! impl_handler {
                     $(
                         let $ty = match $ty::from_request_parts(&mut parts, state).await {
                             Ok(value) => value,
-                            Err(_rejection) => todo!("rejections aren't handled yet"),
+                            Err(rejection) => return Err(rejection.into_response()),
                         };
                     )*
 
@@ -53,10 +57,10 @@ macro_rules! impl_handler {
 
                     let $last = match $last::from_request(req, state).await {
                         Ok(value) => value,
-                        Err(_rejection) => todo!("rejections aren't handled yet"),
+                        Err(rejection) => return Err(rejection.into_response()),
                     };
 
-                    self(context, $($ty,)* $last,).await
+                    Ok(self(context, $($ty,)* $last,).await)
                 })
             }
         }
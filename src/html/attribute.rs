@@ -1,14 +1,14 @@
-use std::fmt::Display;
+use std::{fmt::Display, sync::Arc};
 
 use crate::{
-    closures::Closure,
-    computed::ComputedState,
-    random_id::RandomId,
-    reactive_js::{Content, Reactivity, ReactivityDescriptor, Target},
+    closures::{Closure, ClosureSwitch},
+    computed::{ComputedState, StateGetter},
+    random_id::{RandomId, RANDOM_ID_LENGTH},
+    reactive_js::Content,
     states::State,
 };
 
-#[derive(Default, Debug, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub enum Attribute {
     #[default]
     Empty,
@@ -25,6 +25,25 @@ impl Attribute {
         }
     }
 
+    /// Joins this attribute and `other`, with `separator` between them if both have a value
+    pub(crate) fn merge(self, other: Attribute, separator: &str) -> Attribute {
+        let into_list = |attribute: Attribute| match attribute {
+            Self::Empty => vec![],
+            Self::Value(value) => vec![value],
+            Self::List(list) => list,
+        };
+
+        let mut list = into_list(self);
+        let other = into_list(other);
+
+        if !list.is_empty() && !other.is_empty() {
+            list.push(AttributeValue::Raw(separator.to_string()));
+        }
+        list.extend(other);
+
+        Self::List(list)
+    }
+
     pub(crate) fn optimize(&mut self) {
         match self {
             Self::List(list) => {
@@ -53,6 +72,9 @@ impl Attribute {
             Self::Value(AttributeValue::Text(_)) => {}
             Self::Value(AttributeValue::State(_)) => {}
             Self::Value(AttributeValue::Closure(_)) => {}
+            Self::Value(AttributeValue::ClosureState(_)) => {}
+            Self::Value(AttributeValue::Format(_)) => {}
+            Self::Value(AttributeValue::Bind(..)) => {}
         }
     }
 
@@ -79,12 +101,23 @@ impl Attribute {
                 };
 
                 current.push_str(&next);
+                // the merged value might need to be merged with the next one too
+                continue;
             }
 
             i += 1;
         }
     }
 
+    /// See [`Element::estimated_size`](super::Element::estimated_size)
+    pub(crate) fn estimated_size(&self) -> usize {
+        match self {
+            Self::Empty => 0,
+            Self::Value(value) => value.estimated_size(),
+            Self::List(list) => list.iter().map(AttributeValue::estimated_size).sum(),
+        }
+    }
+
     pub(crate) fn render(&self, output: &mut String) {
         match self {
             Self::Empty => {}
@@ -97,89 +130,118 @@ impl Attribute {
         }
     }
 
-    pub(crate) fn reactivity<'a, 'b>(
-        &'a self,
-        element_id: Option<RandomId>,
-        key: &'a str,
-        reactivity: &'b mut Reactivity<'a>,
-    ) where
-        'a: 'b,
-    {
+    /// Returns whether a closure is one of the values
+    #[cfg(debug_assertions)]
+    pub(crate) fn has_closure(&self) -> bool {
         match self {
-            Self::Value(AttributeValue::State(state_descriptor)) => {
-                let Some(element_id) = element_id else { return };
+            Self::Value(value) => matches!(value, AttributeValue::Closure(_)),
+            Self::List(list) => list
+                .iter()
+                .any(|value| matches!(value, AttributeValue::Closure(_))),
+            Self::Empty => false,
+        }
+    }
 
-                reactivity.add(ReactivityDescriptor {
-                    element_id,
-                    child_node_idx: None,
-                    target: Target::Attribute(key),
+    /// Returns the content used to update this attribute on the client, or None if it's not reactive.
+    ///
+    /// States are added to `state_descriptors` if they aren't in it already,
+    /// and the content references them by their index in it.
+    pub(crate) fn reactive_content<'a>(
+        &'a self,
+        state_descriptors: &mut Vec<&'a StateDescriptor>,
+    ) -> Option<Vec<Content<'a>>> {
+        let mut index = |descriptor: &'a StateDescriptor| {
+            state_descriptors
+                .iter()
+                .position(|s| *s == descriptor)
+                .unwrap_or_else(|| {
+                    state_descriptors.push(descriptor);
+                    state_descriptors.len() - 1
+                })
+        };
+
+        let mut value_content =
+            |value: &'a AttributeValue, content: &mut Vec<Content<'a>>| match value {
+                AttributeValue::Raw(text) => content.push(Content::Text(text.into())),
+                AttributeValue::Text(text) => content.push(Content::Text(
+                    html_escape::encode_script_single_quoted_text(text),
+                )),
+                AttributeValue::State(descriptor) | AttributeValue::Bind(descriptor, _) => {
+                    content.push(Content::Var(index(descriptor)))
+                }
+                AttributeValue::Closure(desc) => {
+                    let mut call = String::new();
+                    desc.call(&mut call);
+                    content.push(Content::Text(
+                        html_escape::encode_script_single_quoted_text(&call)
+                            .into_owned()
+                            .into(),
+                    ));
+                }
+                AttributeValue::ClosureState(descriptor) => {
+                    content.push(Content::Text("window.Coaxial.callClosure(\\'".into()));
+                    content.push(Content::Var(index(descriptor)));
+                    content.push(Content::Text("\\')".into()));
+                }
+                AttributeValue::Format(descriptor) => content.push(Content::Template {
+                    vars: descriptor.states.iter().map(&mut index).collect(),
+                    template: &descriptor.template,
+                }),
+            };
 
-                    state_descriptors: vec![state_descriptor],
-                    content: vec![Content::Var(0)],
-                });
+        match self {
+            Self::Value(AttributeValue::State(state_descriptor)) => {
+                Some(vec![Content::Var(index(state_descriptor))])
             }
-            Self::List(list) => {
-                let Some(element_id) = element_id else { return };
-
-                let state_descriptors = list.iter().filter_map(|c| c.state()).collect::<Vec<_>>();
-
-                let content = list
-                    .iter()
-                    .map(|value| match value {
-                        AttributeValue::Raw(text) => Content::Text(text.into()),
-                        AttributeValue::Text(text) => {
-                            Content::Text(html_escape::encode_script_single_quoted_text(text))
-                        }
-                        AttributeValue::State(descriptor) => Content::Var(
-                            state_descriptors
-                                .iter()
-                                .position(|s| *s == descriptor)
-                                .expect(
-                                "state_descriptors always includes all the states that appear in the group",
-                            ),
-                        ),
-                        AttributeValue::Closure(_) => todo!(),
-                    })
-                    .collect();
-
-                reactivity.add(ReactivityDescriptor {
-                    element_id,
-                    child_node_idx: None,
-                    target: Target::Attribute(key),
-
-                    state_descriptors,
-                    content,
-                });
+            Self::Value(value @ (AttributeValue::ClosureState(_) | AttributeValue::Format(_))) => {
+                let mut content = vec![];
+                value_content(value, &mut content);
+                Some(content)
+            }
+            Self::List(list) if self.is_reactive() => {
+                let mut content = vec![];
+                for value in list {
+                    value_content(value, &mut content);
+                }
+                Some(content)
             }
 
-            Self::Empty => {}
-            Self::Value(AttributeValue::Raw(_)) => {}
-            Self::Value(AttributeValue::Text(_)) => {}
-            Self::Value(AttributeValue::Closure(_)) => {}
+            Self::List(_) => None,
+            Self::Empty => None,
+            Self::Value(AttributeValue::Raw(_)) => None,
+            Self::Value(AttributeValue::Text(_)) => None,
+            Self::Value(AttributeValue::Closure(_)) => None,
+            // bound attributes update the element's property instead, see `Attributes::reactivity`
+            Self::Value(AttributeValue::Bind(..)) => None,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AttributeValue {
     Raw(String),
     Text(String),
     State(StateDescriptor),
+    /// A closure, rendered as the script that calls it, like `window.Coaxial.callClosure('id')`.
+    ///
+    /// It's meant for event handler attributes, like `onclick` or `oninput`, where the browser
+    /// runs it when the event fires. In any other attribute, the script is inserted as text, for
+    /// client-side code to run it.
     Closure(ClosureDescriptor),
+    /// A closure that depends on a state, which holds the id of the closure to call
+    ClosureState(StateDescriptor),
+    /// A value computed from several states, created with [`fmt`]
+    Format(FormatDescriptor),
+    /// A state that is set when the user changes the element, created with [`bind`] or [`bind_on`],
+    /// and the event that sets it
+    Bind(StateDescriptor, InputEvent),
 }
 
 impl AttributeValue {
     fn text_to_raw(&mut self) {
         if let Self::Text(string) = self {
-            *self = Self::Raw(html_escape::encode_text(string).to_string());
-        }
-    }
-
-    fn state(&self) -> Option<&StateDescriptor> {
-        if let Self::State(state) = self {
-            Some(state)
-        } else {
-            None
+            // same escaping as `render`, so merged values can't close the attribute's quotes
+            *self = Self::Raw(html_escape::encode_double_quoted_attribute(string).to_string());
         }
     }
 
@@ -190,79 +252,245 @@ impl AttributeValue {
             Self::Closure(_) => false,
 
             Self::State(_) => true,
+            Self::ClosureState(_) => true,
+            Self::Format(_) => true,
+            Self::Bind(..) => true,
+        }
+    }
+
+    /// See [`Element::estimated_size`](super::Element::estimated_size)
+    fn estimated_size(&self) -> usize {
+        // `window.Coaxial.callClosure('id')`, without arguments
+        const CLOSURE_CALL_LEN: usize = "window.Coaxial.callClosure('')".len() + RANDOM_ID_LENGTH;
+
+        match self {
+            Self::Raw(text) | Self::Text(text) => text.len(),
+            Self::State(desc) | Self::Bind(desc, _) => desc.display.len(),
+            Self::Closure(_) => CLOSURE_CALL_LEN,
+            // the id of the active closure is the display of the state
+            Self::ClosureState(desc) => CLOSURE_CALL_LEN - RANDOM_ID_LENGTH + desc.display.len(),
+            Self::Format(desc) => desc.display.len(),
         }
     }
 
+    fn closure_call(id: &impl Display, output: &mut String) {
+        use std::fmt::Write;
+
+        write!(output, "window.Coaxial.callClosure('{id}')").unwrap();
+    }
+
     pub(crate) fn render(&self, output: &mut String) {
         match self {
             Self::Raw(text) => output.push_str(text),
             Self::Text(text) => output.push_str(&html_escape::encode_double_quoted_attribute(text)),
-            // TODO this needs to include something that updates it
-            // probably outside of it, as generated code
-            Self::State(desc) => {
-                output.push_str(&desc.display);
-
-                // push_strs!(output =>
-                //     &desc.display, "\" coax-change-", &desc.state_id, "=\"", key,
-                // );
-
-                // if key == "value" || key == "checked" {
-                //     push_strs!(output =>
-                //         "\" onchange=\"window.Coaxial.setState(",
-                //         &desc.state_id, ", ['", key, "'])"
-                //     );
-                // }
-            }
+            Self::State(desc) => output.push_str(&desc.display),
             Self::Closure(desc) => {
-                // TODO im pretty sure this is not it now
-
-                // so im not sure how we want to deal with closures
-                // cause we can do the onclick="window.Coaxial.callClosure()" thing,
-                // but that will:
-                // 1) not work for Lists (not sure why a closure would be in a list)
-                // 2) not work if the attribute is something that isn't run as JS
-                // im thinking that someone could do like a (data-function => closure), and then try to run said closure from their own js
-
-                output.push_str("window.Coaxial.callClosure('");
-                desc.closure_id.fmt(output).unwrap();
-                output.push_str("')");
+                // rendered as the script that calls it, which event handler attributes run.
+                // in lists, it's joined with the rest of the script, like `("this.blur();", closure)`.
+                // the arguments are json, which can have quotes
+                let mut call = String::new();
+                desc.call(&mut call);
+                output.push_str(&html_escape::encode_double_quoted_attribute(&call));
+            }
+            Self::ClosureState(desc) => Self::closure_call(&desc.display, output),
+            Self::Format(desc) => {
+                output.push_str(&html_escape::encode_double_quoted_attribute(&desc.display))
+            }
+            Self::Bind(desc, _) => {
+                output.push_str(&html_escape::encode_double_quoted_attribute(&desc.display))
             }
         }
     }
+
+    /// Returns the event that changes the bound attribute `key`, and the script that sets the state.
+    ///
+    /// `event` is the event that sets the state, and `input_type` is the `type` attribute
+    /// of the input, if it has one.
+    pub(crate) fn bind_handler(
+        key: &str,
+        desc: &StateDescriptor,
+        event: InputEvent,
+        input_type: Option<&str>,
+    ) -> (&'static str, String) {
+        debug_assert!(
+            key == "value" || key == "checked",
+            "only value and checked can be bound, not {key}"
+        );
+
+        let name = event.attribute();
+
+        let set = if key == "checked" {
+            format!("window.Coaxial.setState('{}', this.checked)", desc.state_id)
+        } else if input_type == Some("range") && event == InputEvent::Input {
+            format!(
+                "window.Coaxial.setStateThrottled('{}', this.valueAsNumber, {RANGE_THROTTLE_MS})",
+                desc.state_id
+            )
+        } else if input_type == Some("number") && event == InputEvent::Input {
+            // partial values like `-` aren't sent while the user is still typing
+            format!(
+                "window.Coaxial.setStateDebounced('{}', this.value, {NUMBER_DEBOUNCE_MS})",
+                desc.state_id
+            )
+        } else {
+            format!("window.Coaxial.setState('{}', this.value)", desc.state_id)
+        };
+
+        (name, set)
+    }
+}
+
+/// Binds an attribute of an input to `state`, in both directions.
+///
+/// The attribute is rendered with the value of the state, and updated when it changes.
+/// When the user changes the input, the state is set to its new value:
+///
+/// ```ignore
+/// input(attrs!("value" => bind(name)))
+/// input(attrs!("type" => "checkbox", "checked" => bind(accepted)))
+/// ```
+///
+/// The state is set on the `change` event, once the user commits the change, like when the input
+/// loses focus. To set it on every keystroke instead, use [`bind_on`] with [`InputEvent::Input`].
+///
+/// `value` is sent as a string. Strings with numbers, like the ones sent by `type="number"` inputs,
+/// are parsed for states with a number type. `checked` is sent as a boolean, so it needs a `State<bool>`.
+pub fn bind<T>(state: State<T>) -> AttributeValue
+where
+    T: Clone + Send + Sync + 'static,
+{
+    bind_on(state, InputEvent::default())
+}
+
+/// The DOM event that sets a bound state. See [`bind_on`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum InputEvent {
+    /// Sets the state on every change, like each keystroke
+    Input,
+    /// Sets the state when the user commits the change, like when the input loses focus
+    #[default]
+    Change,
+}
+
+impl InputEvent {
+    fn attribute(self) -> &'static str {
+        match self {
+            Self::Input => "oninput",
+            Self::Change => "onchange",
+        }
+    }
+}
+
+/// Like [`bind`], but the state is set on the `on` event.
+///
+/// ```ignore
+/// input(attrs!("value" => bind_on(name, InputEvent::Change)))
+/// input(attrs!("type" => "number", "value" => bind_on(amount, InputEvent::Input)))
+/// ```
+///
+/// With [`InputEvent::Input`], `type="number"` inputs wait until the user stops typing for
+/// 300 milliseconds, so partial values like `-` aren't sent, and `type="range"` inputs send their
+/// value as a number at most once every 50 milliseconds while the slider is dragged.
+pub fn bind_on<T>(state: State<T>, on: InputEvent) -> AttributeValue
+where
+    T: Clone + Send + Sync + 'static,
+{
+    AttributeValue::Bind(state.into(), on)
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// How often bound range inputs send their value while being dragged, in milliseconds
+const RANGE_THROTTLE_MS: u32 = 50;
+/// How long bound number inputs wait for the user to stop typing, in milliseconds
+const NUMBER_DEBOUNCE_MS: u32 = 300;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StateDescriptor {
     pub(crate) display: String,
     pub(crate) state_id: String,
+    /// Arguments for `window.Coaxial.formatNumber`, for states created with `use_state_formatted`
+    pub(crate) format: Option<Arc<str>>,
 }
 impl<T> From<State<T>> for StateDescriptor
 where
-    T: Clone + Display + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
 {
     fn from(value: State<T>) -> Self {
         Self {
-            display: value.get().to_string(),
+            display: value.display(),
             state_id: value.id.to_string(),
+            format: value.format(),
         }
     }
 }
 impl<T> From<ComputedState<T>> for StateDescriptor
 where
-    T: Clone + Display + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
 {
     fn from(value: ComputedState<T>) -> Self {
         value.0.into()
     }
 }
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatDescriptor {
+    /// The value for the current values of the states
+    pub(crate) display: String,
+    pub(crate) states: Vec<StateDescriptor>,
+    /// Body of a JS template literal, where `$0`, `$1`... are the values of the states
+    pub(crate) template: Arc<str>,
+}
+
+/// Returns an attribute value computed from `states`, which is updated when any of them changes.
+///
+/// The page is rendered with the value returned by `render`. On the client, the value comes from
+/// `template`, the body of a JS template literal where `$0`, `$1`... are the values of the states,
+/// in the same order, so both should produce the same value:
+///
+/// ```ignore
+/// attrs!("style" => fmt(
+///     |(w, h)| format!("width:{}px;height:{}px", *w, *h),
+///     (w, h),
+///     "width:${$0}px;height:${$1}px",
+/// ))
+/// ```
+///
+/// The values are strings, like in the rest of the client, and `${}` can hold any JS expression,
+/// like `${$0 * 2}`. The template is added to the script as is, so it must not contain user input.
+pub fn fmt<I, F>(render: F, states: I, template: &str) -> AttributeValue
+where
+    I: StateGetter,
+    F: FnOnce(<I as StateGetter>::Output<'_>) -> String,
+{
+    AttributeValue::Format(FormatDescriptor {
+        display: render(states.get()),
+        states: states.descriptors(),
+        template: template.into(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClosureDescriptor {
     pub(crate) closure_id: RandomId,
+    /// Arguments sent to the closure, as json
+    pub(crate) args: Option<String>,
+}
+impl ClosureDescriptor {
+    /// Writes the JS that calls the closure
+    fn call(&self, output: &mut String) {
+        output.push_str("window.Coaxial.callClosure('");
+        self.closure_id.fmt(output).unwrap();
+        output.push('\'');
+        if let Some(args) = &self.args {
+            output.push_str(", ");
+            output.push_str(args);
+        }
+        output.push(')');
+    }
 }
 impl From<Closure> for ClosureDescriptor {
     fn from(value: Closure) -> Self {
         Self {
             closure_id: value.id,
+            args: None,
         }
     }
 }
@@ -282,9 +510,19 @@ impl From<Closure> for AttributeValue {
         AttributeValue::Closure(value.into())
     }
 }
+impl From<ClosureDescriptor> for AttributeValue {
+    fn from(value: ClosureDescriptor) -> Self {
+        AttributeValue::Closure(value)
+    }
+}
+impl From<ClosureSwitch> for AttributeValue {
+    fn from(value: ClosureSwitch) -> Self {
+        AttributeValue::ClosureState(value.active.into())
+    }
+}
 impl<T> From<State<T>> for AttributeValue
 where
-    T: Clone + Display + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
 {
     fn from(value: State<T>) -> Self {
         AttributeValue::State(value.into())
@@ -292,7 +530,7 @@ where
 }
 impl<T> From<ComputedState<T>> for AttributeValue
 where
-    T: Clone + Display + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
 {
     fn from(value: ComputedState<T>) -> Self {
         AttributeValue::State(value.into())
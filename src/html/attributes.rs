@@ -1,12 +1,28 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
-use crate::{random_id::RandomId, reactive_js::Reactivity};
+use crate::{
+    random_id::RandomId,
+    reactive_js::{Content, Reactivity, ReactivityDescriptor, Target},
+    states::State,
+};
 
-use super::Attribute;
+use super::{Attribute, AttributeValue, Element, StateDescriptor};
 
-#[derive(Default, Debug, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Attributes {
     attributes: HashMap<String, Attribute>,
+    method_toggle: Option<MethodToggle>,
+    /// Classes that are added while their state is true, and removed while it's false
+    class_toggles: Vec<(StateDescriptor, String)>,
+}
+
+/// Calls a method of the element when a boolean state changes,
+/// `then` when it becomes true and `otherwise` when it becomes false.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MethodToggle {
+    pub(crate) state: StateDescriptor,
+    pub(crate) then: &'static str,
+    pub(crate) otherwise: &'static str,
 }
 
 impl Attributes {
@@ -14,25 +30,102 @@ impl Attributes {
         self.attributes.is_empty()
     }
 
+    /// Inserts an attribute.
+    ///
+    /// Inserting `class` or `style` more than once merges the values, separated by a space and
+    /// a `;` respectively. Inserting any other key more than once panics in debug builds.
+    ///
+    /// Attributes bound with [`bind`](super::bind) are rendered with the handler that sets their
+    /// state too, after any handler for the same event.
     pub fn insert(&mut self, key: impl ToString, attribute: impl Into<Attribute>) {
         let key = key.to_string();
+        let mut attribute = attribute.into();
 
-        // HTML doesn't allow repeated attribute keys.
-        // Browsers take the first one and ignore all the rest, so we'll throw an error.
-        // https://stackoverflow.com/a/43859478
-        debug_assert!(
-            !self.attributes.contains_key(&key),
-            "trying to override attribute {}",
-            key
-        );
+        #[cfg(debug_assertions)]
+        if !key.starts_with("on") && attribute.has_closure() {
+            tracing::warn!(
+                attribute = key,
+                "a closure was inserted in an attribute that isn't an event handler, so it won't be called by the browser. it's inserted as the script that calls it"
+            );
+        }
+
+        let separator = match key.as_str() {
+            "class" => Some(" "),
+            "style" => Some(";"),
+            _ => None,
+        };
+
+        match (self.attributes.remove(&key), separator) {
+            (Some(existing), Some(separator)) => attribute = existing.merge(attribute, separator),
+            // HTML doesn't allow repeated attribute keys.
+            // Browsers take the first one and ignore all the rest, so we'll throw an error.
+            // https://stackoverflow.com/a/43859478
+            (Some(_), None) => debug_assert!(false, "trying to override attribute {}", key),
+            (None, _) => {}
+        }
+
+        self.attributes.insert(key, attribute);
+    }
 
-        // TODO we can consider merging class and styles, but idk
+    /// Inserts `value` serialized as JSON in the `data-{name}` attribute, for client-side code
+    /// to read with `JSON.parse(el.dataset.name)`.
+    ///
+    /// Panics if `value` can't be serialized, like maps with keys that aren't strings.
+    pub fn insert_data(&mut self, name: &str, value: &impl serde::Serialize) {
+        let json = serde_json::to_string(value).expect("data attributes are serialized to JSON");
+        self.insert(format!("data-{name}"), json);
+    }
+
+    /// Adds `class` to the element while `state` is true, and removes it while it's false,
+    /// leaving the rest of its classes alone:
+    ///
+    /// ```ignore
+    /// button("save", attrs!("class" => "button").with_class_toggle(saving, "loading"))
+    /// ```
+    ///
+    /// Setting the whole `class` attribute from a state, instead of toggling a class, replaces the
+    /// classes toggled by this too.
+    pub fn with_class_toggle(mut self, state: State<bool>, class: &str) -> Self {
+        let state = StateDescriptor::from(state);
+        if state.display == "true" {
+            self.insert("class", class);
+        }
+        self.class_toggles.push((state, class.to_string()));
+        self
+    }
+
+    /// Inserts an attribute, replacing the one with the same key if there is one
+    pub(crate) fn set(&mut self, key: impl ToString, attribute: impl Into<Attribute>) {
+        self.attributes.insert(key.to_string(), attribute.into());
+    }
+
+    /// Inserts all the attributes in `other`, replacing the ones with the same key
+    pub(crate) fn override_with(&mut self, other: Attributes) {
+        self.attributes.extend(other.attributes);
+        if other.method_toggle.is_some() {
+            self.method_toggle = other.method_toggle;
+        }
+        self.class_toggles.extend(other.class_toggles);
+    }
+
+    pub(crate) fn set_method_toggle(&mut self, method_toggle: MethodToggle) {
+        self.method_toggle = Some(method_toggle);
+    }
 
-        self.attributes.insert(key, attribute.into());
+    /// Creates an element with no content with these attributes
+    pub(crate) fn into_element(self, name: &str) -> Element {
+        Element {
+            id: None,
+            name: name.to_string(),
+            content: Default::default(),
+            attributes: self,
+        }
     }
 
     pub(crate) fn is_reactive(&self) -> bool {
-        self.attributes.values().any(Attribute::is_reactive)
+        self.method_toggle.is_some()
+            || !self.class_toggles.is_empty()
+            || self.attributes.values().any(Attribute::is_reactive)
     }
 
     pub(crate) fn optimize(&mut self) {
@@ -41,7 +134,10 @@ impl Attributes {
         }
     }
 
-    pub(crate) fn render(&self, output: &mut String) {
+    /// Iterates over the attributes.
+    ///
+    /// In debug builds, attributes are sorted by key so output is deterministic.
+    fn iter(&self) -> impl Iterator<Item = (&String, &Attribute)> {
         #[cfg(debug_assertions)]
         let iter = {
             let mut v = Vec::from_iter(self.attributes.iter());
@@ -51,7 +147,74 @@ impl Attributes {
         #[cfg(not(debug_assertions))]
         let iter = self.attributes.iter();
 
-        for (i, (key, attr)) in iter.enumerate() {
+        iter
+    }
+
+    /// Iterates over the attributes that are rendered
+    fn rendered(&self) -> impl Iterator<Item = (&String, &Attribute)> {
+        // `checked` is a boolean attribute, so any value checks the input
+        self.iter().filter(|(key, attr)| match attr {
+            Attribute::Value(AttributeValue::Bind(desc, _)) if *key == "checked" => {
+                desc.display == "true"
+            }
+            _ => true,
+        })
+    }
+
+    /// Returns these attributes with the handlers of the bound attributes added.
+    ///
+    /// They're added when rendering, since they depend on `type`, which can be inserted after them
+    fn with_bind_handlers(&self) -> Cow<'_, Self> {
+        let bound = self
+            .iter()
+            .filter_map(|(key, attr)| match attr {
+                Attribute::Value(AttributeValue::Bind(desc, on)) => Some((key, desc, *on)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if bound.is_empty() {
+            return Cow::Borrowed(self);
+        }
+
+        let input_type = match self.attributes.get("type") {
+            Some(Attribute::Value(AttributeValue::Text(t) | AttributeValue::Raw(t))) => {
+                Some(t.as_str())
+            }
+            _ => None,
+        };
+
+        let mut attributes = self.clone();
+        for (key, desc, on) in bound {
+            let (event, handler) = AttributeValue::bind_handler(key, desc, on, input_type);
+            let mut handler = Attribute::from(handler);
+            if let Some(existing) = attributes.attributes.remove(event) {
+                handler = existing.merge(handler, ";");
+            }
+            attributes.attributes.insert(event.to_string(), handler);
+        }
+
+        Cow::Owned(attributes)
+    }
+
+    /// See [`Element::estimated_size`]
+    pub(crate) fn estimated_size(&self) -> usize {
+        self.with_bind_handlers()
+            .rendered()
+            .map(|(key, attr)| match attr {
+                // ` key`
+                Attribute::Empty => 1 + key.len(),
+                // ` key="value"`
+                attr => 4 + key.len() + attr.estimated_size(),
+            })
+            .sum()
+    }
+
+    pub(crate) fn render(&self, output: &mut String) {
+        for (i, (key, attr)) in self.with_bind_handlers().rendered().enumerate() {
+            if i != 0 {
+                output.push(' ');
+            }
+
             output.push_str(key);
 
             if matches!(attr, Attribute::Empty) {
@@ -61,10 +224,6 @@ impl Attributes {
             output.push_str("=\"");
             attr.render(output);
             output.push('"');
-
-            if i + 1 != self.attributes.len() {
-                output.push(' ');
-            }
         }
     }
 
@@ -75,14 +234,132 @@ impl Attributes {
     ) where
         'a: 'b,
     {
-        for (key, attr) in &self.attributes {
-            attr.reactivity(element_id, key, reactivity);
+        let Some(element_id) = element_id else { return };
+
+        if let Some(toggle) = &self.method_toggle {
+            reactivity.add(ReactivityDescriptor {
+                element_id,
+                child_node_idx: None,
+                target: Target::Method {
+                    name: Content::If {
+                        var: 0,
+                        then: Box::new(Content::Text(toggle.then.into())),
+                        otherwise: Box::new(Content::Text(toggle.otherwise.into())),
+                    },
+                    args: vec![],
+                },
+                state_descriptors: vec![&toggle.state],
+                content: vec![],
+            });
+
+            // the method is called on load too, since the state might start as true
+            if toggle.state.display == "true" {
+                reactivity.refresh_on_load(&toggle.state.state_id);
+            }
+        }
+
+        for (state, class) in &self.class_toggles {
+            reactivity.add(ReactivityDescriptor {
+                element_id,
+                child_node_idx: None,
+                target: Target::ClassToggle(class),
+                state_descriptors: vec![state],
+                content: vec![Content::Var(0)],
+            });
+        }
+
+        // the attribute only sets the initial value, so bound attributes set the property instead.
+        // it's only set when it's different, so the cursor of the input that set it doesn't move
+        for (key, attr) in self.iter() {
+            if let Attribute::Value(AttributeValue::Bind(desc, _)) = attr {
+                reactivity.add(ReactivityDescriptor {
+                    element_id,
+                    child_node_idx: None,
+                    target: Target::Property(key),
+                    state_descriptors: vec![desc],
+                    content: vec![Content::Var(0)],
+                });
+            }
         }
+
+        // all the reactive attributes of an element are updated by a single listener
+        let mut state_descriptors = Vec::new();
+        let mut attributes = self
+            .iter()
+            .filter_map(|(key, attr)| {
+                attr.reactive_content(&mut state_descriptors)
+                    .map(|content| (key.as_str(), content))
+            })
+            .collect::<Vec<_>>();
+
+        let (target, content) = match attributes.len() {
+            0 => return,
+            1 => {
+                let (key, content) = attributes.remove(0);
+                (Target::Attribute(key), content)
+            }
+            _ => (Target::Attributes(attributes), vec![]),
+        };
+
+        reactivity.add(ReactivityDescriptor {
+            element_id,
+            child_node_idx: None,
+            target,
+            state_descriptors,
+            content,
+        });
     }
 }
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn test_class_toggle_keeps_other_classes() {
+        use crate::{context::Context, html::button, reactive_js::element_reactivity_script};
+
+        let mut ctx = Context::<()>::new(0, false);
+        let active = ctx.use_state(true);
+
+        let mut el = button(
+            "save",
+            attrs!("class" => "button").with_class_toggle(active, "active"),
+        );
+        let script = element_reactivity_script(&mut el, 0);
+
+        let mut output = String::new();
+        el.render(&mut output);
+        assert!(output.starts_with("<button class=\"button active\""));
+        assert!(script.contains(&format!("onStateChange(['{}'], (v0) =>", active.id)));
+        assert!(script.contains("el.classList.toggle('active', v0 === 'true')"));
+        assert!(!script.contains("'class'"));
+    }
+
+    #[test]
+    fn test_closure_in_event_attribute() {
+        use crate::{context::Context, html::input, reactive_js::element_reactivity_script};
+
+        let mut ctx = Context::<()>::new(0, false);
+        let search = ctx.use_closure(|| async {});
+
+        let mut el = input(attrs!(
+            "oninput" => search,
+            "onchange" => ("this.blur();", search),
+        ));
+        let script = element_reactivity_script(&mut el, 0);
+
+        let mut output = String::new();
+        el.render(&mut output);
+        assert_eq!(
+            format!(
+                "<input onchange=\"this.blur();window.Coaxial.callClosure('{id}')\" oninput=\"window.Coaxial.callClosure('{id}')\" />",
+                id = search.id
+            ),
+            output
+        );
+        // closures don't change, so there's nothing to update
+        assert!(script.is_empty());
+    }
+
     #[test]
     fn test_can_render_one_attribute() {
         let attrs = attrs!(
@@ -122,4 +399,289 @@ mod tests {
         // has a space between the two attributes, but not at the end
         assert_eq!("data-something=\"wow\" onclick=\"hey\"", output);
     }
+
+    #[test]
+    fn test_reactive_attributes_share_a_listener() {
+        use crate::{context::Context, html::div, reactive_js::element_reactivity_script};
+
+        let mut ctx = Context::<()>::new(0, false);
+
+        let states = (0..10).map(|i| ctx.use_state(i)).collect::<Vec<_>>();
+
+        let mut attrs = super::Attributes::default();
+        for (i, state) in states.iter().enumerate() {
+            attrs.insert(format!("data-attr-{i}"), *state);
+        }
+        let mut el = div("", attrs);
+
+        let script = element_reactivity_script(&mut el, 0);
+
+        let id = el.id.unwrap();
+        let rendered = (0..10)
+            .map(|i| format!(" data-attr-{i}=\"{i}\""))
+            .collect::<String>();
+        assert_eq!(
+            format!("<div{rendered} coax-id=\"{id}\"></div>"),
+            el.render_to_string()
+        );
+
+        // a single listener, for all the states, sets every attribute
+        let ids = states
+            .iter()
+            .map(|state| state.id.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(1, script.matches("window.Coaxial.onStateChange").count());
+        assert!(script.contains(&format!(
+            "window.Coaxial.onStateChange(['{}'], (v0,v1,v2,v3,v4,v5,v6,v7,v8,v9) =>",
+            ids.join("','")
+        )));
+        for i in 0..10 {
+            assert!(script.contains(&format!(
+                "el[v{i} === '' ? 'removeAttribute' : 'setAttribute']('data-attr-{i}', v{i})"
+            )));
+        }
+    }
+
+    #[test]
+    fn test_can_render_empty_attribute_before_others() {
+        let attrs = attrs!(
+            "defer" => (),
+            "type" => "module",
+        );
+
+        let mut output = String::new();
+        attrs.render(&mut output);
+
+        assert_eq!("defer type=\"module\"", output);
+    }
+
+    #[test]
+    fn test_class_is_merged() {
+        let mut attrs = attrs!("class" => "a");
+        attrs.insert("class", "b");
+
+        let mut output = String::new();
+        attrs.render(&mut output);
+        assert_eq!("class=\"a b\"", output);
+    }
+
+    #[test]
+    fn test_reactive_class_is_merged() {
+        use crate::{context::Context, html::p, reactive_js::element_reactivity_script};
+
+        let mut ctx = Context::<()>::new(0, false);
+        let modifier = ctx.use_state("active".to_string());
+
+        let mut attrs = attrs!("class" => "base", "style" => "color: red");
+        attrs.insert("class", modifier);
+        attrs.insert("style", "margin: 0");
+
+        let mut el = p("hi", attrs);
+        let script = element_reactivity_script(&mut el, 0);
+
+        let mut output = String::new();
+        el.render(&mut output);
+        assert!(output.starts_with("<p class=\"base active\" style=\"color: red;margin: 0\""));
+        assert!(script.contains("(v => el[v === '' ? 'removeAttribute' : 'setAttribute']('class', v))(['base ',v0].join(''))"));
+    }
+
+    #[test]
+    fn test_attribute_formatted_from_two_states() {
+        use crate::{
+            context::Context,
+            html::{div, fmt},
+            reactive_js::element_reactivity_script,
+        };
+
+        let mut ctx = Context::<()>::new(0, false);
+        let width = ctx.use_state(10u32);
+        let height = ctx.use_state(20u32);
+
+        let mut el = div(
+            "",
+            attrs!("style" => fmt(
+                |(w, h)| format!("width:{}px;height:{}px", *w, *h),
+                (width, height),
+                "width:${$0}px;height:${$1}px",
+            )),
+        );
+        let script = element_reactivity_script(&mut el, 0);
+
+        let mut output = String::new();
+        el.render(&mut output);
+        assert!(output.starts_with("<div style=\"width:10px;height:20px\""));
+        assert!(script.contains(&format!(
+            "window.Coaxial.onStateChange(['{}','{}'], (v0,v1) =>",
+            width.id, height.id
+        )));
+        assert!(script.contains(
+            "(v => el[v === '' ? 'removeAttribute' : 'setAttribute']('style', v))((($0,$1) => `width:${$0}px;height:${$1}px`)(v0,v1))"
+        ));
+    }
+
+    #[test]
+    fn test_bound_value_renders_value_and_handler() {
+        use crate::{
+            context::Context,
+            html::{bind, input},
+            reactive_js::element_reactivity_script,
+        };
+
+        let mut ctx = Context::<()>::new(0, false);
+        let name = ctx.use_state("Ann \"A\"".to_string());
+        let amount = ctx.use_state(3u32);
+        let accepted = ctx.use_state(false);
+
+        let mut el = input(attrs!("type" => "text", "value" => bind(name)));
+        let script = element_reactivity_script(&mut el, 0);
+        let mut output = String::new();
+        el.render(&mut output);
+        assert!(output.starts_with(&format!(
+            "<input onchange=\"window.Coaxial.setState('{}', this.value)\" type=\"text\" value=\"Ann &quot;A&quot;\"",
+            name.id
+        )));
+        assert!(script.contains("if (el['value'] !== v0) el['value'] = v0;"));
+        assert!(!script.contains("setAttribute"));
+
+        // the handler goes after the existing one
+        let mut attrs = attrs!("type" => "number", "onchange" => "validate(this)");
+        attrs.insert("value", bind(amount));
+        let mut output = String::new();
+        attrs.render(&mut output);
+        assert_eq!(
+            format!(
+                "onchange=\"validate(this);window.Coaxial.setState('{}', this.value)\" type=\"number\" value=\"3\"",
+                amount.id
+            ),
+            output
+        );
+
+        let mut el = input(attrs!("type" => "checkbox", "checked" => bind(accepted)));
+        let script = element_reactivity_script(&mut el, 0);
+        let mut output = String::new();
+        el.render(&mut output);
+        // unchecked, so the attribute isn't rendered
+        assert!(output.starts_with(&format!(
+            "<input onchange=\"window.Coaxial.setState('{}', this.checked)\" type=\"checkbox\" coax-id=",
+            accepted.id
+        )));
+        assert!(script.contains("el.checked = v0 === 'true';"));
+
+        accepted.set(true);
+        let mut output = String::new();
+        attrs!("checked" => bind(accepted)).render(&mut output);
+        assert!(output.starts_with("checked=\"true\""));
+    }
+
+    #[test]
+    fn test_bound_value_uses_requested_event() {
+        use crate::{
+            context::Context,
+            html::{bind, bind_on, InputEvent},
+        };
+
+        let mut ctx = Context::<()>::new(0, false);
+        let name = ctx.use_state(String::new());
+        let amount = ctx.use_state(3i32);
+
+        let mut output = String::new();
+        attrs!("value" => bind_on(name, InputEvent::Change)).render(&mut output);
+        assert!(output.starts_with(&format!(
+            "onchange=\"window.Coaxial.setState('{}', this.value)\"",
+            name.id
+        )));
+
+        let mut output = String::new();
+        attrs!("value" => bind_on(name, InputEvent::Input)).render(&mut output);
+        assert!(output.starts_with("oninput="));
+
+        // number inputs don't send partial values while the user is typing
+        let mut output = String::new();
+        attrs!("type" => "number", "value" => bind_on(amount, InputEvent::Input))
+            .render(&mut output);
+        assert!(output.starts_with(&format!(
+            "oninput=\"window.Coaxial.setStateDebounced('{}', this.value, 300)\"",
+            amount.id
+        )));
+
+        let mut output = String::new();
+        attrs!("type" => "number", "value" => bind_on(amount, InputEvent::Change))
+            .render(&mut output);
+        assert!(output.starts_with(&format!(
+            "onchange=\"window.Coaxial.setState('{}', this.value)\"",
+            amount.id
+        )));
+
+        // `type` can be inserted after the bound attribute
+        let mut output = String::new();
+        attrs!("value" => bind_on(amount, InputEvent::Input), "type" => "range")
+            .render(&mut output);
+        assert!(output.starts_with(&format!(
+            "oninput=\"window.Coaxial.setStateThrottled('{}', this.valueAsNumber, 50)\"",
+            amount.id
+        )));
+
+        // bind sets it on change too
+        let mut output = String::new();
+        attrs!("value" => bind(name)).render(&mut output);
+        assert!(output.starts_with("onchange="));
+    }
+
+    #[test]
+    #[should_panic(expected = "trying to override attribute id")]
+    fn test_other_attributes_are_not_merged() {
+        let mut attrs = attrs!("id" => "a");
+        attrs.insert("id", "b");
+    }
+
+    #[test]
+    fn test_data_attribute_is_escaped_json() {
+        #[derive(serde::Serialize)]
+        struct Chart {
+            title: &'static str,
+            points: Vec<u32>,
+        }
+
+        let mut attrs = super::Attributes::default();
+        attrs.insert_data(
+            "config",
+            &Chart {
+                title: "\"a\" & <b>",
+                points: vec![1, 2],
+            },
+        );
+
+        let mut output = String::new();
+        attrs.render(&mut output);
+        assert_eq!(
+            "data-config=\"{&quot;title&quot;:&quot;\\&quot;a\\&quot; &amp; &lt;b&gt;&quot;,&quot;points&quot;:[1,2]}\"",
+            output
+        );
+    }
+
+    #[test]
+    fn test_adjacent_strings_are_merged() {
+        use crate::html::{div, p, Attribute, AttributeValue, Content, ContentValue};
+
+        let mut el = div(
+            p("x", attrs!("title" => ("a \"quote\"", " and ", "<tag>"))),
+            Default::default(),
+        );
+        el.optimize();
+
+        // attributes of nested elements are optimized too
+        let Content::Value(ContentValue::Element(child)) = &el.content else {
+            panic!("expected a single element, got {:?}", el.content);
+        };
+        assert_eq!(
+            Attribute::Value(AttributeValue::Raw(
+                "a &quot;quote&quot; and &lt;tag&gt;".to_string()
+            )),
+            child.attributes.attributes["title"]
+        );
+        assert_eq!(
+            "<div><p title=\"a &quot;quote&quot; and &lt;tag&gt;\">x</p></div>",
+            el.render_to_string()
+        );
+    }
 }
@@ -1,25 +1,110 @@
-use std::fmt::Display;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
 
 use crate::{
     computed::ComputedState,
-    random_id::RandomId,
+    random_id::{IdPath, RandomId},
     reactive_js::{Content as ReactiveContent, Reactivity, ReactivityDescriptor, Target},
     states::State,
 };
 
-use super::{attribute::StateDescriptor, element::Element};
-use rand::Rng;
+use super::{
+    attribute::StateDescriptor,
+    element::{Element, ID_ATTRIBUTE_LEN},
+};
 
-#[derive(Default, Debug, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub enum Content {
     #[default]
     Empty,
     Value(ContentValue),
     List(Vec<ContentValue>),
+    /// Content that switches between two branches depending on a boolean state.
+    ///
+    /// Created with `Context::use_bool_content`.
+    If(Box<IfContent>),
+    /// Content that shows a placeholder until the live connection sends the value of a state.
+    ///
+    /// Created with `Context::use_skeleton`.
+    Skeleton(Box<SkeletonContent>),
+    /// An element that is only in the page while a boolean state is true.
+    ///
+    /// Created with `Context::use_show`.
+    Show(Box<ShowContent>),
+    /// HTML rendered on the server from an element computed from states, which replaces the
+    /// children of the element when it's recomputed.
+    ///
+    /// Created with `Context::use_computed_element`.
+    Html(Box<HtmlContent>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfContent {
+    pub(crate) condition: StateDescriptor,
+    pub(crate) then: Content,
+    pub(crate) otherwise: Content,
+}
+
+impl IfContent {
+    /// Returns the branch that matches the current value of the condition
+    fn current(&self) -> &Content {
+        if self.condition.display == "true" {
+            &self.then
+        } else {
+            &self.otherwise
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkeletonContent {
+    pub(crate) state: StateDescriptor,
+    pub(crate) placeholder: Content,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShowContent {
+    pub(crate) condition: StateDescriptor,
+    pub(crate) element: Element,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlContent {
+    /// Computed state holding the HTML of the element and the script that makes it reactive, as JSON
+    pub(crate) state: StateDescriptor,
+    /// The element as it was computed when the page was rendered, with its ids already given
+    pub(crate) element: Element,
+    /// Tag of the listeners of the states nested in the element,
+    /// which are removed when the element is replaced
+    pub(crate) scope: String,
+}
+
+impl ShowContent {
+    fn render(&self, output: &mut String) {
+        if self.condition.display == "true" {
+            self.element.render(output);
+            return;
+        }
+
+        // the placeholder keeps the element's place in the parent, and can be found by the same id
+        output.push_str("<template");
+        if let Some(id) = &self.element.id {
+            output.push_str(" coax-id=\"");
+            id.fmt(output).unwrap();
+            output.push('"');
+        }
+        output.push_str("></template>");
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ContentValue {
+    /// HTML that is rendered as is, without escaping.
+    ///
+    /// Control characters other than tab, line feed, form feed and carriage return are not allowed
+    /// in HTML, so they are stripped when rendering.
     Raw(String),
     Text(String),
     Element(Box<Element>),
@@ -43,6 +128,16 @@ impl ContentValue {
         }
     }
 
+    /// Returns whether this renders to nothing, like the values created from `None`
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Raw(text) | Self::Text(text) => text.is_empty(),
+
+            Self::Element(_) => false,
+            Self::State(_) => false,
+        }
+    }
+
     fn is_text(&self) -> bool {
         match self {
             Self::State(_) => true,
@@ -61,9 +156,20 @@ impl ContentValue {
         }
     }
 
+    /// See [`Element::estimated_size`]
+    pub(crate) fn estimated_size(&self) -> usize {
+        match self {
+            // control characters are skipped, so raw HTML could be shorter
+            Self::Raw(_) => 0,
+            Self::Text(text) => text.len(),
+            Self::Element(child) => child.estimated_size(),
+            Self::State(desc) => desc.display.len(),
+        }
+    }
+
     pub(crate) fn render(&self, output: &mut String) {
         match self {
-            Self::Raw(raw) => output.push_str(raw),
+            Self::Raw(raw) => push_without_controls(raw, output),
             Self::Text(escaped) => output.push_str(&html_escape::encode_text(escaped)),
             Self::Element(child) => child.render(output),
             Self::State(desc) => output.push_str(&desc.display),
@@ -71,14 +177,65 @@ impl ContentValue {
     }
 }
 
+/// Pushes `raw`, skipping the control characters that aren't allowed in HTML
+fn push_without_controls(raw: &str, output: &mut String) {
+    let disallowed = |c: char| c.is_control() && !matches!(c, '\t' | '\n' | '\x0C' | '\r');
+
+    if !raw.contains(disallowed) {
+        output.push_str(raw);
+        return;
+    }
+
+    output.extend(raw.chars().filter(|c| !disallowed(*c)));
+}
+
 impl Content {
+    /// Returns `content` if `condition` is true, and nothing if it isn't.
+    ///
+    /// This is decided when building the page, so it's not reactive.
+    /// Use [`Context::use_show`](crate::context::Context::use_show) for content that depends on a state.
+    pub fn when(condition: bool, content: impl Into<Content>) -> Content {
+        if condition {
+            content.into()
+        } else {
+            Content::Empty
+        }
+    }
+
+    /// Returns `then` if `condition` is true, and `otherwise` if it isn't.
+    ///
+    /// This is decided when building the page, so it's not reactive.
+    /// Use [`Context::use_bool_content`](crate::context::Context::use_bool_content) for content
+    /// that depends on a state.
+    pub fn when_else(
+        condition: bool,
+        then: impl Into<Content>,
+        otherwise: impl Into<Content>,
+    ) -> Content {
+        if condition {
+            then.into()
+        } else {
+            otherwise.into()
+        }
+    }
+
     /// Turns this Content into it's canonical form
     ///
     /// For example, a `Content::List` with an empty list will be transformed into a `Content::Empty`.
     pub(crate) fn optimize(&mut self) {
         match self {
-            Content::Value(ContentValue::Element(element)) => element.content.optimize(),
+            Content::Value(ContentValue::Element(element)) => {
+                element.optimize();
+                // like in lists, the fragment's content takes its place
+                if element.is_fragment() {
+                    *self = std::mem::take(&mut element.content);
+                }
+            }
+            Content::Value(value) if value.is_empty() => *self = Content::Empty,
             Content::List(list) => {
+                Self::flatten_fragments(list);
+                list.retain(|item| !item.is_empty());
+
                 match list.len() {
                     0 => {
                         // if the list is empty, change it for an empty
@@ -99,6 +256,15 @@ impl Content {
                 }
             }
 
+            Content::If(if_content) => {
+                if_content.then.optimize();
+                if_content.otherwise.optimize();
+            }
+            Content::Skeleton(skeleton) => skeleton.placeholder.optimize(),
+            // already optimized when it was computed
+            Content::Html(_) => {}
+            Content::Show(show) => show.element.optimize(),
+
             Content::Empty => {}
             Content::Value(ContentValue::Raw(_)) => {}
             Content::Value(ContentValue::Text(_)) => {}
@@ -126,22 +292,89 @@ impl Content {
                 };
 
                 current.push_str(&next);
+                // the merged value might need to be merged with the next one too
+                continue;
             }
 
             i += 1;
         }
     }
 
-    pub(crate) fn give_ids<RNG: Rng>(&mut self, rng: &mut RNG) {
+    /// Optimizes the elements in `list`, and replaces fragments with their content.
+    ///
+    /// Fragments render their content in place, where the browser merges its text with the text
+    /// around it, so text groups have to include it for their index in `childNodes` to be right.
+    fn flatten_fragments(list: &mut Vec<ContentValue>) {
+        let mut i = 0;
+        while i < list.len() {
+            let ContentValue::Element(element) = &mut list[i] else {
+                i += 1;
+                continue;
+            };
+
+            element.optimize();
+            if !element.is_fragment() {
+                i += 1;
+                continue;
+            }
+
+            let values = match std::mem::take(&mut element.content) {
+                Content::Empty => vec![],
+                Content::Value(value) => vec![value],
+                Content::List(list) => list,
+                // conditional content is replaced as a whole, so it stays in the fragment
+                content => {
+                    element.content = content;
+                    i += 1;
+                    continue;
+                }
+            };
+
+            // the content was optimized, so there are no fragments left in it
+            let count = values.len();
+            list.splice(i..=i, values);
+            i += count;
+        }
+    }
+
+    /// Gives ids to the reactive elements in this content, where `path` is the path of the
+    /// element that contains it
+    pub(crate) fn give_ids(&mut self, path: IdPath, used: &mut HashSet<RandomId>) {
         match self {
             Content::List(list) => {
+                // how many elements with each tag came before
+                let mut occurrences = HashMap::<String, usize>::new();
                 for item in list {
                     if let ContentValue::Element(element) = item {
-                        element.give_ids(rng);
+                        let occurrence = occurrences.entry(element.name.clone()).or_default();
+                        let child = path.child(&element.name, *occurrence);
+                        *occurrence += 1;
+                        element.give_ids(child, used);
                     }
                 }
             }
-            Content::Value(ContentValue::Element(element)) => element.give_ids(rng),
+            Content::Value(ContentValue::Element(element)) => {
+                let child = path.child(&element.name, 0);
+                element.give_ids(child, used);
+            }
+            Content::If(if_content) => {
+                if_content.then.give_ids(path.branch("then"), used);
+                if_content
+                    .otherwise
+                    .give_ids(path.branch("otherwise"), used);
+            }
+            // the placeholder is replaced as a whole, so it doesn't need ids
+            Content::Skeleton(_) => {}
+            // the element is given ids when it's computed, so the recomputed ones match
+            Content::Html(_) => {}
+            Content::Show(show) => {
+                let path = path.branch("show").child(&show.element.name, 0);
+                show.element.give_ids(path, used);
+                // the element is always shown and hidden by id, even if it's not reactive
+                if show.element.id.is_none() {
+                    show.element.id = Some(path.unique_id(used));
+                }
+            }
 
             Content::Empty => {}
             Content::Value(ContentValue::Raw(_)) => {}
@@ -155,6 +388,10 @@ impl Content {
             Content::Empty => false,
             Content::Value(value) => value.is_reactive(),
             Content::List(list) => list.iter().any(ContentValue::is_reactive),
+            Content::If(_) => true,
+            Content::Skeleton(_) => true,
+            Content::Html(_) => true,
+            Content::Show(_) => true,
         }
     }
 
@@ -186,6 +423,10 @@ impl Content {
 
                     let state_descriptors =
                         group.iter().filter_map(|c| c.state()).collect::<Vec<_>>();
+                    // static text never changes, and a listener without states isn't valid
+                    if state_descriptors.is_empty() {
+                        return;
+                    }
                     let content = group
                         .iter()
                         .map(|content| match content {
@@ -250,7 +491,94 @@ impl Content {
                     content: vec![ReactiveContent::Var(0)],
                 });
             }
+            Content::Skeleton(skeleton) => {
+                let Some(id) = element_id else { return };
+
+                // setting the text content replaces the whole placeholder, be it text or elements
+                reactivity.add(ReactivityDescriptor {
+                    element_id: id,
+                    child_node_idx: None,
+                    target: Target::TextContent,
+                    state_descriptors: vec![&skeleton.state],
+                    content: vec![ReactiveContent::Var(0)],
+                });
+            }
+            Content::Html(html) => {
+                let Some(id) = element_id else { return };
+
+                let mut nested = Reactivity::default();
+                html.element.reactivity(&mut nested);
+                reactivity.add_scoped(&html.scope, nested);
+
+                reactivity.add(ReactivityDescriptor {
+                    element_id: id,
+                    child_node_idx: None,
+                    target: Target::Html { scope: &html.scope },
+                    state_descriptors: vec![&html.state],
+                    content: vec![ReactiveContent::Var(0)],
+                });
+            }
             Content::Value(ContentValue::Element(element)) => element.reactivity(reactivity),
+            Content::If(if_content) => {
+                let start = reactivity.len();
+                if_content.then.reactivity(element_id, reactivity);
+                if_content.otherwise.reactivity(element_id, reactivity);
+
+                let Some(id) = element_id else { return };
+
+                // after swapping a branch in, the states it displays are re-applied,
+                // since the rendered branches contain the values at the time of the initial render
+                let refresh = reactivity
+                    .state_ids_since(start)
+                    .filter(|state_id| *state_id != if_content.condition.state_id)
+                    .collect();
+
+                // ids have already been given at this point, so the rendered branches include them
+                let branch = |content: &Content| {
+                    let mut output = String::new();
+                    content.render(&mut output);
+                    ReactiveContent::Html(output.into())
+                };
+
+                reactivity.add(ReactivityDescriptor {
+                    element_id: id,
+                    child_node_idx: None,
+                    target: Target::InnerHtml { refresh },
+                    state_descriptors: vec![&if_content.condition],
+                    content: vec![ReactiveContent::If {
+                        var: 0,
+                        then: Box::new(branch(&if_content.then)),
+                        otherwise: Box::new(branch(&if_content.otherwise)),
+                    }],
+                });
+            }
+
+            Content::Show(show) => {
+                let start = reactivity.len();
+                show.element.reactivity(reactivity);
+
+                let Some(id) = show.element.id else { return };
+
+                // like with `If`, the states of the element are re-applied after showing it
+                let refresh = reactivity
+                    .state_ids_since(start)
+                    .filter(|state_id| *state_id != show.condition.state_id)
+                    .collect();
+
+                let mut html = String::new();
+                show.element.render(&mut html);
+
+                reactivity.add(ReactivityDescriptor {
+                    element_id: id,
+                    child_node_idx: None,
+                    target: Target::Show {
+                        html: html.into(),
+                        refresh,
+                    },
+                    state_descriptors: vec![&show.condition],
+                    content: vec![],
+                });
+            }
 
             Content::Empty => {}
             Content::Value(ContentValue::Raw(_)) => {}
@@ -258,6 +586,27 @@ impl Content {
         }
     }
 
+    /// See [`Element::estimated_size`]
+    pub(crate) fn estimated_size(&self) -> usize {
+        match self {
+            Self::Empty => 0,
+            Self::Value(value) => value.estimated_size(),
+            Self::List(list) => list.iter().map(ContentValue::estimated_size).sum(),
+            Self::If(if_content) => if_content.current().estimated_size(),
+            Self::Skeleton(skeleton) => skeleton.placeholder.estimated_size(),
+            Self::Html(html) => html.element.estimated_size(),
+            Self::Show(show) if show.condition.display == "true" => show.element.estimated_size(),
+            Self::Show(show) => {
+                let id = if show.element.id.is_some() {
+                    ID_ATTRIBUTE_LEN
+                } else {
+                    0
+                };
+                "<template></template>".len() + id
+            }
+        }
+    }
+
     pub(crate) fn render(&self, output: &mut String) {
         match self {
             Self::Empty => {}
@@ -267,6 +616,10 @@ impl Content {
                     item.render(output);
                 }
             }
+            Self::If(if_content) => if_content.current().render(output),
+            Self::Skeleton(skeleton) => skeleton.placeholder.render(output),
+            Self::Show(show) => show.render(output),
+            Self::Html(html) => html.element.render(output),
         }
     }
 }
@@ -293,7 +646,7 @@ impl From<Element> for ContentValue {
 }
 impl<T> From<State<T>> for ContentValue
 where
-    T: Clone + Display + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
 {
     fn from(value: State<T>) -> Self {
         Self::State(value.into())
@@ -301,13 +654,26 @@ where
 }
 impl<T> From<ComputedState<T>> for ContentValue
 where
-    T: Clone + Display + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
 {
     fn from(value: ComputedState<T>) -> Self {
         Self::State(value.into())
     }
 }
 
+/// `None` renders nothing, so `condition.then(|| element)` can be used in lists.
+impl<T> From<Option<T>> for ContentValue
+where
+    ContentValue: From<T>,
+{
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => value.into(),
+            None => Self::Text(String::new()),
+        }
+    }
+}
+
 impl<T> From<T> for Content
 where
     ContentValue: From<T>,
@@ -321,6 +687,26 @@ impl From<Vec<ContentValue>> for Content {
         Self::List(value)
     }
 }
+/// Renders the value if it's `Ok`, and the error message if it's `Err`.
+///
+/// The error is rendered inside a `<span class="coaxial-error">`, so it's meant for prototyping.
+/// It has no styles of its own, so style the class to make errors stand out.
+/// Keep in mind that the error message is shown to everyone viewing the page, so it shouldn't
+/// contain anything sensitive.
+impl<T, E> From<Result<T, E>> for Content
+where
+    T: Into<Content>,
+    E: Display,
+{
+    fn from(value: Result<T, E>) -> Self {
+        match value {
+            Ok(value) => value.into(),
+            Err(error) => {
+                super::span(error.to_string(), crate::attrs!("class" => "coaxial-error")).into()
+            }
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -350,4 +736,363 @@ mod tests {
             Content::Value(ContentValue::Raw("heyhi".to_string()))
         );
     }
+
+    #[test]
+    fn test_when() {
+        let render = |content: Content| {
+            let mut output = String::new();
+            crate::html::p(content, Default::default()).render(&mut output);
+            output
+        };
+
+        assert_eq!("<p>yes</p>", render(Content::when(true, "yes")));
+        assert_eq!("<p></p>", render(Content::when(false, "yes")));
+        assert_eq!("<p>no</p>", render(Content::when_else(false, "yes", "no")));
+
+        let admin = false;
+        let mut content = Content::List(vec![
+            "hello".into(),
+            admin
+                .then(|| crate::html::strong("admin", Default::default()))
+                .into(),
+            None::<&str>.into(),
+        ]);
+        content.optimize();
+        assert_eq!(
+            Content::Value(ContentValue::Text("hello".to_string())),
+            content
+        );
+
+        let mut content: Content = Some("some").into();
+        content.optimize();
+        assert_eq!(
+            Content::Value(ContentValue::Text("some".to_string())),
+            content
+        );
+
+        let mut content: Content = None::<String>.into();
+        content.optimize();
+        assert_eq!(Content::Empty, content);
+    }
+
+    #[test]
+    fn test_bool_content() {
+        use crate::{context::Context, html::p, reactive_js::element_reactivity_script};
+
+        let mut ctx = Context::<()>::new(0, false);
+        let state = ctx.use_state(true);
+
+        let mut el = p(
+            ctx.use_bool_content(state, "yes", crate::html::strong("no", Default::default())),
+            Default::default(),
+        );
+        let script = element_reactivity_script(&mut el, 0);
+
+        let mut output = String::new();
+        el.render(&mut output);
+
+        let id = el.id.unwrap();
+        assert_eq!(format!("<p coax-id=\"{id}\">yes</p>"), output);
+        assert!(
+            script.contains("el.innerHTML = (v0 === 'true' ? \"yes\" : \"<strong>no</strong>\");")
+        );
+
+        // newlines are escaped, so the strings in the script aren't cut short
+        let mut el = p(
+            ctx.use_bool_content(state, "a\nb", "c\r\nd"),
+            Default::default(),
+        );
+        let script = element_reactivity_script(&mut el, 0);
+        assert!(script.contains("el.innerHTML = (v0 === 'true' ? \"a\\nb\" : \"c\\r\\nd\");"));
+    }
+
+    #[test]
+    fn test_skeleton_renders_placeholder() {
+        use crate::{context::Context, html::p, reactive_js::element_reactivity_script};
+
+        let mut ctx = Context::<()>::new(0, false);
+        let state = ctx.use_state(1u32);
+        let computed = ctx.use_computed(state, |state| *state + 1);
+
+        let mut el = p(
+            ctx.use_skeleton(crate::html::strong("loading", Default::default()), computed),
+            Default::default(),
+        );
+        let script = element_reactivity_script(&mut el, 0);
+
+        let mut output = String::new();
+        el.render(&mut output);
+
+        let id = el.id.unwrap();
+        assert_eq!(
+            format!("<p coax-id=\"{id}\"><strong>loading</strong></p>"),
+            output
+        );
+        assert!(script.contains(&format!("onStateChange(['{}']", computed.0.id)));
+        assert!(script.contains("el.textContent = v0;"));
+        assert_eq!(vec![computed.0.id], ctx.skeletons);
+    }
+
+    #[test]
+    fn test_computed_element_renders_new_html() {
+        use crate::{
+            context::Context,
+            html::{p, strong},
+            reactive_js::element_reactivity_script,
+        };
+
+        let mut ctx = Context::<()>::new(0, true);
+        let count = ctx.use_state(1u32);
+        let content = ctx.use_computed_element(count, |count| {
+            strong(format!("{} <items>", *count), Default::default())
+        });
+        let Content::Html(html) = &content else {
+            panic!("expected html content");
+        };
+        let (html_id, scope) = (html.state.state_id.clone(), html.scope.clone());
+
+        let mut el = p(content, Default::default());
+        let script = element_reactivity_script(&mut el, 0);
+
+        let id = el.id.unwrap();
+        assert_eq!(
+            format!("<p coax-id=\"{id}\"><strong>1 &lt;items&gt;</strong></p>"),
+            el.render_to_string()
+        );
+        assert!(script.contains(&format!("onStateChange(['{html_id}'], (v0) =>")));
+        assert!(script.contains(&format!("window.Coaxial.setHtml(el, v0, '{scope}');")));
+
+        count.set(2);
+        ctx.computed_states.recompute_dependents(count.id);
+        let mut html = None;
+        while let Ok((id, change)) = ctx.states.changes_rx.try_recv() {
+            if id.to_string() == html_id {
+                html = Some(change.display);
+            }
+        }
+        let html: serde_json::Value = serde_json::from_str(&html.unwrap()).unwrap();
+        assert_eq!("<strong>2 &lt;items&gt;</strong>", html["html"]);
+        assert_eq!("", html["script"]);
+    }
+
+    #[test]
+    fn test_computed_element_keeps_nested_states_reactive() {
+        use crate::{
+            context::Context,
+            html::{div, p, span},
+            random_id::IdPath,
+            reactive_js::element_reactivity_script_with_states,
+        };
+
+        let mut ctx = Context::<()>::new(0, true);
+        let items = ctx.use_state(1u32);
+        let name = ctx.use_state("ferris".to_string());
+        let content = ctx.use_computed_element(items, move |items| {
+            div(
+                Content::List(
+                    (0..*items)
+                        .map(|_| span(name, Default::default()).into())
+                        .collect(),
+                ),
+                Default::default(),
+            )
+        });
+        let Content::Html(html) = &content else {
+            panic!("expected html content");
+        };
+        let (html_id, scope) = (html.state.state_id.clone(), html.scope.clone());
+        let span_id = |html: &str| {
+            let start = html.find("<span coax-id=\"").unwrap() + "<span coax-id=\"".len();
+            html[start..start + 8].to_string()
+        };
+
+        let mut el = p(content, Default::default());
+        let mut used = HashSet::new();
+        let script = element_reactivity_script_with_states(&mut el, IdPath::root(0, ""), &mut used);
+        let page = el.render_to_string();
+
+        // the nested state is registered with the page, in the scope of the computed element
+        let span = span_id(&page);
+        assert!(page.contains(&format!("<span coax-id=\"{span}\">ferris</span>")));
+        assert!(used.contains(&name.id.to_string()));
+        assert!(script.contains(&format!(
+            "window.Coaxial.scoped('{scope}', () => {{ window.Coaxial.onStateChange(['{}'], (v0) => {{ if (el = document.querySelector('[coax-id=\"{span}\"]')) el.textContent = v0; }});",
+            name.id
+        )));
+
+        items.set(2);
+        ctx.computed_states.recompute_dependents(items.id);
+        let mut html = None;
+        while let Ok((id, change)) = ctx.states.changes_rx.try_recv() {
+            if id.to_string() == html_id {
+                html = Some(change.display);
+            }
+        }
+        let html: serde_json::Value = serde_json::from_str(&html.unwrap()).unwrap();
+        let (html, script) = (
+            html["html"].as_str().unwrap(),
+            html["script"].as_str().unwrap(),
+        );
+
+        // the recomputed element keeps the ids, and brings the listeners for both spans
+        assert_eq!(span, span_id(html));
+        assert_eq!(2, html.matches("<span coax-id=").count());
+        assert_eq!(2, script.matches("onStateChange").count());
+        assert!(script.contains(&format!("onStateChange(['{}'], (v0) =>", name.id)));
+    }
+
+    #[test]
+    fn test_content_from_result() {
+        let render = |content: Content| {
+            let mut output = String::new();
+            content.render(&mut output);
+            output
+        };
+
+        let ok: Result<&str, String> = Ok("fine");
+        assert_eq!("fine", render(ok.into()));
+
+        let err: Result<&str, String> = Err("<oops>".to_string());
+        assert_eq!(
+            "<span class=\"coaxial-error\">&lt;oops&gt;</span>",
+            render(err.into())
+        );
+    }
+
+    #[test]
+    fn test_show() {
+        use crate::{
+            context::Context,
+            html::{div, strong},
+            reactive_js::element_reactivity_script,
+        };
+
+        let render = |visible: bool| {
+            let mut ctx = Context::<()>::new(0, false);
+            let state = ctx.use_state(visible);
+
+            let mut el = div(
+                Content::List(vec![
+                    "before".into(),
+                    ctx.use_show(state, strong("shown", Default::default()))
+                        .into(),
+                    "after".into(),
+                ]),
+                Default::default(),
+            );
+            let script = element_reactivity_script(&mut el, 0);
+
+            let mut output = String::new();
+            el.render(&mut output);
+            (output, script, state.id)
+        };
+
+        let (shown, script, state) = render(true);
+        let (hidden, _, _) = render(false);
+
+        // same seed, so the element gets the same id in both
+        let id = shown
+            .split("coax-id=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            format!("<div>before<strong coax-id=\"{id}\">shown</strong>after</div>"),
+            shown
+        );
+        assert_eq!(
+            format!("<div>before<template coax-id=\"{id}\"></template>after</div>"),
+            hidden
+        );
+        assert!(script.starts_with(&format!(
+            "window.Coaxial.onStateChange(['{state}'], (v0) => {{ if (el = document.querySelector('[coax-id=\"{id}\"]')) window.Coaxial.show(el, v0 === 'true', \"<strong coax-id=\\\"{id}\\\">shown</strong>\", []); }});"
+        )));
+    }
+
+    #[test]
+    fn test_show_element_with_newlines() {
+        use crate::{
+            context::Context,
+            html::{div, pre},
+            reactive_js::element_reactivity_script,
+        };
+
+        let mut ctx = Context::<()>::new(0, false);
+        let open = ctx.use_state(true);
+        let mut el = div(
+            ctx.use_show(open, pre("line one\nline two", Default::default())),
+            Default::default(),
+        );
+        let script = element_reactivity_script(&mut el, 0);
+
+        // the newline is escaped, so the string in the script isn't cut short
+        assert!(script.contains("\\\">line one\\nline two</pre>\", []);"));
+        assert!(!script.contains("line one\nline two"));
+    }
+
+    #[test]
+    fn test_raw_strips_control_characters() {
+        let mut output = String::new();
+        ContentValue::Raw("<b>a\u{0}b\u{7}c\u{1b}\u{7f}\u{85}d</b>".to_string())
+            .render(&mut output);
+        assert_eq!("<b>abcd</b>", output);
+
+        // whitespace control characters are allowed
+        let mut output = String::new();
+        ContentValue::Raw("a\tb\nc\rd\u{c}e".to_string()).render(&mut output);
+        assert_eq!("a\tb\nc\rd\u{c}e", output);
+    }
+
+    #[test]
+    fn test_text_groups_match_child_nodes() {
+        use crate::{
+            context::Context,
+            html::{div, fragment, p, span},
+            reactive_js::element_reactivity_script,
+        };
+
+        let mut ctx = Context::<()>::new(0, false);
+        let class = ctx.use_state("big".to_string());
+        let count = ctx.use_state(1u32);
+
+        // childNodes: "text ", <span>, " more ", the state
+        let mut el = div(
+            vec![
+                ContentValue::from("text "),
+                span("x", crate::attrs!("class" => class)).into(),
+                ContentValue::from(" more "),
+                count.into(),
+            ],
+            Default::default(),
+        );
+        let script = element_reactivity_script(&mut el, 0);
+        assert!(
+            script.contains("if (el = el.childNodes[2]) el.textContent = [' more ',v0].join('');")
+        );
+        assert!(!script.contains("childNodes[0]"));
+        assert!(script.contains("('class', v0)"));
+
+        // the fragment's text is merged with the text around it, and its element is a sibling of it
+        let mut el = div(
+            vec![
+                ContentValue::from("a"),
+                fragment(vec![ContentValue::from("b"), ContentValue::from(count)]).into(),
+                p("x", Default::default()).into(),
+                ContentValue::from(count),
+            ],
+            Default::default(),
+        );
+        let script = element_reactivity_script(&mut el, 0);
+        assert!(script.contains("if (el = el.childNodes[0]) el.textContent = ['ab',v0].join('');"));
+        assert!(script.contains("if (el = el.childNodes[2]) el.textContent = v0;"));
+
+        // a fragment that is the whole content takes the place of it
+        let mut el = div(fragment(count), Default::default());
+        let script = element_reactivity_script(&mut el, 0);
+        let id = el.id.unwrap();
+        assert!(script.contains(&format!(
+            "if (el = document.querySelector('[coax-id=\"{id}\"]')) el.textContent = v0;"
+        )));
+    }
 }
@@ -1,10 +1,28 @@
-use rand::Rng;
+use std::{collections::HashSet, fmt::Display};
 
-use crate::{random_id::RandomId, reactive_js::Reactivity};
+use axum::response::Html;
+
+use crate::{
+    random_id::{IdPath, RandomId, RANDOM_ID_LENGTH},
+    reactive_js::Reactivity,
+};
 
 use super::{Attributes, Content, VOID_ELEMENTS};
 
-#[derive(Debug, PartialEq, Eq)]
+/// Length of ` coax-id="..."`
+pub(crate) const ID_ATTRIBUTE_LEN: usize = " coax-id=\"\"".len() + RANDOM_ID_LENGTH;
+
+/// Two elements with the same id would share their reactivity, which is very confusing to debug,
+/// so it fails loudly while developing
+fn report_id_collision(id: RandomId) {
+    #[cfg(debug_assertions)]
+    panic!("coaxial: the id {id} was given to more than one element in the same tree");
+
+    #[cfg(not(debug_assertions))]
+    tracing::error!(%id, "the same id was given to more than one element in the same tree");
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Element {
     pub(crate) id: Option<RandomId>,
     pub(crate) name: String,
@@ -13,6 +31,11 @@ pub struct Element {
 }
 
 impl Element {
+    /// Fragments render their content without a wrapping tag. See [`fragment`](super::fragment).
+    pub(crate) fn is_fragment(&self) -> bool {
+        self.name.is_empty()
+    }
+
     pub(crate) fn optimize(&mut self) {
         self.content.optimize();
         self.attributes.optimize();
@@ -22,15 +45,27 @@ impl Element {
         self.content.is_reactive() || self.attributes.is_reactive()
     }
 
-    pub(crate) fn give_ids<RNG: Rng>(&mut self, rng: &mut RNG) {
-        if self.is_reactive() && self.id.is_none() {
-            self.id = Some(RandomId::from_rng(rng));
+    /// Gives ids to this element and its descendants if they're reactive, where `path` is the
+    /// path of this element. Ids are never repeated within `used`, which they're added to
+    pub(crate) fn give_ids(&mut self, path: IdPath, used: &mut HashSet<RandomId>) {
+        if let Some(id) = self.id {
+            if !used.insert(id) {
+                report_id_collision(id);
+            }
+        } else if self.is_reactive() && !self.is_fragment() {
+            // fragments aren't in the DOM, so they can't be found by id
+            self.id = Some(path.unique_id(used));
         }
 
-        self.content.give_ids(rng);
+        self.content.give_ids(path, used);
     }
 
     pub(crate) fn render(&self, output: &mut String) {
+        if self.is_fragment() {
+            self.content.render(output);
+            return;
+        }
+
         output.push('<');
         output.push_str(&self.name);
 
@@ -39,18 +74,18 @@ impl Element {
             self.attributes.render(output);
         }
 
-        // void elements cannot have a closing tag
-        if VOID_ELEMENTS.contains(&self.name.as_str()) {
-            output.push_str(" />");
-            return;
-        }
-
         if let Some(id) = &self.id {
             output.push_str(" coax-id=\"");
             id.fmt(output).unwrap();
             output.push('\"');
         }
 
+        // void elements cannot have a closing tag
+        if VOID_ELEMENTS.contains(&self.name.as_str()) {
+            output.push_str(" />");
+            return;
+        }
+
         output.push('>');
 
         self.content.render(output);
@@ -60,6 +95,29 @@ impl Element {
         output.push('>');
     }
 
+    /// Returns roughly how long the rendered element is, without going over, to allocate the
+    /// output before rendering. Escaping can only make the output longer
+    pub(crate) fn estimated_size(&self) -> usize {
+        if self.is_fragment() {
+            return self.content.estimated_size();
+        }
+
+        let id = if self.id.is_some() {
+            ID_ATTRIBUTE_LEN
+        } else {
+            0
+        };
+        // `<name` and `>`
+        let open = 2 + self.name.len() + self.attributes.estimated_size() + id;
+        if VOID_ELEMENTS.contains(&self.name.as_str()) {
+            // ` />` instead of `>`
+            return open + 2;
+        }
+
+        // `</name>`
+        open + self.content.estimated_size() + 3 + self.name.len()
+    }
+
     pub(crate) fn reactivity<'a, 'b>(&'a self, reactivity: &'b mut Reactivity<'a>)
     where
         'a: 'b,
@@ -71,12 +129,43 @@ impl Element {
     pub fn attributes(&self) -> &Attributes {
         &self.attributes
     }
+
+    /// Renders the element as HTML, the same way it is rendered in a page.
+    ///
+    /// Unlike in a page, elements are not given ids and no script is generated for them,
+    /// so states are rendered with their current value and are never updated.
+    /// Useful for tests and for static HTML.
+    pub fn render_to_string(&self) -> String {
+        let mut element = self.clone();
+        element.optimize();
+
+        let mut output = String::with_capacity(element.estimated_size());
+        element.render(&mut output);
+        output
+    }
+
+    /// Renders the element as an HTML response, for routes that aren't live,
+    /// like static pages or fragments requested by htmx.
+    ///
+    /// It's rendered like [`Element::render_to_string`], without the doctype.
+    pub fn into_html_response(mut self) -> Html<String> {
+        self.optimize();
+
+        let mut output = String::new();
+        self.render(&mut output);
+        Html(output)
+    }
+}
+
+impl Display for Element {
+    /// Renders the element as HTML. See [`Element::render_to_string`].
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render_to_string())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use rand::rngs::mock::StepRng;
-
     use crate::html::{content::ContentValue, div, p, StateDescriptor};
 
     use super::*;
@@ -127,6 +216,59 @@ mod tests {
         assert_eq!(output, "<div><p>hello</p></div>");
     }
 
+    #[tokio::test]
+    async fn test_into_html_response() {
+        use axum::{http::header::CONTENT_TYPE, response::IntoResponse};
+
+        let response = div(
+            Content::List(vec![p("hello", Default::default()).into()]),
+            Default::default(),
+        )
+        .into_html_response()
+        .into_response();
+
+        assert_eq!(
+            "text/html; charset=utf-8",
+            response.headers()[CONTENT_TYPE].to_str().unwrap()
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!("<div><p>hello</p></div>", body);
+    }
+
+    #[test]
+    fn test_fragment_has_no_wrapper() {
+        use crate::html::fragment;
+
+        let mut el = fragment(Content::List(vec![
+            p("a", Default::default()).into(),
+            p(
+                ContentValue::State(StateDescriptor {
+                    display: "b".to_string(),
+                    state_id: "my_state".to_string(),
+                    format: None,
+                }),
+                Default::default(),
+            )
+            .into(),
+        ]));
+        el.give_ids(IdPath::root(0, ""), &mut HashSet::new());
+
+        let mut output = String::new();
+        el.render(&mut output);
+
+        // the reactive child still gets an id
+        let Content::List(children) = &el.content else {
+            panic!("content should be a list");
+        };
+        let ContentValue::Element(second) = &children[1] else {
+            panic!("second child should be an element");
+        };
+        let id = second.id.unwrap();
+        assert_eq!(format!("<p>a</p><p coax-id=\"{id}\">b</p>"), output);
+    }
+
     #[test]
     fn test_reactive_elements_have_ids() {
         let mut el = Element {
@@ -135,17 +277,95 @@ mod tests {
             content: Content::Value(ContentValue::State(StateDescriptor {
                 display: "value".to_string(),
                 state_id: "my_state".to_string(),
+                format: None,
             })),
 
             attributes: Default::default(),
         };
 
-        el.give_ids(&mut StepRng::new(0, 1));
+        el.give_ids(IdPath::root(0, ""), &mut HashSet::new());
 
         assert!(el.content.is_reactive());
         assert!(el.id.is_some());
     }
 
+    #[test]
+    fn test_reactive_input_has_id() {
+        use crate::html::input;
+
+        let mut ctx = crate::context::Context::<()>::new(0, false);
+        let name = ctx.use_state("ada".to_string());
+
+        let mut el = input(crate::attrs!("value" => name));
+        el.give_ids(IdPath::root(0, ""), &mut HashSet::new());
+        let id = el.id.expect("reactive inputs need an id");
+
+        let mut output = String::new();
+        el.render(&mut output);
+        assert!(output.starts_with("<input value=\"ada\" "));
+        assert!(output.ends_with(&format!(" coax-id=\"{id}\" />")));
+    }
+
+    #[test]
+    fn test_void_elements_are_spaced_consistently() {
+        use crate::html::{bind, br, img, input};
+
+        let mut ctx = crate::context::Context::<()>::new(0, false);
+        let checked = ctx.use_state(false);
+
+        let mut output = String::new();
+        br(Default::default()).render(&mut output);
+        img(crate::attrs!("src" => "x")).render(&mut output);
+        // an unchecked `checked` isn't rendered, but its handler is
+        input(crate::attrs!("checked" => bind(checked))).render(&mut output);
+        assert_eq!(
+            format!(
+                "<br /><img src=\"x\" /><input onchange=\"window.Coaxial.setState('{}', this.checked)\" />",
+                checked.id
+            ),
+            output
+        );
+    }
+
+    #[test]
+    fn test_ids_dont_depend_on_conditional_siblings() {
+        use crate::html::aside;
+
+        let state = || {
+            ContentValue::State(StateDescriptor {
+                display: "value".to_string(),
+                state_id: "my_state".to_string(),
+                format: None,
+            })
+        };
+        let render = |with_aside: bool| {
+            let mut children = Vec::new();
+            if with_aside {
+                children.push(aside(state(), Default::default()).into());
+            }
+            children.push(p(state(), Default::default()).into());
+            children.push(p(state(), Default::default()).into());
+
+            let mut el = div(Content::List(children), Default::default());
+            el.give_ids(IdPath::root(0, ""), &mut HashSet::new());
+            let Content::List(children) = el.content else {
+                panic!("content should be a list");
+            };
+            children
+                .into_iter()
+                .filter_map(|child| match child {
+                    ContentValue::Element(el) if el.name == "p" => el.id,
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let without = render(false);
+        assert_eq!(without, render(true));
+        assert_eq!(2, without.len());
+        assert_ne!(without[0], without[1]);
+    }
+
     #[test]
     fn test_non_reactive_elements_dont_have_ids() {
         let mut el = Element {
@@ -155,9 +375,73 @@ mod tests {
             attributes: Default::default(),
         };
 
-        el.give_ids(&mut StepRng::new(0, 1));
+        el.give_ids(IdPath::root(0, ""), &mut HashSet::new());
 
         assert!(!el.content.is_reactive());
         assert!(el.id.is_none());
     }
+
+    #[test]
+    fn test_render_to_string() {
+        assert_eq!(div("hi", Default::default()).to_string(), "<div>hi</div>");
+
+        // escaping is the same as when rendering a page
+        let el = div(
+            Content::List(vec![
+                "<b>".into(),
+                " & ".into(),
+                p("x", Default::default()).into(),
+            ]),
+            crate::attrs!("title" => ("a \"quote\"", " <tag>")),
+        );
+        let mut output = String::new();
+        el.render(&mut output);
+        assert_eq!(output, el.render_to_string());
+        assert_eq!(
+            "<div title=\"a &quot;quote&quot; &lt;tag&gt;\">&lt;b&gt; &amp; <p>x</p></div>",
+            el.render_to_string()
+        );
+    }
+
+    #[test]
+    fn test_estimated_size_is_lower_bound() {
+        use crate::html::{br, fragment, img};
+
+        let mut el = div(
+            Content::List(vec![
+                p("hello & goodbye", crate::attrs!("class" => "greeting")).into(),
+                img(crate::attrs!("src" => "/a.png", "alt" => "")).into(),
+                br(Default::default()).into(),
+                fragment("world").into(),
+            ]),
+            crate::attrs!("id" => "main"),
+        );
+        el.id = Some(RandomId::from_str("aaaabbbb"));
+
+        let rendered = el.render_to_string();
+        let estimated = el.estimated_size();
+        // only the escaped `&` is missed
+        assert!(estimated <= rendered.len());
+        assert_eq!(rendered.len() - "amp;".len(), estimated);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "was given to more than one element")]
+    fn test_id_collision_panics() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        // the same seed makes the rng produce the same id twice
+        let id = || Some(RandomId::from_rng(&mut StdRng::seed_from_u64(1)));
+        let mut first = p("first", Default::default());
+        first.id = id();
+        let mut second = p("second", Default::default());
+        second.id = id();
+
+        let mut el = div(
+            Content::List(vec![first.into(), second.into()]),
+            Default::default(),
+        );
+        el.give_ids(IdPath::root(0, ""), &mut HashSet::new());
+    }
 }
@@ -1,4 +1,7 @@
-use super::{Attributes, Content, Element};
+use std::fmt::Display;
+
+use super::{bind_on, Attributes, Content, Element, InputEvent};
+use crate::states::State;
 
 macro_rules! make_elements_funcs {
     ($($name:ident),* $(,)?) => {
@@ -17,9 +20,40 @@ macro_rules! make_elements_funcs {
 
 make_elements_funcs!(
     div, html, head, body, p, a, button, section, aside, main, script, strong, b, i, em, style,
-    pre, code
+    pre, code, title, span, h1, h2, h3, h4, h5, h6, ul, ol, li, nav, header, footer, article, form,
+    label, select, option, textarea, table, thead, tbody, tr, td, th, small, blockquote, dialog,
 );
 
+/// Creates a fragment, which renders its content without a wrapping element.
+///
+/// Useful for returning multiple sibling elements from a handler.
+/// The fragment itself is not in the DOM: its content is merged into the content of the element
+/// it's placed in, so states placed directly in it are updated like any other child of that element.
+pub fn fragment(content: impl Into<Content>) -> Element {
+    Element {
+        id: None,
+        name: String::new(),
+        content: content.into(),
+        attributes: Default::default(),
+    }
+}
+
+/// Creates an element with any tag name, like custom elements or tags without their own function.
+///
+/// Void elements, like `br`, are still rendered without a closing tag.
+pub fn element(
+    name: impl Into<String>,
+    content: impl Into<Content>,
+    attributes: Attributes,
+) -> Element {
+    Element {
+        id: None,
+        name: name.into(),
+        content: content.into(),
+        attributes,
+    }
+}
+
 macro_rules! make_void_elements {
     ($($name:ident),* $(,)?) => {
         /// HTML elements that cannot have any child nodes
@@ -44,4 +78,72 @@ make_void_elements!(
     area, base, br, col, embed, hr, img, input, link, meta, param, source, track, wbr,
 );
 
+/// Creates an `<input type="range">` bound to `state`, going from `min` to `max` in steps of `step`.
+///
+/// Dragging the slider sets the state as a number, at most once every 50 milliseconds,
+/// and the slider moves when the state is changed by the server. See [`bind_on`].
+pub fn range_input<T>(state: State<T>, min: T, max: T, step: T) -> Element
+where
+    T: Display + Clone + Send + Sync + 'static,
+{
+    input(crate::attrs!(
+        "type" => "range",
+        "min" => min.to_string(),
+        "max" => max.to_string(),
+        "step" => step.to_string(),
+        "value" => bind_on(state, InputEvent::Input),
+    ))
+}
+
 pub(crate) const DOCTYPE_HTML: &str = "<!DOCTYPE html>";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_list() {
+        let el = ul(
+            Content::List(vec![li("a", Default::default()).into()]),
+            Default::default(),
+        );
+
+        let mut output = String::new();
+        el.render(&mut output);
+        assert_eq!("<ul><li>a</li></ul>", output);
+    }
+
+    #[test]
+    fn test_custom_element() {
+        let mut output = String::new();
+        element("my-card", "hi", Default::default()).render(&mut output);
+        assert_eq!("<my-card>hi</my-card>", output);
+
+        let mut output = String::new();
+        element("br", Content::Empty, Default::default()).render(&mut output);
+        assert_eq!("<br />", output);
+    }
+
+    #[test]
+    fn test_textarea_is_not_void() {
+        let mut output = String::new();
+        textarea(Content::Empty, Default::default()).render(&mut output);
+        assert_eq!("<textarea></textarea>", output);
+    }
+
+    #[test]
+    fn test_range_input_sends_numbers() {
+        let mut ctx = crate::context::Context::<()>::new(0, false);
+        let level = ctx.use_state(5i32);
+
+        let mut output = String::new();
+        range_input(level, 0, 10, 1).render(&mut output);
+        assert_eq!(
+            format!(
+                "<input max=\"10\" min=\"0\" oninput=\"window.Coaxial.setStateThrottled('{}', this.valueAsNumber, 50)\" step=\"1\" type=\"range\" value=\"5\" />",
+                level.id
+            ),
+            output
+        );
+    }
+}
@@ -19,8 +19,12 @@ mod content;
 mod element;
 mod funcs;
 
-pub use attribute::{Attribute, AttributeValue, ClosureDescriptor, StateDescriptor};
+pub use attribute::{
+    bind, bind_on, fmt, Attribute, AttributeValue, ClosureDescriptor, FormatDescriptor, InputEvent,
+    StateDescriptor,
+};
 pub use attributes::Attributes;
-pub use content::{Content, ContentValue};
+pub(crate) use attributes::MethodToggle;
+pub use content::{Content, ContentValue, HtmlContent, IfContent, ShowContent, SkeletonContent};
 pub use element::Element;
 pub use funcs::*;
@@ -11,14 +11,22 @@ pub mod computed;
 pub mod config;
 pub mod context;
 mod events;
+mod frames;
 mod handler;
 mod helpers;
 pub mod html;
+mod lists;
 pub mod live;
+pub mod model;
+pub mod pagination;
+pub mod presence;
 mod random_id;
 mod reactive_js;
 mod states;
-pub use states::StateGet;
+#[cfg(any(test, feature = "test"))]
+pub mod testing;
+pub use events::{EventOptions, KeyFilter};
+pub use states::{batch, NumberFormat, State, StateGet};
 
 pub type CoaxialResponse<S = ()> = Response<Output<S>>;
 pub struct Output<S = ()> {
@@ -0,0 +1,356 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{html::Element, random_id::RandomId, states::State};
+
+/// Keyed lists, stored by the id of the state holding their items
+#[derive(Default)]
+pub(crate) struct Lists {
+    lists: HashMap<RandomId, Vec<Box<dyn AnyList>>>,
+}
+
+impl Lists {
+    pub(crate) fn insert(&mut self, state_id: RandomId, list: Box<dyn AnyList>) {
+        self.lists.entry(state_id).or_default().push(list);
+    }
+
+    /// Returns whether any list gets its items from the state `id`
+    #[cfg(debug_assertions)]
+    pub(crate) fn contains(&self, id: RandomId) -> bool {
+        self.lists.contains_key(&id)
+    }
+
+    /// Returns the updates for the lists whose items are in the state `id`.
+    ///
+    /// Lists that didn't change are skipped.
+    pub(crate) fn update(&mut self, id: RandomId) -> Vec<ListUpdate> {
+        let Some(lists) = self.lists.get_mut(&id) else {
+            return vec![];
+        };
+
+        lists
+            .iter_mut()
+            .map(|list| list.update())
+            .filter(|update| !update.ops.is_empty())
+            .collect()
+    }
+}
+
+/// Trait used to type-erase all lists, so they can be stored in the same HashMap
+pub(crate) trait AnyList: Send + Sync {
+    /// Renders the current items, and returns the operations that turn the previous ones into them
+    fn update(&mut self) -> ListUpdate;
+}
+
+pub(crate) struct List<T: 'static, K, R> {
+    pub(crate) element_id: RandomId,
+    pub(crate) state: State<Vec<T>>,
+    pub(crate) key: K,
+    pub(crate) render: R,
+    /// Items as they were last rendered, with their keys
+    pub(crate) items: Vec<(String, Element)>,
+}
+
+impl<T, KEY, K, R> List<T, K, R>
+where
+    T: Send + Sync + 'static,
+    KEY: Display,
+    K: Fn(&T) -> KEY,
+    R: Fn(&T) -> Element,
+{
+    /// Renders every item, marking their root element with their key.
+    ///
+    /// A `coax-key` set by `render` is replaced, since the client finds the items by it.
+    pub(crate) fn render_items(&self) -> Vec<(String, Element)> {
+        self.state.with(|items| {
+            items
+                .iter()
+                .map(|item| {
+                    let key = (self.key)(item).to_string();
+
+                    let mut element = (self.render)(item);
+                    element.attributes.set("coax-key", key.clone());
+                    element.optimize();
+
+                    (key, element)
+                })
+                .collect()
+        })
+    }
+}
+
+impl<T, KEY, K, R> AnyList for List<T, K, R>
+where
+    T: Send + Sync + 'static,
+    KEY: Display,
+    K: Fn(&T) -> KEY + Send + Sync,
+    R: Fn(&T) -> Element + Send + Sync,
+{
+    fn update(&mut self) -> ListUpdate {
+        let items = self.render_items();
+        let ops = diff(&self.items, &items);
+        self.items = items;
+
+        ListUpdate {
+            element_id: self.element_id,
+            ops,
+        }
+    }
+}
+
+/// Operations sent to the client to update a list, by applying them in order
+pub(crate) struct ListUpdate {
+    pub(crate) element_id: RandomId,
+    pub(crate) ops: Vec<ListOp>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "op")]
+pub(crate) enum ListOp {
+    /// Removes the item with `key`
+    Remove { key: String },
+    /// Replaces the item with `key` with `html`, keeping its position
+    Replace { key: String, html: String },
+    /// Inserts a new item before the item with `before`, or at the end if it's `None`
+    Insert {
+        key: String,
+        before: Option<String>,
+        html: String,
+    },
+    /// Moves the item with `key` before the item with `before`, or to the end if it's `None`
+    Move { key: String, before: Option<String> },
+}
+
+/// Returns the operations that turn the items in `old` into the items in `new`.
+///
+/// Items are matched by key, so an item that only changed its content is replaced on its own,
+/// and items that are in both lists are moved instead of rendered again.
+pub(crate) fn diff(old: &[(String, Element)], new: &[(String, Element)]) -> Vec<ListOp> {
+    debug_assert!(
+        {
+            let mut keys = new.iter().map(|(key, _)| key).collect::<Vec<_>>();
+            keys.sort();
+            keys.windows(2).all(|pair| pair[0] != pair[1])
+        },
+        "list keys must be unique"
+    );
+
+    let render = |element: &Element| {
+        let mut html = String::new();
+        element.render(&mut html);
+        html
+    };
+
+    let new_items = new
+        .iter()
+        .map(|(key, element)| (key.as_str(), element))
+        .collect::<HashMap<_, _>>();
+
+    let mut ops = Vec::new();
+
+    // the order of the client after each op, to know which items need to be moved
+    let mut current = Vec::new();
+    for (key, element) in old {
+        match new_items.get(key.as_str()) {
+            None => ops.push(ListOp::Remove { key: key.clone() }),
+            Some(new_element) => {
+                if *new_element != element {
+                    ops.push(ListOp::Replace {
+                        key: key.clone(),
+                        html: render(new_element),
+                    });
+                }
+                current.push(key.as_str());
+            }
+        }
+    }
+
+    // going from the end, place every item right before the one that follows it in `new`
+    for (i, (key, element)) in new.iter().enumerate().rev() {
+        let before = new.get(i + 1).map(|(key, _)| key.as_str());
+
+        match current.iter().position(|current| current == key) {
+            None => {
+                ops.push(ListOp::Insert {
+                    key: key.clone(),
+                    before: before.map(str::to_string),
+                    html: render(element),
+                });
+            }
+            Some(position) if current.get(position + 1).copied() == before => continue,
+            Some(position) => {
+                current.remove(position);
+                ops.push(ListOp::Move {
+                    key: key.clone(),
+                    before: before.map(str::to_string),
+                });
+            }
+        }
+
+        let position = match before {
+            Some(before) => current
+                .iter()
+                .position(|current| *current == before)
+                .unwrap(),
+            None => current.len(),
+        };
+        current.insert(position, key);
+    }
+
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::li;
+
+    fn items(items: &[(&str, &str)]) -> Vec<(String, Element)> {
+        items
+            .iter()
+            .map(|(key, text)| (key.to_string(), li(*text, Default::default())))
+            .collect()
+    }
+
+    #[test]
+    fn test_append() {
+        let old = items(&[("a", "one"), ("b", "two")]);
+        let new = items(&[("a", "one"), ("b", "two"), ("c", "three")]);
+
+        assert_eq!(
+            vec![ListOp::Insert {
+                key: "c".to_string(),
+                before: None,
+                html: "<li>three</li>".to_string()
+            }],
+            diff(&old, &new)
+        );
+    }
+
+    #[test]
+    fn test_remove_middle() {
+        let old = items(&[("a", "one"), ("b", "two"), ("c", "three")]);
+        let new = items(&[("a", "one"), ("c", "three")]);
+
+        assert_eq!(
+            vec![ListOp::Remove {
+                key: "b".to_string()
+            }],
+            diff(&old, &new)
+        );
+    }
+
+    #[test]
+    fn test_change_middle() {
+        let old = items(&[("a", "one"), ("b", "two"), ("c", "three")]);
+        let new = items(&[("a", "one"), ("b", "TWO"), ("c", "three")]);
+
+        assert_eq!(
+            vec![ListOp::Replace {
+                key: "b".to_string(),
+                html: "<li>TWO</li>".to_string()
+            }],
+            diff(&old, &new)
+        );
+    }
+
+    #[test]
+    fn test_reorder() {
+        let old = items(&[("a", "one"), ("b", "two"), ("c", "three")]);
+        let new = items(&[("c", "three"), ("a", "one"), ("b", "two")]);
+
+        let ops = diff(&old, &new);
+        assert_eq!(
+            vec![
+                ListOp::Move {
+                    key: "b".to_string(),
+                    before: None
+                },
+                ListOp::Move {
+                    key: "a".to_string(),
+                    before: Some("b".to_string())
+                },
+            ],
+            ops
+        );
+
+        // a swap only moves the items that changed place
+        let new = items(&[("a", "one"), ("c", "three"), ("b", "two")]);
+        assert_eq!(
+            vec![ListOp::Move {
+                key: "b".to_string(),
+                before: None
+            }],
+            diff(&old, &new)
+        );
+    }
+
+    #[test]
+    fn test_unchanged_list_has_no_ops() {
+        let old = items(&[("a", "one"), ("b", "two")]);
+        assert!(diff(&old, &items(&[("a", "one"), ("b", "two")])).is_empty());
+    }
+
+    #[test]
+    fn test_render_can_set_coax_key() {
+        use crate::{attrs, context::Context, html::ul};
+
+        let mut ctx = Context::<()>::new(0, true);
+        let items = ctx.use_list_state(vec![1u32]);
+        let list = ctx.use_list(
+            ul((), Default::default()),
+            items,
+            |item| *item,
+            |item| li(item.to_string(), attrs!("coax-key" => "mine")),
+        );
+
+        let mut output = String::new();
+        list.render(&mut output);
+        assert!(output.contains("<li coax-key=\"1\">1</li>"));
+    }
+
+    #[test]
+    fn test_use_list() {
+        use crate::{context::Context, html::ul};
+
+        let mut ctx = Context::<()>::new(0, true);
+        let todos = ctx.use_list_state(vec![(1, "one".to_string()), (2, "two".to_string())]);
+        let list = ctx.use_list(
+            ul((), Default::default()),
+            todos,
+            |(id, _)| *id,
+            |(_, text)| li(text.clone(), Default::default()),
+        );
+
+        let mut output = String::new();
+        list.render(&mut output);
+        let id = list.id.unwrap();
+        assert_eq!(
+            format!(
+                "<ul coax-id=\"{id}\"><li coax-key=\"1\">one</li><li coax-key=\"2\">two</li></ul>"
+            ),
+            output
+        );
+
+        todos.set(vec![(2, "two".to_string()), (3, "three".to_string())]);
+        let updates = ctx.lists.update(todos.id);
+        assert_eq!(1, updates.len());
+        assert_eq!(id, updates[0].element_id);
+        assert_eq!(
+            vec![
+                ListOp::Remove {
+                    key: "1".to_string()
+                },
+                ListOp::Insert {
+                    key: "3".to_string(),
+                    before: None,
+                    html: "<li coax-key=\"3\">three</li>".to_string()
+                },
+            ],
+            updates[0].ops
+        );
+
+        // the state displays as the amount of items
+        let (_, change) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!("2", change.display);
+    }
+}
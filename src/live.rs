@@ -1,20 +1,43 @@
-use std::collections::HashMap;
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
 
 use axum::{
     body::Body,
     extract::{
-        ws::{Message, WebSocket},
-        FromRequestParts, Query, Request, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket},
+        FromRequestParts, MatchedPath, OriginalUri, Query, Request, WebSocketUpgrade,
     },
+    http::{request::Parts, StatusCode, Uri},
+    response::IntoResponse,
     routing::{get, MethodRouter},
     Extension,
 };
 use rand::random;
-use tokio::{select, sync::mpsc::UnboundedSender};
+use tokio::{
+    select,
+    sync::{broadcast::error::RecvError, mpsc::UnboundedSender},
+    task::JoinError,
+};
 
 use crate::{
-    config::Config, context::Context, events::Events, handler::CoaxialHandler, html::DOCTYPE_HTML,
-    random_id::RandomId, reactive_js::Reactivity, states::States,
+    computed::ComputedStates,
+    config::{Config, LayoutContext},
+    context::Context,
+    events::Events,
+    frames::Frames,
+    handler::CoaxialHandler,
+    html::{fragment, DOCTYPE_HTML},
+    lists::{ListOp, ListUpdate},
+    random_id::{IdPath, RandomId},
+    reactive_js::element_reactivity_script_with_states,
+    states::{StateChange, States},
 };
 
 pub fn live<T, H, S>(handler: H) -> MethodRouter<S>
@@ -22,12 +45,17 @@ where
     H: CoaxialHandler<T, S>,
     S: Clone + Send + Sync + 'static,
 {
+    // routes without a config get their own, which keeps their sessions between requests
+    let default_config = Config::default();
+
     get(
-        |axum::extract::State(state): axum::extract::State<S>,
-         config: Option<Extension<Config>>,
-         Query(query): Query<HashMap<String, String>>,
-         request: Request| {
-            let config = config.map(|c| c.0).unwrap_or_default();
+        move |axum::extract::State(state): axum::extract::State<S>,
+              config: Option<Extension<Config>>,
+              Query(query): Query<HashMap<String, String>>,
+              request: Request| {
+            let config = config
+                .map(|c| c.0)
+                .unwrap_or_else(|| default_config.clone());
 
             let is_websocket = request
                 .headers()
@@ -39,123 +67,956 @@ where
                 if !is_websocket {
                     let rng_seed: u64 = random();
 
-                    let response = handler
-                        .call(request, state, Context::new(rng_seed, false))
-                        .await;
+                    let socket_path = socket_path(&request, &config);
+
+                    let (request_parts, request_body) = request.into_parts();
+                    let layout_parts = request_parts.clone();
+                    let path = request_parts.uri.path().to_string();
+                    let serve_static = config.should_serve_static(&request_parts);
+                    let nonce = config.nonce(&request_parts);
+                    let request = Request::from_parts(request_parts, request_body);
+
+                    let response = match handler
+                        .call(
+                            request,
+                            state,
+                            Context::with_config(rng_seed, false, &config),
+                        )
+                        .await
+                    {
+                        Ok(response) => response,
+                        Err(rejection) => return rejection,
+                    };
 
-                    let (parts, mut body) = response.into_parts();
+                    let (mut parts, mut body) = response.into_parts();
+
+                    for (name, value) in &body.context.response_headers {
+                        parts.headers.append(name, value.clone());
+                    }
 
                     let mut element = body.element;
-                    element.optimize();
-                    element.give_ids(&mut body.context.rng);
+                    let mut root_attributes =
+                        std::mem::take(&mut body.context.html_attributes).into_element("html");
+
+                    let adapter_script = if serve_static {
+                        // static pages are rendered as they are, without ids or scripts
+                        fragment(())
+                    } else {
+                        let mut used_states = HashSet::new();
+                        let mut reactive_scripts = element_reactivity_script_with_states(
+                            &mut element,
+                            IdPath::root(rng_seed, "content"),
+                            &mut used_states,
+                        );
+
+                        // the attributes for the root element are given an id and made reactive separately,
+                        // since the root element is created by the layout
+                        reactive_scripts.push_str(&element_reactivity_script_with_states(
+                            &mut root_attributes,
+                            IdPath::root(rng_seed, "html"),
+                            &mut used_states,
+                        ));
 
-                    let reactive_scripts = {
-                        let mut reactivity = Reactivity::default();
-                        element.reactivity(&mut reactivity);
-                        reactivity.script()
+                        #[cfg(debug_assertions)]
+                        for (id, location) in body.context.orphaned_states(&used_states) {
+                            tracing::warn!(
+                                state = %id,
+                                %location,
+                                "state is not used by the page, but is still synced with the client"
+                            );
+                        }
+
+                        body.context.adapter_script_element(
+                            &reactive_scripts,
+                            &config,
+                            &socket_path,
+                            nonce.as_deref(),
+                        )
                     };
 
-                    let adapter_script = body.context.adapter_script_element(&reactive_scripts);
-                    let mut html = config.layout.call(element, adapter_script);
+                    // only rendered for reporting its size, when something is going to report it
+                    let script_bytes = (cfg!(debug_assertions)
+                        || tracing::enabled!(tracing::Level::DEBUG))
+                    .then(|| {
+                        let mut rendered_script =
+                            String::with_capacity(adapter_script.estimated_size());
+                        adapter_script.render(&mut rendered_script);
+                        rendered_script.len()
+                    });
+
+                    let layout_context = LayoutContext {
+                        title: body.context.title.take(),
+                        head: std::mem::take(&mut body.context.head),
+                        nonce,
+                        parts: layout_parts,
+                    };
+                    let mut html = config
+                        .layout
+                        .call(element, adapter_script, layout_context)
+                        .await;
+                    // attributes from the handler take precedence over the ones set by the layout,
+                    // and the id has to be the one the reactive script targets
+                    html.attributes.override_with(root_attributes.attributes);
+                    html.id = root_attributes.id.or(html.id);
                     html.optimize();
 
-                    let mut output = String::from(DOCTYPE_HTML);
+                    let mut output =
+                        String::with_capacity(DOCTYPE_HTML.len() + html.estimated_size());
+                    output.push_str(DOCTYPE_HTML);
                     html.render(&mut output);
 
+                    // static pages never connect, so there's no need to keep their context
+                    if !serve_static {
+                        config.sessions.insert(rng_seed, path, body.context);
+                    }
+
+                    let html_bytes = output.len();
+                    if let Some(script_bytes) = script_bytes {
+                        tracing::debug!(html_bytes, script_bytes, "rendered live page");
+
+                        // lets authors keep an eye on the size of their pages from the browser's dev tools
+                        #[cfg(debug_assertions)]
+                        {
+                            parts
+                                .headers
+                                .insert("x-coaxial-html-bytes", html_bytes.into());
+                            parts
+                                .headers
+                                .insert("x-coaxial-script-bytes", script_bytes.into());
+                        }
+                    }
+
                     return axum::response::Response::from_parts(parts, Body::from(output));
                 }
 
                 let (mut parts, body) = request.into_parts();
-                let request_parts = parts.clone();
+                if !origin_allowed(&parts, &config) {
+                    return StatusCode::FORBIDDEN.into_response();
+                }
+
+                let Some(rng_seed) = query
+                    .get(&config.seed_param)
+                    .and_then(|seed| seed.parse::<u64>().ok())
+                else {
+                    return StatusCode::BAD_REQUEST.into_response();
+                };
+
+                // closures extract from the parts of the upgrade request. it has the same path as the page,
+                // so path params are the same, but we need to remove the seed from the query
+                let mut request_parts = parts.clone();
+                remove_seed_from_query(&mut request_parts, &config.seed_param);
                 let ws = WebSocketUpgrade::from_request_parts(&mut parts, &state)
                     .await
                     .unwrap();
                 let request = Request::from_parts(parts, body);
 
-                let rng_seed: u64 = query
-                    .get("coaxial-seed")
-                    .expect("coaxial-seed param was not present")
-                    .parse()
-                    .expect("seed is not a number");
+                let route = request_parts
+                    .extensions
+                    .get::<MatchedPath>()
+                    .map(|path| path.as_str().to_string())
+                    .unwrap_or_else(|| request_parts.uri.path().to_string());
 
-                // TODO ideally, we'll store the context in a HashMap after the initial request,
-                // which allows us to not re-run the handler here
-                let response = handler
-                    .call(request, state.clone(), Context::new(rng_seed, true))
-                    .await;
+                let reconnect = query.contains_key(RECONNECT_PARAM);
+                let path = request_parts.uri.path().to_string();
+
+                let context = match config.sessions.take::<S>(rng_seed, &path) {
+                    Some(mut context) => {
+                        context.connect();
+                        context
+                    }
+                    // the client has been showing the page, so a new context wouldn't match it
+                    None if reconnect => {
+                        return ws.on_upgrade(|mut socket: WebSocket| async move {
+                            let close = CloseFrame {
+                                code: CLOSE_CONTEXT_EXPIRED,
+                                reason: "context expired".into(),
+                            };
+                            let _ = socket.send(Message::Close(Some(close))).await;
+                        });
+                    }
+                    // the page was served by another server, or the session expired,
+                    // so the context has to be created again
+                    None => match handler
+                        .call(
+                            request,
+                            state.clone(),
+                            Context::with_config(rng_seed, true, &config),
+                        )
+                        .await
+                    {
+                        Ok(response) => response.into_parts().1.context,
+                        Err(rejection) => return rejection,
+                    },
+                };
+
+                // subscribed before upgrading, so reloads sent after the handshake aren't missed
+                let mut reloads = config.reloads.subscribe();
+                let user = config
+                    .presence_user
+                    .as_ref()
+                    .and_then(|user| user(&request_parts));
+
+                ws.on_upgrade(move |mut socket: WebSocket| async move {
+                    // dropped when this future ends, be it by returning or panicking
+                    let _connection = config.live_stats.connect(route);
+                    let _presence = user.map(|user| config.presence.join(user));
 
-                ws.on_upgrade(|mut socket: WebSocket| async move {
-                    let (_parts, body) = response.into_parts();
-
-                    let mut context = body.context;
-
-                    let mut changes = Vec::new();
-                    let mut closure_calls = Vec::new();
-
-                    loop {
-                        select! {
-                            msg = socket.recv() => {
-                                let Some(msg) = msg else {
-                                    return;
-                                };
-
-                                let res = handle_socket_message(
-                                    msg.map_err(|_| ()),
-                                    &context.states,
-                                    &context.closures.call_tx,
-                                    &mut context.events,
-                                )
-                                    .await;
-
-                                match res {
-                                    Ok(_) => {}
-                                    Err(SocketError::SkipMessage) => continue,
-                                    Err(SocketError::Fatal) => return,
-                                };
+                    let mut context = context;
+
+                    // returning from this ends the connection, with whether to keep the context
+                    let keep = async {
+                        // the first frame replaces the skeletons with their values.
+                        // clients that reconnect get every state, since they could have missed changes
+                        let ids = if reconnect {
+                            context.states.ids().collect()
+                        } else {
+                            context.skeletons.clone()
+                        };
+                        if !ids.is_empty() {
+                            let snapshot = context.states.snapshot(&ids);
+                            if socket.send(update_message(snapshot)).await.is_err() {
+                                return true;
                             }
-                            _ = context.states.changes_rx.recv_many(&mut changes, 10000) => {
-                                let mut updates = Vec::new();
-                                std::mem::swap(&mut changes, &mut updates);
+                        }
+
+                        let mut changes = Vec::new();
+                        let mut closure_calls = Vec::new();
+                        let mut invalidations = Vec::new();
+
+                        // pings go out on a fixed schedule, no matter how busy the connection is
+                        let mut heartbeat = tokio::time::interval_at(
+                            tokio::time::Instant::now() + config.heartbeat_interval,
+                            config.heartbeat_interval,
+                        );
+                        // when the pong for the last ping has to arrive by
+                        let mut pong_deadline: Option<tokio::time::Instant> = None;
+                        // with a throttle, changes wait here until `flush_at`, a window after the last update
+                        let mut throttled: Vec<Throttled> = Vec::new();
+                        let mut flush_at: Option<tokio::time::Instant> = None;
+                        let mut last_update: Option<tokio::time::Instant> = None;
 
-                                for (id, _) in &updates {
-                                    context.computed_states.recompute_dependents(*id);
+                        loop {
+                            select! {
+                                msg = socket.recv() => {
+                                    let Some(msg) = msg else {
+                                        return true;
+                                    };
+                                    if let Ok(Message::Pong(_)) = msg {
+                                        pong_deadline = None;
+                                        continue;
+                                    }
+
+                                    let res = handle_socket_message(
+                                        msg.map_err(|_| ()),
+                                        &context.states,
+                                        &context.closures.call_tx,
+                                        &mut context.events,
+                                        &mut context.frames,
+                                        &request_parts,
+                                        &state,
+                                    )
+                                        .await;
+
+                                    match res {
+                                        Ok(_) => {}
+                                        Err(SocketError::SkipMessage) => continue,
+                                        Err(SocketError::Fatal) => return true,
+                                        Err(SocketError::Client(message)) => {
+                                            if socket.send(error_message(message)).await.is_err() {
+                                                return true;
+                                            }
+                                        }
+                                    };
                                 }
+                                _ = context.states.changes_rx.recv_many(&mut changes, 10000) => {
+                                    let mut updates = Vec::new();
+                                    std::mem::swap(&mut changes, &mut updates);
 
-                                let updates = updates.into_iter().map(|(id, v)| (id.to_string(), v)).collect::<Vec<_>>();
+                                    // the rest of a batch could still be on its way
+                                    while updates.last().is_some_and(|(_, change)| change.more) {
+                                        match context.states.changes_rx.recv().await {
+                                            Some(change) => updates.push(change),
+                                            None => break,
+                                        }
+                                    }
 
-                                let out = OutMessage::Update { fields: &updates };
-                                let msg = axum::extract::ws::Message::Text(serde_json::to_string(&out).unwrap());
-                                socket.send(msg).await.unwrap();
-                            }
-                            _ = context.closures.call_rx.recv_many(&mut closure_calls, 10000) => {
-                                let mut closures: Vec<RandomId> = Vec::new();
-                                std::mem::swap(&mut closures, &mut closure_calls);
+                                    let batches = collect_batches(updates, &mut context.states, &mut context.computed_states);
+                                    let list_updates = batches
+                                        .iter()
+                                        .flatten()
+                                        .flat_map(|(id, _)| context.lists.update(*id))
+                                        .collect::<Vec<_>>();
+
+                                    let now = tokio::time::Instant::now();
+                                    let window_end = config.update_throttle.zip(last_update).map(|(window, last)| last + window);
+                                    let messages = match window_end {
+                                        Some(window_end) if flush_at.is_some() || now < window_end => {
+                                            throttle_updates(&mut throttled, batches, list_updates);
+                                            flush_at = Some(window_end);
+                                            vec![]
+                                        }
+                                        _ => {
+                                            last_update = Some(now);
+                                            let mut messages = batch_messages(batches);
+                                            messages.extend(list_updates.into_iter().map(list_update_message));
+                                            messages
+                                        }
+                                    };
+                                    for msg in messages {
+                                        if socket.send(msg).await.is_err() {
+                                            return true;
+                                        }
+                                    }
+                                }
+                                _ = tokio::time::sleep_until(flush_at.unwrap_or_else(tokio::time::Instant::now)), if flush_at.is_some() => {
+                                    flush_at = None;
+                                    last_update = Some(tokio::time::Instant::now());
+                                    for msg in throttled_messages(std::mem::take(&mut throttled)) {
+                                        if socket.send(msg).await.is_err() {
+                                            return true;
+                                        }
+                                    }
+                                }
+                                _ = context.closures.call_rx.recv_many(&mut closure_calls, 10000) => {
+                                    let mut closures: Vec<(RandomId, serde_json::Value)> = Vec::new();
+                                    std::mem::swap(&mut closures, &mut closure_calls);
 
-                                for closure in  &closures {
-                                    context.closures.run(*closure, &request_parts, &state);
+                                    for (closure, args) in closures {
+                                        context.closures.run(closure, args, &request_parts, &state);
+                                    }
+                                }
+                                _ = context.computed_states.invalidate_rx.recv_many(&mut invalidations, 10000) => {
+                                    for id in invalidations.drain(..) {
+                                        context.computed_states.recompute(id);
+                                    }
+                                }
+                                Some(result) = context.closures.join_set.join_next() => {
+                                    if let Some(message) = result.err().and_then(|error| task_panicked(error, "closure", &config)) {
+                                        if socket.send(error_message(message)).await.is_err() {
+                                            return true;
+                                        }
+                                    }
+                                }
+                                Some(result) = context.computed_states.join_set.join_next() => {
+                                    if let Some(message) = result.err().and_then(|error| task_panicked(error, "async computed state", &config)) {
+                                        if socket.send(error_message(message)).await.is_err() {
+                                            return true;
+                                        }
+                                    }
+                                }
+                                _ = heartbeat.tick() => {
+                                    // a ping is already waiting for its pong
+                                    if pong_deadline.is_none() {
+                                        if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                                            return true;
+                                        }
+                                        pong_deadline = Some(tokio::time::Instant::now() + config.heartbeat_timeout);
+                                    }
+                                }
+                                _ = tokio::time::sleep_until(pong_deadline.unwrap_or_else(tokio::time::Instant::now)), if pong_deadline.is_some() => {
+                                    tracing::debug!("client didn't answer the ping, closing the connection");
+                                    // the connection is dead, so there's no client to reconnect
+                                    return false;
+                                }
+                                Some(navigation) = context.navigation_rx.recv() => {
+                                    if socket.send(navigation_message(navigation)).await.is_err() {
+                                        return true;
+                                    }
+                                }
+                                Some(()) = context.reload_rx.recv() => {
+                                    let _ = socket.send(reload_message()).await;
+                                    // the page is loaded again, with a new context
+                                    return false;
+                                }
+                                Ok(()) | Err(RecvError::Lagged(_)) = reloads.recv() => {
+                                    let _ = socket.send(reload_message()).await;
+                                    return false;
+                                }
+                                Some(result) = context.events.join_set.join_next() => {
+                                    if let Some(message) = result.err().and_then(|error| task_panicked(error, "event handler", &config)) {
+                                        if socket.send(error_message(message)).await.is_err() {
+                                            return true;
+                                        }
+                                    }
+                                }
+                                Some(result) = context.frames.join_set.join_next() => {
+                                    if let Some(message) = result.err().and_then(|error| task_panicked(error, "frame handler", &config)) {
+                                        if socket.send(error_message(message)).await.is_err() {
+                                            return true;
+                                        }
+                                    }
                                 }
                             }
                         }
                     }
+                    .await;
+
+                    context.disconnect();
+
+                    // kept so the client can pick up where it left off if it reconnects.
+                    // otherwise it's finished, and dropping it runs its disconnect callbacks
+                    if keep {
+                        config.sessions.insert(rng_seed, path, context);
+                    }
                 })
             }
         },
     )
 }
 
+/// Handle that makes a client reload the page, created with
+/// [`Context::use_reload_client`](crate::context::Context::use_reload_client).
+#[derive(Clone)]
+pub struct ReloadClient {
+    pub(crate) tx: UnboundedSender<()>,
+}
+
+impl ReloadClient {
+    /// Makes the client reload the page.
+    ///
+    /// The page is loaded again from scratch, so it gets a new context and connects again.
+    /// Does nothing if the client has already disconnected.
+    pub fn reload(&self) {
+        let _ = self.tx.send(());
+    }
+}
+
+/// Handle that sends a client to another page, created with
+/// [`Context::use_navigator`](crate::context::Context::use_navigator).
+#[derive(Clone)]
+pub struct Navigator {
+    pub(crate) tx: UnboundedSender<Navigation>,
+}
+
+pub(crate) enum Navigation {
+    /// Loads `url`, like following a link
+    Navigate(String),
+    /// Changes the URL without loading it
+    ReplaceState(String),
+}
+
+impl Navigator {
+    /// Makes the client load `url`, like following a link.
+    ///
+    /// The new page gets its own context. Does nothing if the client has already disconnected.
+    pub fn navigate(&self, url: impl ToString) {
+        let _ = self.tx.send(Navigation::Navigate(url.to_string()));
+    }
+
+    /// Changes the URL shown by the client to `url`, without loading it, like to keep the
+    /// filters of a list in the URL.
+    ///
+    /// It replaces the current history entry. Does nothing if the client has already disconnected.
+    pub fn replace_state(&self, url: impl ToString) {
+        let _ = self.tx.send(Navigation::ReplaceState(url.to_string()));
+    }
+}
+
+/// Counts the live connections to each route.
+///
+/// Get it with [`Config::live_stats`]. Only routes that have that config as a layer are counted.
+/// Routes are identified by the path they were registered with, like `/users/:id`.
+#[derive(Clone, Default)]
+pub struct LiveStats {
+    routes: Arc<Mutex<HashMap<String, Arc<AtomicUsize>>>>,
+}
+
+impl LiveStats {
+    /// Returns the amount of clients connected to `route`
+    pub fn connections(&self, route: &str) -> usize {
+        self.routes
+            .lock()
+            .unwrap()
+            .get(route)
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or_default()
+    }
+
+    /// Returns the amount of clients connected to all routes
+    pub fn total_connections(&self) -> usize {
+        self.routes
+            .lock()
+            .unwrap()
+            .values()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Counts a connection to `route`, until the returned guard is dropped
+    fn connect(&self, route: String) -> ConnectionGuard {
+        let count = self
+            .routes
+            .lock()
+            .unwrap()
+            .entry(route)
+            .or_default()
+            .clone();
+        count.fetch_add(1, Ordering::Relaxed);
+
+        ConnectionGuard { count }
+    }
+}
+
+struct ConnectionGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Contexts of the pages that have been served, waiting for their websocket to connect.
+///
+/// They are identified by the seed of the page and the path it was served from.
+/// Clones share the same sessions.
+#[derive(Clone)]
+pub(crate) struct Sessions {
+    sessions: Arc<Mutex<HashMap<(u64, String), Session>>>,
+    ttl: Duration,
+    /// Whether the task that removes the expired sessions is running
+    sweeping: Arc<AtomicBool>,
+}
+
+struct Session {
+    created: Instant,
+    /// The `Context<S>` of the page. It's type-erased since the config is shared by all routes
+    context: Box<dyn Any + Send>,
+}
+
+impl Sessions {
+    pub(crate) fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    /// Keeps `context` until it's taken or expires
+    fn insert<S: Send + 'static>(&self, seed: u64, path: String, context: Context<S>) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        self.sessions.lock().unwrap().insert(
+            (seed, path),
+            Session {
+                created: Instant::now(),
+                context: Box::new(context),
+            },
+        );
+
+        self.start_sweeping();
+    }
+
+    /// Starts the task that removes the expired sessions every `ttl`, so pages whose websocket
+    /// never connected don't pile up. It stops once the sessions are dropped
+    fn start_sweeping(&self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        if self.sweeping.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let sessions = Arc::downgrade(&self.sessions);
+        let ttl = self.ttl;
+        runtime.spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + ttl, ttl);
+            loop {
+                interval.tick().await;
+                let Some(sessions) = Weak::upgrade(&sessions) else {
+                    return;
+                };
+                Self::remove_expired(&sessions, ttl);
+            }
+        });
+    }
+
+    /// Removes the expired sessions, dropping them without holding the lock.
+    ///
+    /// Dropping a context runs its disconnect callbacks, which could take a while.
+    fn remove_expired(sessions: &Mutex<HashMap<(u64, String), Session>>, ttl: Duration) {
+        let expired = {
+            let mut sessions = sessions.lock().unwrap();
+            let expired = sessions
+                .iter()
+                .filter(|(_, session)| session.created.elapsed() >= ttl)
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+
+            expired
+                .into_iter()
+                .filter_map(|key| sessions.remove(&key))
+                .collect::<Vec<_>>()
+        };
+        drop(expired);
+    }
+
+    /// Removes and returns the context of the page, if it hasn't expired
+    fn take<S: 'static>(&self, seed: u64, path: &str) -> Option<Context<S>> {
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .remove(&(seed, path.to_string()))?;
+
+        if session.created.elapsed() >= self.ttl {
+            return None;
+        }
+
+        match session.context.downcast() {
+            Ok(context) => Some(*context),
+            Err(_) => {
+                tracing::warn!(
+                    seed,
+                    path,
+                    "the context of the page was created for a router with another state, so it's created again"
+                );
+                None
+            }
+        }
+    }
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self {
+            sessions: Default::default(),
+            ttl: Duration::from_secs(60),
+            sweeping: Default::default(),
+        }
+    }
+}
+
+/// Returns the path the websocket should connect to.
+///
+/// This is the path the page was served from, prefixed with the configured base path or the
+/// `X-Forwarded-Prefix` header.
+fn socket_path(request: &Request, config: &Config) -> String {
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path())
+        .unwrap_or_else(|| request.uri().path());
+
+    let prefix = config.base_path.as_deref().or_else(|| {
+        request
+            .headers()
+            .get("X-Forwarded-Prefix")
+            .and_then(|v| v.to_str().ok())
+    });
+
+    join_paths(prefix.unwrap_or_default(), path)
+}
+
+/// Joins the prefix and path, collapsing repeated slashes.
+///
+/// The trailing slash of `path` is kept as is, since routes can be sensitive to it.
+fn join_paths(prefix: &str, path: &str) -> String {
+    let mut output = String::with_capacity(prefix.len() + path.len() + 1);
+
+    for c in "/"
+        .chars()
+        .chain(prefix.chars())
+        .chain("/".chars())
+        .chain(path.chars())
+    {
+        if c == '/' && output.ends_with('/') {
+            continue;
+        }
+        output.push(c);
+    }
+
+    // the separator we added shouldn't turn into a trailing slash
+    if !path.ends_with('/') && output.len() > 1 && output.ends_with('/') {
+        output.pop();
+    }
+
+    output
+}
+
+/// Returns whether the websocket can be opened from the origin of the request
+fn origin_allowed(parts: &Parts, config: &Config) -> bool {
+    let Some(origin) = parts.headers.get("Origin") else {
+        // only browsers are affected by cross-site websocket hijacking, and they always send the origin
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+
+    if let Some(allowed_origins) = &config.allowed_origins {
+        return allowed_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin));
+    }
+
+    // same origin: the host of the origin must be the host the page was requested from
+    let host = parts
+        .headers
+        .get("X-Forwarded-Host")
+        .or_else(|| parts.headers.get("Host"))
+        .and_then(|v| v.to_str().ok());
+    let origin_host = origin
+        .parse::<Uri>()
+        .ok()
+        .and_then(|uri| uri.authority().map(|a| a.as_str().to_string()));
+
+    match (host, origin_host) {
+        (Some(host), Some(origin_host)) => host.eq_ignore_ascii_case(&origin_host),
+        _ => false,
+    }
+}
+
+/// Query param added by clients that are reconnecting after their websocket was dropped
+const RECONNECT_PARAM: &str = "coaxial-reconnect";
+
+/// Close code sent to reconnecting clients whose context is gone, which makes them reload the page
+const CLOSE_CONTEXT_EXPIRED: u16 = 4000;
+
+/// Removes the seed and reconnect params from the query, so it looks like the query of the original request
+fn remove_seed_from_query(parts: &mut Parts, seed_param: &str) {
+    let Some(query) = parts.uri.query() else {
+        return;
+    };
+
+    let query = query
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split('=').next();
+            name != Some(seed_param) && name != Some(RECONNECT_PARAM)
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut path_and_query = parts.uri.path().to_string();
+    if !query.is_empty() {
+        path_and_query.push('?');
+        path_and_query.push_str(&query);
+    }
+
+    let mut uri_parts = parts.uri.clone().into_parts();
+    uri_parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = Uri::from_parts(uri_parts) {
+        parts.uri = uri;
+    }
+}
+
+/// Maximum amount of rounds of dependent recomputes that are sent in the same batch.
+///
+/// Changes from further rounds are left in the channel, and sent in the next batch.
+const MAX_BATCH_ROUNDS: usize = 16;
+
+/// Recomputes the dependents of `updates`, and the dependents of those, collecting each round
+/// of changes made by synchronous computed states into its own batch.
+fn collect_batches(
+    updates: Vec<(RandomId, StateChange)>,
+    states: &mut States,
+    computed_states: &mut ComputedStates,
+) -> Vec<Vec<(RandomId, StateChange)>> {
+    let mut batches = vec![updates];
+
+    for _ in 0..MAX_BATCH_ROUNDS {
+        let last = batches.last().expect("there is always at least one batch");
+        let ids = last.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        computed_states.recompute_dependents_of_all(&ids);
+
+        // synchronous computed states are set while recomputing, so their changes are already queued
+        let mut next = Vec::new();
+        while let Ok(change) = states.changes_rx.try_recv() {
+            next.push(change);
+        }
+
+        if next.is_empty() {
+            break;
+        }
+        batches.push(next);
+    }
+
+    batches
+}
+
+/// Adds `changes` to `pending`, replacing the changes of the same states
+fn coalesce_changes(
+    pending: &mut Vec<(RandomId, StateChange)>,
+    changes: impl IntoIterator<Item = (RandomId, StateChange)>,
+) {
+    for (id, change) in changes {
+        match pending.iter_mut().find(|(pending_id, _)| *pending_id == id) {
+            Some((_, pending_change)) => {
+                // the patch is relative to a value the client never gets
+                *pending_change = StateChange {
+                    patch: None,
+                    ..change
+                };
+            }
+            None => pending.push((id, change)),
+        }
+    }
+}
+
+/// An update held back by the throttle
+enum Throttled {
+    States(Vec<(RandomId, StateChange)>),
+    List(ListUpdate),
+}
+
+/// Adds the updates produced by one round of changes to `pending`, keeping them in order.
+///
+/// Changes are coalesced with the pending ones, unless a list update came in between,
+/// since it could depend on them
+fn throttle_updates(
+    pending: &mut Vec<Throttled>,
+    batches: Vec<Vec<(RandomId, StateChange)>>,
+    list_updates: Vec<ListUpdate>,
+) {
+    let changes = batches.into_iter().flatten();
+    match pending.last_mut() {
+        Some(Throttled::States(states)) => coalesce_changes(states, changes),
+        _ => pending.push(Throttled::States(changes.collect())),
+    }
+    pending.extend(list_updates.into_iter().map(Throttled::List));
+}
+
+/// Builds the messages that send the updates held back by the throttle, to be applied at once
+fn throttled_messages(pending: Vec<Throttled>) -> Vec<Message> {
+    let messages = pending
+        .into_iter()
+        .map(|update| match update {
+            Throttled::States(changes) => update_message(changes),
+            Throttled::List(update) => list_update_message(update),
+        })
+        .collect();
+    applied_together(messages)
+}
+
+/// Builds the messages that send `batches` to the client, to be applied at once
+fn batch_messages(batches: Vec<Vec<(RandomId, StateChange)>>) -> Vec<Message> {
+    applied_together(batches.into_iter().map(update_message).collect())
+}
+
+/// Wraps `messages` in `Begin` and `Commit` when there's more than one,
+/// so the client can apply all of them at once.
+fn applied_together(messages: Vec<Message>) -> Vec<Message> {
+    if messages.len() <= 1 {
+        return messages;
+    }
+
+    let message = |out: &OutMessage| Message::Text(serde_json::to_string(out).unwrap());
+
+    std::iter::once(message(&OutMessage::Begin))
+        .chain(messages)
+        .chain(std::iter::once(message(&OutMessage::Commit)))
+        .collect()
+}
+
+/// Builds the message that sends `updates` to the client
+fn update_message(updates: Vec<(RandomId, StateChange)>) -> Message {
+    let mut fields = Vec::with_capacity(updates.len());
+    let mut values = Vec::new();
+    for (id, change) in updates {
+        let id = id.to_string();
+
+        // only send the typed value when it's different from the display string
+        if let Some(value) = change.value {
+            if value.as_str() != Some(change.display.as_str()) {
+                values.push((id.clone(), value));
+            }
+        }
+
+        let field = match change.patch {
+            Some(patch) => Field::Patch(patch.start, patch.end, patch.text),
+            None => Field::Text(change.display),
+        };
+        fields.push((id, field));
+    }
+
+    let out = OutMessage::Update {
+        fields: &fields,
+        values: &values,
+    };
+    Message::Text(serde_json::to_string(&out).unwrap())
+}
+
+/// Builds the message that sends the operations of a list to the client
+fn list_update_message(update: ListUpdate) -> Message {
+    let out = OutMessage::ListUpdate {
+        element_id: update.element_id.to_string(),
+        ops: &update.ops,
+    };
+    Message::Text(serde_json::to_string(&out).unwrap())
+}
+
+fn reload_message() -> Message {
+    Message::Text(serde_json::to_string(&OutMessage::Reload).unwrap())
+}
+
+fn navigation_message(navigation: Navigation) -> Message {
+    let out = match &navigation {
+        Navigation::Navigate(url) => OutMessage::Navigate { url },
+        Navigation::ReplaceState(url) => OutMessage::ReplaceState { url },
+    };
+    Message::Text(serde_json::to_string(&out).unwrap())
+}
+
+fn error_message(message: String) -> Message {
+    let out = OutMessage::Error { message };
+    Message::Text(serde_json::to_string(&out).unwrap())
+}
+
+/// Logs a task that panicked, and returns the error to send to the client, if any.
+///
+/// `task` is what the task was running, like `closure`, for the log.
+fn task_panicked(error: JoinError, task: &str, config: &Config) -> Option<String> {
+    // tasks are cancelled when the connection closes, and when a newer recompute replaces them
+    if !error.is_panic() {
+        return None;
+    }
+
+    let payload = error.into_panic();
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    tracing::error!(task, message, "task panicked");
+
+    config.panic_error(message)
+}
+
 enum SocketError {
     Fatal,
     SkipMessage,
+    /// The message couldn't be handled, and the client is told why. The connection stays open
+    Client(String),
 }
 
-async fn handle_socket_message(
+async fn handle_socket_message<S: Clone + Send + 'static>(
     msg: Result<Message, ()>,
     states: &States,
-    closure_call_tx: &UnboundedSender<RandomId>,
-    events: &mut Events,
+    closure_call_tx: &UnboundedSender<(RandomId, serde_json::Value)>,
+    events: &mut Events<S>,
+    frames: &mut Frames,
+    parts: &Parts,
+    state: &S,
 ) -> Result<(), SocketError> {
     let msg: InMessage = match msg {
-        Ok(Message::Text(msg)) => serde_json::from_str(&msg).unwrap(),
+        // built-in messages take precedence, anything else goes to the custom handler
+        Ok(Message::Text(msg)) => match serde_json::from_str(&msg) {
+            Ok(msg) => msg,
+            // built-in messages that are malformed are reported instead of being treated as custom ones,
+            // so a buggy client can be noticed
+            Err(error) if is_built_in_message(&msg) => {
+                return Err(SocketError::Client(format!("malformed message: {error}")));
+            }
+            Err(_) => {
+                frames.handle_text(msg);
+                return Ok(());
+            }
+        },
+        Ok(Message::Binary(bytes)) => {
+            frames.handle_binary(bytes);
+            return Ok(());
+        }
         Ok(_) => {
             return Err(SocketError::SkipMessage);
         }
@@ -166,40 +1027,1763 @@ async fn handle_socket_message(
     };
 
     match msg {
-        InMessage::Closure { closure } => {
-            closure_call_tx.send(closure).unwrap();
+        InMessage::Closure { closure, args } => {
+            closure_call_tx.send((closure, args)).unwrap();
         }
-        InMessage::Event { name, params } => {
-            events.handle(name, params);
+        InMessage::Event {
+            name,
+            params,
+            element: None,
+        } => {
+            events.handle(name, params, parts, state);
+        }
+        InMessage::Event {
+            name,
+            params,
+            element: Some(element),
+        } => {
+            events.handle_element(element, name, params, parts, state);
         }
         InMessage::SetState { id, value } => {
-            states.set(id, value);
+            if let Err(error) = states.set(id, value) {
+                return Err(SocketError::Client(error.to_string()));
+            }
         }
     }
 
     Ok(())
 }
 
+/// Returns whether `msg` is a JSON object tagged as one of the variants of [`InMessage`]
+fn is_built_in_message(msg: &str) -> bool {
+    let Ok(serde_json::Value::Object(object)) = serde_json::from_str(msg) else {
+        return false;
+    };
+
+    matches!(
+        object.get("t").and_then(|t| t.as_str()),
+        Some("Closure" | "Event" | "SetState")
+    )
+}
+
 #[derive(serde::Deserialize)]
 #[serde(tag = "t")]
 enum InMessage {
     Closure {
         closure: RandomId,
+        /// Arguments for closures created with `use_closure_with_args`
+        #[serde(default)]
+        args: serde_json::Value,
     },
     Event {
         name: String,
         params: serde_json::Value,
+        /// Id of the element, for events added with `on_element_event`
+        #[serde(default)]
+        element: Option<RandomId>,
     },
     SetState {
         id: RandomId,
         value: serde_json::Value,
     },
 }
+/// New value of a state in an `Update`
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum Field {
+    Text(String),
+    /// (start, end, text), replacing that range of the previous value
+    Patch(usize, usize, String),
+}
+
 #[derive(serde::Serialize)]
 #[serde(tag = "t")]
 enum OutMessage<'a> {
     Update {
         /// (field, value)
-        fields: &'a [(String, String)],
+        fields: &'a [(String, Field)],
+        /// (field, value) for states that send their serialized value
+        #[serde(skip_serializing_if = "<[_]>::is_empty")]
+        values: &'a [(String, serde_json::Value)],
     },
+    /// Starts a batch of updates, which the client holds until `Commit`
+    Begin,
+    /// Applies the updates received since `Begin`
+    Commit,
+    /// Operations to apply, in order, to the children of a keyed list
+    ListUpdate {
+        element_id: String,
+        ops: &'a [ListOp],
+    },
+    /// A message from the client couldn't be handled
+    Error { message: String },
+    /// Makes the client reload the page
+    Reload,
+    /// Makes the client load another page
+    Navigate { url: &'a str },
+    /// Changes the URL of the client, without loading it
+    ReplaceState { url: &'a str },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_paths() {
+        assert_eq!("/", join_paths("", ""));
+        assert_eq!("/", join_paths("", "/"));
+        assert_eq!("/counter", join_paths("", "/counter"));
+        assert_eq!("/counter/", join_paths("", "/counter/"));
+        assert_eq!("/app/counter", join_paths("/app", "/counter"));
+        assert_eq!("/app/counter", join_paths("/app/", "/counter"));
+        assert_eq!("/app/counter/", join_paths("app", "counter/"));
+        assert_eq!("/app/", join_paths("/app", "/"));
+        assert_eq!("/app", join_paths("/app", ""));
+        assert_eq!("/a/b/c", join_paths("//a//", "//b///c"));
+    }
+
+    #[test]
+    fn test_remove_seed_from_query() {
+        fn run(uri: &str) -> String {
+            let (mut parts, _) = Request::builder()
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .into_parts();
+            remove_seed_from_query(&mut parts, "coaxial-seed");
+            parts.uri.to_string()
+        }
+
+        assert_eq!("/counter", run("/counter"));
+        assert_eq!("/counter", run("/counter?coaxial-seed=123"));
+        assert_eq!(
+            "/counter?amount=2",
+            run("/counter?amount=2&coaxial-seed=123")
+        );
+        assert_eq!(
+            "/counter?amount=2&other=3",
+            run("/counter?amount=2&coaxial-seed=123&other=3")
+        );
+        assert_eq!(
+            "/counter?amount=2",
+            run("/counter?amount=2&coaxial-seed=123&coaxial-reconnect=1")
+        );
+    }
+
+    #[test]
+    fn test_socket_path_uses_forwarded_prefix() {
+        let request = Request::builder()
+            .uri("/counter")
+            .header("X-Forwarded-Prefix", "/app")
+            .body(Body::empty())
+            .unwrap();
+
+        assert_eq!("/app/counter", socket_path(&request, &Config::default()));
+        assert_eq!(
+            "/base/counter",
+            socket_path(&request, &Config::default().with_base_path("/base"))
+        );
+    }
+
+    #[test]
+    fn test_origin_allowed() {
+        fn run(headers: &[(&str, &str)], config: &Config) -> bool {
+            let mut request = Request::builder().uri("/counter");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let (parts, _) = request.body(Body::empty()).unwrap().into_parts();
+            origin_allowed(&parts, config)
+        }
+
+        let default = Config::default();
+        assert!(run(&[("Host", "example.com")], &default));
+        assert!(run(
+            &[("Host", "example.com"), ("Origin", "https://example.com")],
+            &default
+        ));
+        assert!(run(
+            &[
+                ("Host", "localhost:3000"),
+                ("Origin", "http://localhost:3000")
+            ],
+            &default
+        ));
+        assert!(!run(
+            &[("Host", "example.com"), ("Origin", "https://evil.com")],
+            &default
+        ));
+        assert!(!run(
+            &[
+                ("Host", "localhost:3000"),
+                ("Origin", "http://localhost:4000")
+            ],
+            &default
+        ));
+        assert!(run(
+            &[
+                ("Host", "internal:8080"),
+                ("X-Forwarded-Host", "example.com"),
+                ("Origin", "https://example.com")
+            ],
+            &default
+        ));
+
+        let allowlist = Config::default().with_allowed_origins(["https://app.example.com/"]);
+        assert!(run(
+            &[
+                ("Host", "example.com"),
+                ("Origin", "https://app.example.com")
+            ],
+            &allowlist
+        ));
+        assert!(!run(
+            &[("Host", "example.com"), ("Origin", "https://example.com")],
+            &allowlist
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_from_other_origin_is_forbidden() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(ctx: Context) -> CoaxialResponse {
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let app = Router::new().route("/", live(handler));
+        let response = app
+            .oneshot(
+                Request::get("/?coaxial-seed=1")
+                    .header("Host", "example.com")
+                    .header("Origin", "https://evil.com")
+                    .header("Connection", "upgrade")
+                    .header("Upgrade", "websocket")
+                    .header("Sec-WebSocket-Version", "13")
+                    .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+    }
+
+    #[tokio::test]
+    async fn test_handler_extractor_rejection_is_returned() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{html::p, CoaxialResponse};
+
+        #[derive(serde::Deserialize)]
+        struct Params {
+            amount: u32,
+        }
+
+        async fn handler(ctx: Context, Query(params): Query<Params>) -> CoaxialResponse {
+            ctx.with(p(params.amount.to_string(), Default::default()))
+        }
+
+        let app = Router::new().route("/", live(handler));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+
+        let response = app
+            .oneshot(Request::get("/?amount=2").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    /// Serves `app` on a free port, for tests that need a real connection
+    async fn serve(app: axum::Router) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    /// Requests the page at `/`, returning the whole response
+    async fn get_page(addr: std::net::SocketAddr) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// Returns the seed of a page returned by [`get_page`]
+    fn page_seed(page: &str) -> &str {
+        page.split("new Coaxial('")
+            .nth(1)
+            .and_then(|rest| rest.split('\'').next())
+            .unwrap()
+    }
+
+    /// Returns the ids of the closures called by a page returned by [`get_page`], in order
+    fn page_closures(page: &str) -> Vec<&str> {
+        page.split("callClosure('")
+            .skip(1)
+            .filter_map(|rest| rest.split('\'').next())
+            .collect()
+    }
+
+    /// Opens the websocket of the page with `seed`, returning the stream after the handshake
+    async fn open_socket(addr: std::net::SocketAddr, seed: &str) -> tokio::net::TcpStream {
+        open_socket_at(addr, &format!("/?coaxial-seed={seed}")).await
+    }
+
+    /// Opens a websocket to `uri`, returning the stream after the handshake
+    async fn open_socket_at(addr: std::net::SocketAddr, uri: &str) -> tokio::net::TcpStream {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {uri} HTTP/1.1\r\nHost: localhost\r\nConnection: upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        // read a byte at a time, so the frames after the headers are left in the stream
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        assert!(response.starts_with(b"HTTP/1.1 101"));
+
+        stream
+    }
+
+    /// Sends a text frame. Frames sent by clients have to be masked
+    async fn send_frame(stream: &mut tokio::net::TcpStream, text: &str) {
+        use tokio::io::AsyncWriteExt;
+
+        assert!(text.len() < 126, "long frames aren't supported");
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x81, 0x80 | text.len() as u8];
+        frame.extend(mask);
+        frame.extend(text.bytes().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        stream.write_all(&frame).await.unwrap();
+    }
+
+    /// Receives a text frame
+    async fn recv_frame(stream: &mut tokio::net::TcpStream) -> String {
+        use tokio::io::AsyncReadExt;
+
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(0x81, header[0], "expected a text frame");
+        let len = match header[1] {
+            126 => stream.read_u16().await.unwrap() as usize,
+            127 => stream.read_u64().await.unwrap() as usize,
+            len => len as usize,
+        };
+
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.unwrap();
+        String::from_utf8(payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handler_runs_once_when_socket_follows_page() {
+        use axum::Router;
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(
+            ctx: Context,
+            Extension(runs): Extension<Arc<AtomicUsize>>,
+        ) -> CoaxialResponse {
+            runs.fetch_add(1, Ordering::SeqCst);
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(Extension(runs.clone())),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let seed = page_seed(&page);
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+
+        let _first = open_socket(addr, seed).await;
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+
+        // the first connection is using the context, so connecting again needs a new one
+        open_socket(addr, seed).await;
+        assert_eq!(2, runs.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_resumes_context() {
+        use axum::Router;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(
+            ctx: Context,
+            Extension(runs): Extension<Arc<AtomicUsize>>,
+        ) -> CoaxialResponse {
+            runs.fetch_add(1, Ordering::SeqCst);
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(Extension(runs.clone())),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let seed = page_seed(&page);
+
+        // close the connection, and wait until the server drops it
+        let mut socket = open_socket(addr, seed).await;
+        socket.write_all(&[0x88, 0x80, 1, 2, 3, 4]).await.unwrap();
+        socket.read_to_end(&mut Vec::new()).await.unwrap();
+
+        let _socket =
+            open_socket_at(addr, &format!("/?coaxial-seed={seed}&coaxial-reconnect=1")).await;
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+
+        // there's no context for this seed, so the client is told to reload
+        let mut socket = open_socket_at(addr, "/?coaxial-seed=1&coaxial-reconnect=1").await;
+        let mut frame = [0; 4];
+        socket.read_exact(&mut frame).await.unwrap();
+        assert_eq!(0x88, frame[0]);
+        assert_eq!(CLOSE_CONTEXT_EXPIRED.to_be_bytes(), frame[2..]);
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_client_is_reloaded() {
+        use axum::Router;
+
+        use crate::{attrs, html::button, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let reload = ctx.use_reload_client();
+            let desync = ctx.use_closure(move || {
+                let reload = reload.clone();
+                async move { reload.reload() }
+            });
+            ctx.with(button("desync", attrs!("onclick" => desync)))
+        }
+
+        let config = Config::default();
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(config.clone().layer()),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let desync = page_closures(&page)[0];
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{desync}"}}"#),
+        )
+        .await;
+        assert_eq!(r#"{"t":"Reload"}"#, recv_frame(&mut socket).await);
+
+        // every client of the config
+        let page = get_page(addr).await;
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+        config.reload_clients();
+        assert_eq!(r#"{"t":"Reload"}"#, recv_frame(&mut socket).await);
+    }
+
+    #[tokio::test]
+    async fn test_closure_navigates_client() {
+        use axum::Router;
+
+        use crate::{attrs, html::button, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let navigator = ctx.use_navigator();
+            let save = ctx.use_closure(move || {
+                let navigator = navigator.clone();
+                async move {
+                    navigator.replace_state("/posts/new?draft=1");
+                    navigator.navigate("/posts");
+                }
+            });
+            ctx.with(button("save", attrs!("onclick" => save)))
+        }
+
+        let addr = serve(Router::new().route("/", live(handler))).await;
+
+        let page = get_page(addr).await;
+        let save = page_closures(&page)[0];
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{save}"}}"#),
+        )
+        .await;
+        assert_eq!(
+            r#"{"t":"ReplaceState","url":"/posts/new?draft=1"}"#,
+            recv_frame(&mut socket).await
+        );
+        assert_eq!(
+            r#"{"t":"Navigate","url":"/posts"}"#,
+            recv_frame(&mut socket).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_without_pong_is_closed() {
+        use axum::Router;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(ctx: Context) -> CoaxialResponse {
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let config =
+            Config::default().with_heartbeat(Duration::from_millis(20), Duration::from_millis(20));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(config.layer()),
+        )
+        .await;
+
+        let mut ping = [0; 2];
+
+        // answering keeps the connection open past the timeout
+        let page = get_page(addr).await;
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+        socket.read_exact(&mut ping).await.unwrap();
+        assert_eq!([0x89, 0], ping);
+        socket.write_all(&[0x8a, 0x80, 1, 2, 3, 4]).await.unwrap();
+        socket.read_exact(&mut ping).await.unwrap();
+        assert_eq!([0x89, 0], ping);
+
+        let page = get_page(addr).await;
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+        socket.read_exact(&mut ping).await.unwrap();
+        assert_eq!([0x89, 0], ping);
+        // the server drops the connection once the pong is late
+        let closed =
+            tokio::time::timeout(Duration::from_secs(1), socket.read_to_end(&mut Vec::new())).await;
+        assert!(closed.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_callbacks_run_when_session_expires() {
+        use axum::Router;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::{attrs, html::button, CoaxialResponse};
+
+        async fn handler(
+            mut ctx: Context,
+            Extension(disconnects): Extension<Arc<AtomicUsize>>,
+        ) -> CoaxialResponse {
+            ctx.on_disconnect(move || {
+                disconnects.fetch_add(1, Ordering::SeqCst);
+            });
+            let wait = ctx.use_closure(std::future::pending::<()>);
+            ctx.with(button("wait", attrs!("onclick" => wait)))
+        }
+
+        let disconnects = Arc::new(AtomicUsize::new(0));
+        // the context is kept after the connection ends, for the client to reconnect to
+        let config = Config::default().with_session_ttl(Duration::from_millis(50));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(Extension(disconnects.clone()))
+                .layer(config.layer()),
+        )
+        .await;
+
+        // the context of the page waits for its websocket
+        let page = get_page(addr).await;
+        assert_eq!(0, disconnects.load(Ordering::SeqCst));
+        let wait = page_closures(&page)[0];
+
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+        // leaves a closure running when the connection ends
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{wait}"}}"#),
+        )
+        .await;
+        assert_eq!(0, disconnects.load(Ordering::SeqCst));
+
+        // the client can still reconnect, so the context isn't finished yet
+        socket.write_all(&[0x88, 0x80, 1, 2, 3, 4]).await.unwrap();
+        socket.read_to_end(&mut Vec::new()).await.unwrap();
+        assert_eq!(0, disconnects.load(Ordering::SeqCst));
+
+        // it is once the kept context expires
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(1, disconnects.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_intervals_stop_when_connection_ends() {
+        use axum::Router;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(
+            mut ctx: Context,
+            Extension(ticks): Extension<Arc<AtomicUsize>>,
+        ) -> CoaxialResponse {
+            ctx.use_interval(Duration::from_millis(5), move || {
+                ticks.fetch_add(1, Ordering::SeqCst);
+            });
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let ticks = Arc::new(AtomicUsize::new(0));
+        // the context is kept after the connection ends, for the client to reconnect to
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(Extension(ticks.clone())),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while ticks.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the interval should tick while connected");
+
+        socket.write_all(&[0x88, 0x80, 1, 2, 3, 4]).await.unwrap();
+        socket.read_to_end(&mut Vec::new()).await.unwrap();
+        let after_close = ticks.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(after_close, ticks.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_presence_tracks_connected_users() {
+        use axum::Router;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::{html::p, presence::PresenceChange, CoaxialResponse};
+
+        async fn handler(ctx: Context) -> CoaxialResponse {
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let config = Config::default().with_presence(|parts| {
+            let query = parts.uri.query()?;
+            query.strip_prefix("user=").map(|user| user.to_string())
+        });
+        let presence = config.presence();
+        let mut changes = presence.subscribe();
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(config.layer()),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        // only websockets count as connections
+        assert!(presence.users().is_empty());
+
+        let mut socket = open_socket_at(
+            addr,
+            &format!("/?user=ada&coaxial-seed={}", page_seed(&page)),
+        )
+        .await;
+        let change = tokio::time::timeout(Duration::from_secs(1), changes.recv()).await;
+        assert_eq!(
+            PresenceChange::Joined("ada".to_string()),
+            change.unwrap().unwrap()
+        );
+        assert!(presence.is_online("ada"));
+
+        socket.write_all(&[0x88, 0x80, 1, 2, 3, 4]).await.unwrap();
+        socket.read_to_end(&mut Vec::new()).await.unwrap();
+        let change = tokio::time::timeout(Duration::from_secs(1), changes.recv()).await;
+        assert_eq!(
+            PresenceChange::Left("ada".to_string()),
+            change.unwrap().unwrap()
+        );
+        assert!(presence.users().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_socket_without_seed_is_bad_request() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(ctx: Context) -> CoaxialResponse {
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let request = Request::get("/?coaxial-seed=nope")
+            .header("Connection", "upgrade")
+            .header("Upgrade", "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .body(Body::empty())
+            .unwrap();
+        let response = Router::new()
+            .route("/", live(handler))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[tokio::test]
+    async fn test_connection_survives_panicking_closure() {
+        use axum::Router;
+
+        use crate::{
+            attrs,
+            html::{button, div, p},
+            CoaxialResponse,
+        };
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let count = ctx.use_state(0u32);
+            let boom = ctx.use_closure(|| async { panic!("boom") });
+            let add = ctx.use_closure(move || async move { count.modify(|count| count + 1) });
+
+            ctx.with(div(
+                vec![
+                    p(count, Default::default()).into(),
+                    button("boom", attrs!("onclick" => boom)).into(),
+                    button("add", attrs!("onclick" => add)).into(),
+                ],
+                Default::default(),
+            ))
+        }
+
+        let config =
+            Config::default().with_panic_handler(|message| Some(format!("panicked: {message}")));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(config.layer()),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let closures = page_closures(&page);
+        let [boom, add] = closures[..] else {
+            panic!("expected two closures, got {closures:?}");
+        };
+
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{boom}"}}"#),
+        )
+        .await;
+        assert_eq!(
+            r#"{"t":"Error","message":"panicked: boom"}"#,
+            recv_frame(&mut socket).await
+        );
+
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{add}"}}"#),
+        )
+        .await;
+        let update = recv_frame(&mut socket).await;
+        assert!(update.starts_with(r#"{"t":"Update""#));
+        assert!(update.ends_with(r#""1"]]}"#));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_async_computed_state_is_reported() {
+        use axum::Router;
+
+        use crate::{
+            attrs,
+            html::{button, div, p},
+            CoaxialResponse,
+        };
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let count = ctx.use_state(0u32);
+            let _checked = ctx
+                .use_computed_async(count, |count| {
+                    let count = *count;
+                    async move {
+                        assert!(count == 0, "count is {count}");
+                        count
+                    }
+                })
+                .await;
+            let add = ctx.use_closure(move || async move { count.modify(|count| count + 1) });
+
+            ctx.with(div(
+                vec![
+                    p(count, Default::default()).into(),
+                    button("add", attrs!("onclick" => add)).into(),
+                ],
+                Default::default(),
+            ))
+        }
+
+        let config =
+            Config::default().with_panic_handler(|message| Some(format!("panicked: {message}")));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(config.layer()),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let add = page_closures(&page)[0];
+
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{add}"}}"#),
+        )
+        .await;
+        let update = recv_frame(&mut socket).await;
+        assert!(update.starts_with(r#"{"t":"Update""#));
+        assert_eq!(
+            r#"{"t":"Error","message":"panicked: count is 1"}"#,
+            recv_frame(&mut socket).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_panicking_frame_handler_is_reported() {
+        use axum::Router;
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            ctx.on_text(|text| async move { panic!("unknown command {text}") });
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let config =
+            Config::default().with_panic_handler(|message| Some(format!("panicked: {message}")));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(config.layer()),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+
+        send_frame(&mut socket, "jump").await;
+        assert_eq!(
+            r#"{"t":"Error","message":"panicked: unknown command jump"}"#,
+            recv_frame(&mut socket).await
+        );
+    }
+
+    #[test]
+    fn test_sessions_expire() {
+        let mut sessions = Sessions::default();
+        sessions.insert(1, "/".to_string(), Context::<()>::new(1, false));
+        assert!(sessions.take::<()>(1, "/other").is_none());
+        assert!(sessions.take::<()>(1, "/").is_some());
+        assert!(sessions.take::<()>(1, "/").is_none());
+
+        // contexts for a different router state are not used
+        sessions.insert(2, "/".to_string(), Context::<()>::new(2, false));
+        assert!(sessions.take::<u32>(2, "/").is_none());
+
+        sessions.set_ttl(Duration::ZERO);
+        sessions.insert(3, "/".to_string(), Context::<()>::new(3, false));
+        assert!(sessions.take::<()>(3, "/").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_sessions_are_removed() {
+        let mut sessions = Sessions::default();
+        sessions.set_ttl(Duration::from_millis(40));
+
+        sessions.insert(1, "/".to_string(), Context::<()>::new(1, false));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        sessions.insert(2, "/".to_string(), Context::<()>::new(2, false));
+        // a single task removes them, no matter how many are added
+        assert!(sessions.sweeping.load(Ordering::Relaxed));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(1, sessions.sessions.lock().unwrap().len());
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(sessions.sessions.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_live_stats_count_connections() {
+        let stats = LiveStats::default();
+
+        let first = stats.connect("/counter".to_string());
+        let second = stats.connect("/counter".to_string());
+        let _other = stats.connect("/other".to_string());
+        assert_eq!(2, stats.connections("/counter"));
+        assert_eq!(3, stats.total_connections());
+        assert_eq!(0, stats.connections("/missing"));
+
+        drop(first);
+        assert_eq!(1, stats.connections("/counter"));
+
+        // connections that panic are also removed
+        let result = std::panic::catch_unwind(move || {
+            let _second = second;
+            panic!("connection panicked");
+        });
+        assert!(result.is_err());
+        assert_eq!(0, stats.connections("/counter"));
+        assert_eq!(1, stats.total_connections());
+    }
+
+    #[tokio::test]
+    async fn test_custom_frames_are_handled() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let binary_tx = tx.clone();
+        ctx.on_binary(move |bytes| {
+            let tx = binary_tx.clone();
+            async move { tx.send(format!("binary {bytes:?}")).unwrap() }
+        });
+        ctx.on_text(move |text| {
+            let tx = tx.clone();
+            async move { tx.send(format!("text {text}")).unwrap() }
+        });
+        let state = ctx.use_state(0u32);
+
+        let (parts, _) = Request::new(Body::empty()).into_parts();
+        let messages = [
+            Message::Binary(vec![1, 2, 3]),
+            Message::Text("move left".to_string()),
+            // built-in messages are not sent to the custom handler
+            Message::Text(format!(
+                r#"{{"t":"SetState","id":"{}","value":5}}"#,
+                state.id
+            )),
+        ];
+        for msg in messages {
+            let res = handle_socket_message(
+                Ok(msg),
+                &ctx.states,
+                &ctx.closures.call_tx,
+                &mut ctx.events,
+                &mut ctx.frames,
+                &parts,
+                &(),
+            )
+            .await;
+            assert!(res.is_ok());
+        }
+
+        assert_eq!("binary [1, 2, 3]", rx.recv().await.unwrap());
+        assert_eq!("text move left", rx.recv().await.unwrap());
+        assert_eq!(5, *state.get());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_state_is_applied_and_sent_back() {
+        let mut ctx = Context::<()>::new(0, true);
+        let name = ctx.use_state(String::new());
+        let amount = ctx.use_state(0u32);
+
+        let (parts, _) = Request::new(Body::empty()).into_parts();
+        // what `window.Coaxial.setState` sends, with the values of bound inputs
+        for (id, value) in [(name.id, "\"Ann\""), (amount.id, "\"42\"")] {
+            let res = handle_socket_message(
+                Ok(Message::Text(format!(
+                    r#"{{"t":"SetState","id":"{id}","value":{value}}}"#
+                ))),
+                &ctx.states,
+                &ctx.closures.call_tx,
+                &mut ctx.events,
+                &mut ctx.frames,
+                &parts,
+                &(),
+            )
+            .await;
+            assert!(res.is_ok());
+        }
+
+        assert_eq!("Ann", *name.get());
+        assert_eq!(42, *amount.get());
+
+        // the changes go through the same channel as changes made on the server
+        let mut changes = Vec::new();
+        ctx.states.changes_rx.recv_many(&mut changes, 10).await;
+        let Message::Text(text) = update_message(changes) else {
+            panic!("expected a text message");
+        };
+        assert_eq!(
+            format!(
+                r#"{{"t":"Update","fields":[["{}","Ann"],["{}","42"]]}}"#,
+                name.id, amount.id
+            ),
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttled_updates_are_coalesced() {
+        use axum::Router;
+
+        use crate::{attrs, html::button, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let count = ctx.use_state(0u32);
+            let add = ctx.use_closure(move || async move {
+                for i in 1..=3 {
+                    count.set(i);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            });
+            ctx.with(button(count, attrs!("onclick" => add)))
+        }
+
+        let config = Config::default().with_update_throttle(Duration::from_millis(200));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(config.layer()),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let add = page_closures(&page)[0];
+        let count = page
+            .split("onStateChange(['")
+            .nth(1)
+            .and_then(|rest| rest.split('\'').next())
+            .expect("page has a state");
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{add}"}}"#),
+        )
+        .await;
+        // the first update isn't delayed, and the next two are sent together once the window is over
+        assert_eq!(
+            format!(r#"{{"t":"Update","fields":[["{count}","1"]]}}"#),
+            recv_frame(&mut socket).await
+        );
+        assert_eq!(
+            format!(r#"{{"t":"Update","fields":[["{count}","3"]]}}"#),
+            recv_frame(&mut socket).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttled_updates_keep_list_order() {
+        use axum::Router;
+
+        use crate::{
+            attrs,
+            html::{button, div, li, ul},
+            CoaxialResponse,
+        };
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let count = ctx.use_state(0u32);
+            let items = ctx.use_list_state(Vec::<u32>::new());
+            let list = ctx.use_list(
+                ul((), Default::default()),
+                items,
+                |item| *item,
+                |item| li(item.to_string(), Default::default()),
+            );
+            let add = ctx.use_closure(move || async move {
+                count.set(1);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                items.set(vec![1]);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                count.set(2);
+            });
+            ctx.with(div(
+                vec![button(count, attrs!("onclick" => add)).into(), list.into()],
+                Default::default(),
+            ))
+        }
+
+        let config = Config::default().with_update_throttle(Duration::from_millis(100));
+        let addr = serve(
+            Router::new()
+                .route("/", live(handler))
+                .layer(config.layer()),
+        )
+        .await;
+
+        let page = get_page(addr).await;
+        let add = page_closures(&page)[0];
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{add}"}}"#),
+        )
+        .await;
+        assert!(recv_frame(&mut socket)
+            .await
+            .starts_with(r#"{"t":"Update""#));
+
+        // the held back updates are applied together, with the list update where it happened
+        let frames = [
+            recv_frame(&mut socket).await,
+            recv_frame(&mut socket).await,
+            recv_frame(&mut socket).await,
+            recv_frame(&mut socket).await,
+            recv_frame(&mut socket).await,
+        ];
+        assert_eq!(r#"{"t":"Begin"}"#, frames[0]);
+        assert!(frames[1].starts_with(r#"{"t":"Update""#));
+        assert!(frames[1].ends_with(r#""1"]]}"#));
+        assert!(frames[2].starts_with(r#"{"t":"ListUpdate""#));
+        assert!(frames[3].ends_with(r#""2"]]}"#));
+        assert_eq!(r#"{"t":"Commit"}"#, frames[4]);
+    }
+
+    #[test]
+    fn test_coalesced_changes_drop_patches() {
+        let change = |display: &str, patch: Option<crate::states::TextPatch>| StateChange {
+            display: display.to_string(),
+            value: None,
+            patch,
+            more: false,
+        };
+        let a = RandomId::from_str("aaaaaaaa");
+        let b = RandomId::from_str("bbbbbbbb");
+
+        let mut pending = vec![(a, change("x", None))];
+        coalesce_changes(
+            &mut pending,
+            [
+                (b, change("y", None)),
+                (
+                    a,
+                    change("xz", Some(crate::states::TextPatch::between("x", "xz"))),
+                ),
+            ],
+        );
+        assert_eq!(
+            vec![(a, change("xz", None)), (b, change("y", None))],
+            pending
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malformed_messages_keep_the_connection_open() {
+        use axum::Router;
+
+        use crate::{attrs, html::button, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let count = ctx.use_state(0u32);
+            let add = ctx.use_closure(move || async move {
+                count.modify(|count| count + 1);
+            });
+            ctx.with(button(count, attrs!("onclick" => add)))
+        }
+
+        let addr = serve(Router::new().route("/", live(handler))).await;
+
+        let page = get_page(addr).await;
+        let add = page_closures(&page)[0];
+        let mut socket = open_socket(addr, page_seed(&page)).await;
+
+        // not json, so it goes to the custom text handler, which there isn't
+        send_frame(&mut socket, r#"{"t":"Closure""#).await;
+        // json, but not a valid built-in message
+        send_frame(&mut socket, r#"{"t":"Event","name":1}"#).await;
+        let error = recv_frame(&mut socket).await;
+        assert!(
+            error.starts_with(r#"{"t":"Error","message":"malformed message: "#),
+            "{error}"
+        );
+
+        send_frame(
+            &mut socket,
+            &format!(r#"{{"t":"Closure","closure":"{add}"}}"#),
+        )
+        .await;
+        assert!(recv_frame(&mut socket).await.contains(r#""1"]"#));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_state_is_an_error() {
+        let mut ctx = Context::<()>::new(0, true);
+        let state = ctx.use_state(0u32);
+
+        // a state from a previous render, which this context doesn't have
+        let stale = RandomId::from_rng(&mut ctx.rng);
+        assert_eq!(
+            Err(crate::states::SetStateError::NotFound(stale)),
+            ctx.states.set(stale, serde_json::json!(5))
+        );
+
+        let (parts, _) = Request::new(Body::empty()).into_parts();
+        let res = handle_socket_message(
+            Ok(Message::Text(format!(
+                r#"{{"t":"SetState","id":"{stale}","value":5}}"#
+            ))),
+            &ctx.states,
+            &ctx.closures.call_tx,
+            &mut ctx.events,
+            &mut ctx.frames,
+            &parts,
+            &(),
+        )
+        .await;
+        let Err(SocketError::Client(message)) = res else {
+            panic!("expected a client error");
+        };
+        assert_eq!(format!("state {stale} not found"), message);
+        assert_eq!(0, *state.get());
+
+        let Message::Text(text) = error_message(message) else {
+            panic!("expected a text message");
+        };
+        assert_eq!(
+            format!(r#"{{"t":"Error","message":"state {stale} not found"}}"#),
+            text
+        );
+    }
+
+    #[test]
+    fn test_dependent_changes_are_batched() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(1u32);
+        let plus_one = ctx.use_computed(state, |state| *state + 1);
+        let _plus_two = ctx.use_computed(plus_one.0, |plus_one| *plus_one + 1);
+
+        state.set(2);
+        let first = ctx.states.changes_rx.try_recv().unwrap();
+
+        let batches = collect_batches(vec![first], &mut ctx.states, &mut ctx.computed_states);
+        let displays = batches
+            .iter()
+            .map(|batch| {
+                batch
+                    .iter()
+                    .map(|(_, change)| change.display.as_str())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![vec!["2"], vec!["3"], vec!["4"]], displays);
+
+        let messages = batch_messages(batches)
+            .into_iter()
+            .map(|msg| msg.into_text().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(5, messages.len());
+        assert_eq!(r#"{"t":"Begin"}"#, messages[0]);
+        assert!(messages[1].starts_with(r#"{"t":"Update""#));
+        assert_eq!(r#"{"t":"Commit"}"#, messages[4]);
+    }
+
+    #[test]
+    fn test_batched_changes_recompute_once() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let a = ctx.use_state(1u32);
+        let b = ctx.use_state(2u32);
+        let computes = Arc::new(AtomicUsize::new(0));
+        let counter = computes.clone();
+        let sum = ctx.use_computed((a, b), move |(a, b)| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            *a + *b
+        });
+        let before = computes.load(Ordering::SeqCst);
+
+        crate::batch(|| {
+            a.set(10);
+            b.set(20);
+        });
+        let mut changes = Vec::new();
+        while let Ok(change) = ctx.states.changes_rx.try_recv() {
+            changes.push(change);
+        }
+        assert_eq!(2, changes.len());
+        assert!(changes[0].1.more);
+        assert!(!changes[1].1.more);
+
+        let batches = collect_batches(changes, &mut ctx.states, &mut ctx.computed_states);
+        assert_eq!(1, computes.load(Ordering::SeqCst) - before);
+        assert_eq!(2, batches.len());
+        assert_eq!(30, *sum.get());
+    }
+
+    #[test]
+    fn test_batch_sends_last_change_of_each_state() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let a = ctx.use_state(1u32);
+        let b = ctx.use_state(1u32);
+        crate::batch(|| {
+            a.set(2);
+            b.set(2);
+            crate::batch(|| a.set(3));
+        });
+
+        let first = ctx.states.changes_rx.try_recv().unwrap();
+        let second = ctx.states.changes_rx.try_recv().unwrap();
+        assert!(ctx.states.changes_rx.try_recv().is_err());
+        assert_eq!((a.id, "3"), (first.0, first.1.display.as_str()));
+        assert_eq!((b.id, "2"), (second.0, second.1.display.as_str()));
+    }
+
+    #[test]
+    fn test_single_batch_is_not_wrapped() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(1u32);
+        state.set(2);
+        let first = ctx.states.changes_rx.try_recv().unwrap();
+
+        let batches = collect_batches(vec![first], &mut ctx.states, &mut ctx.computed_states);
+        let messages = batch_messages(batches);
+        assert_eq!(1, messages.len());
+    }
+
+    #[test]
+    fn test_diffed_state_update_sends_patch() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let log = ctx.use_state_diffed("a long line of the log".to_string());
+        log.set("a long line of the log".to_string());
+        log.modify(|log| format!("{log}, and more"));
+        let first = ctx.states.changes_rx.try_recv().unwrap();
+        let second = ctx.states.changes_rx.try_recv().unwrap();
+
+        let Message::Text(message) = update_message(vec![first, second]) else {
+            panic!("update messages are text");
+        };
+        assert_eq!(
+            format!(
+                r#"{{"t":"Update","fields":[["{id}","a long line of the log"],["{id}",[22,22,", and more"]]]}}"#,
+                id = log.id
+            ),
+            message
+        );
+    }
+
+    #[tokio::test]
+    async fn test_html_attributes_are_added_to_root() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{attrs, html::p, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let dir = ctx.use_state("ltr".to_string());
+            ctx.set_html_attrs(attrs!("lang" => "en", "dir" => dir));
+
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let app = Router::new().route("/", live(handler));
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.starts_with("<!DOCTYPE html><html dir=\"ltr\" lang=\"en\" coax-id=\""));
+        assert!(body.contains("el[v0 === '' ? 'removeAttribute' : 'setAttribute']('dir', v0)"));
+    }
+
+    #[tokio::test]
+    async fn test_title_and_head_are_added_to_head() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{
+            attrs,
+            html::{meta, p},
+            CoaxialResponse,
+        };
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            ctx.set_title("first");
+            ctx.push_head(meta(attrs!("name" => "description", "content" => "a page")));
+            // the last title wins
+            ctx.set_title("second & last");
+
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let app = Router::new().route("/", live(handler));
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains(
+            "<head><title>second &amp; last</title><meta content=\"a page\" name=\"description\" /></head>"
+        ));
+        assert!(!body.contains("<title>first"));
+    }
+
+    #[tokio::test]
+    async fn test_html_attributes_override_the_layout() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{
+            attrs,
+            html::{body, head, html, p, Content},
+            CoaxialResponse,
+        };
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let lang = ctx.use_state("en".to_string());
+            ctx.set_html_attrs(attrs!("lang" => lang));
+
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let config = Config::with_layout(|content, scripts, mut context| {
+            html(
+                Content::List(vec![
+                    head(Content::List(context.take_head()), Default::default()).into(),
+                    body(
+                        Content::List(vec![content.into(), scripts.into()]),
+                        Default::default(),
+                    )
+                    .into(),
+                ]),
+                attrs!("lang" => "es", "data-theme" => "dark"),
+            )
+        });
+        let app = Router::new()
+            .route("/", live(handler))
+            .layer(config.layer());
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let id = body
+            .split("coax-id=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap();
+        assert!(body.starts_with(&format!(
+            "<!DOCTYPE html><html data-theme=\"dark\" lang=\"en\" coax-id=\"{id}\">"
+        )));
+        assert!(body.contains(&format!("document.querySelector('[coax-id=\"{id}\"]')")));
+    }
+
+    #[tokio::test]
+    async fn test_nonce_is_added_to_scripts() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{
+            attrs,
+            html::{body, html, p, style, Content},
+            CoaxialResponse,
+        };
+
+        #[derive(Clone)]
+        struct CspNonce(String);
+
+        async fn handler(ctx: Context) -> CoaxialResponse {
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let config = Config::with_layout(|content, scripts, context| {
+            let nonce = context.nonce.unwrap_or_default();
+            html(
+                body(
+                    Content::List(vec![
+                        style("p { color: red }", attrs!("nonce" => nonce)).into(),
+                        content.into(),
+                        scripts.into(),
+                    ]),
+                    Default::default(),
+                ),
+                Default::default(),
+            )
+        })
+        .with_nonce(|parts| {
+            parts
+                .extensions
+                .get::<CspNonce>()
+                .map(|nonce| nonce.0.clone())
+        });
+        let app = Router::new()
+            .route("/", live(handler))
+            .layer(config.layer());
+
+        let response = app
+            .oneshot(
+                Request::get("/")
+                    .extension(CspNonce("r4nd0m".to_string()))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("<style nonce=\"r4nd0m\">"));
+        assert!(body.contains("<script nonce=\"r4nd0m\">"));
+    }
+
+    #[tokio::test]
+    async fn test_static_page_for_bots() {
+        use axum::{http::header::USER_AGENT, Router};
+        use tower::ServiceExt;
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let count = ctx.use_state(3u32);
+            ctx.with(p(count, Default::default()))
+        }
+
+        let config = Config::default().with_serve_static(|parts| {
+            parts
+                .headers
+                .get(USER_AGENT)
+                .and_then(|agent| agent.to_str().ok())
+                .is_some_and(|agent| agent.contains("Googlebot"))
+        });
+        let app = Router::new()
+            .route("/", live(handler))
+            .layer(config.layer());
+
+        let get = |user_agent: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::get("/")
+                            .header(USER_AGENT, user_agent)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let page = get("Mozilla/5.0 (compatible; Googlebot/2.1)").await;
+        assert_eq!(
+            "<!DOCTYPE html><html><head></head><body><p>3</p></body></html>",
+            page
+        );
+
+        let page = get("Mozilla/5.0 (X11; Linux x86_64)").await;
+        assert!(page.contains("<script"));
+        assert!(page.contains("<p coax-id=\""));
+    }
+
+    #[tokio::test]
+    async fn test_response_headers_are_added() {
+        use axum::{
+            http::{header, HeaderValue},
+            Router,
+        };
+        use tower::ServiceExt;
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            ctx.response_header(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            ctx.response_header(header::SET_COOKIE, HeaderValue::from_static("a=1"));
+            ctx.response_header(header::SET_COOKIE, HeaderValue::from_static("b=2"));
+
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let app = Router::new().route("/", live(handler));
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let headers = response.headers();
+        assert_eq!("no-store", headers[header::CACHE_CONTROL]);
+        assert_eq!(
+            vec!["a=1", "b=2"],
+            headers
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    async fn test_size_headers_in_debug_builds() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{html::p, CoaxialResponse};
+
+        async fn handler(mut ctx: Context) -> CoaxialResponse {
+            let count = ctx.use_state(3u32);
+            ctx.with(p(count, Default::default()))
+        }
+
+        let app = Router::new().route("/", live(handler));
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let header =
+            |name: &str| -> usize { response.headers()[name].to_str().unwrap().parse().unwrap() };
+        let html_bytes = header("X-Coaxial-Html-Bytes");
+        let script_bytes = header("X-Coaxial-Script-Bytes");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), html_bytes);
+        assert!(script_bytes > 0);
+        assert!(script_bytes < html_bytes);
+    }
+
+    #[tokio::test]
+    async fn test_layout_receives_request_parts() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{
+            html::{body, html, p},
+            CoaxialResponse,
+        };
+
+        async fn handler(ctx: Context) -> CoaxialResponse {
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let config = Config::with_layout(|content, adapter, context| {
+            html(
+                vec![
+                    p(context.parts.uri.path().to_string(), Default::default()).into(),
+                    body(vec![content.into(), adapter.into()], Default::default()).into(),
+                ],
+                Default::default(),
+            )
+        });
+        let app = Router::new()
+            .route("/page", live(handler))
+            .layer(config.layer());
+        let response = app
+            .oneshot(Request::get("/page").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.starts_with("<!DOCTYPE html><html><p>/page</p><body>"));
+    }
+
+    #[tokio::test]
+    async fn test_async_layout_is_awaited() {
+        use axum::Router;
+        use tower::ServiceExt;
+
+        use crate::{
+            html::{body, html, nav, p},
+            CoaxialResponse,
+        };
+
+        async fn handler(ctx: Context) -> CoaxialResponse {
+            ctx.with(p("hi", Default::default()))
+        }
+
+        let config = Config::with_async_layout(|content, adapter, _context| async move {
+            tokio::task::yield_now().await;
+            let menu = nav("menu", Default::default());
+            html(
+                vec![body(
+                    vec![menu.into(), content.into(), adapter.into()],
+                    Default::default(),
+                )
+                .into()],
+                Default::default(),
+            )
+        });
+        let app = Router::new()
+            .route("/", live(handler))
+            .layer(config.layer());
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.starts_with("<!DOCTYPE html><html><body><nav>menu</nav><p>hi</p>"));
+    }
 }
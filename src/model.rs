@@ -0,0 +1,123 @@
+use crate::context::Context;
+
+/// A struct whose fields can be turned into individual states.
+///
+/// Usually implemented with the [`model!`](crate::model!) macro.
+pub trait Model {
+    /// Struct holding one `State` per field
+    type States: Copy;
+
+    fn use_states<S>(self, ctx: &mut Context<S>) -> Self::States;
+}
+
+/// Declares a struct, plus a second struct holding a `State` for each of its fields.
+///
+/// Each field becomes its own state, so setting one field only updates that field's bindings.
+/// Nested structs and collections are not split up: they are stored as a single state,
+/// and updating them replaces the whole value.
+///
+/// Attributes on the fields, like docs or `#[serde(...)]`, are only added to the first struct.
+///
+/// ```ignore
+/// model! {
+///     #[derive(Default)]
+///     struct Counter => CounterStates {
+///         count: i32,
+///         clicks: u32,
+///     }
+/// }
+///
+/// let counter = ctx.use_model(Counter::default());
+/// counter.count.set(1);
+/// ```
+#[macro_export]
+macro_rules! model {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident => $states:ident {
+            $( $(#[$field_meta:meta])* $field_vis:vis $field:ident : $ty:ty ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $( $(#[$field_meta])* $field_vis $field: $ty, )*
+        }
+
+        #[derive(Clone, Copy)]
+        $vis struct $states {
+            $( $field_vis $field: $crate::State<$ty>, )*
+        }
+
+        impl $crate::model::Model for $name {
+            type States = $states;
+
+            #[track_caller]
+            fn use_states<S>(self, ctx: &mut $crate::context::Context<S>) -> Self::States {
+                $states {
+                    $( $field: ctx.use_state(self.$field), )*
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::Context;
+
+    model! {
+        #[derive(Default)]
+        struct Counter => CounterStates {
+            count: i32,
+            label: String,
+        }
+    }
+
+    #[test]
+    fn test_model_fields_are_states() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let counter = ctx.use_model(Counter {
+            count: 3,
+            label: "hello".to_string(),
+        });
+
+        assert_eq!(3, *counter.count.get());
+        assert_eq!("hello", *counter.label.get());
+    }
+
+    #[test]
+    fn test_setting_a_field_only_changes_that_field() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let counter = ctx.use_model(Counter::default());
+        counter.count.set(1);
+
+        let (id, change) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!(counter.count.id, id);
+        assert_eq!("1", change.display);
+        assert!(ctx.states.changes_rx.try_recv().is_err());
+    }
+
+    model! {
+        #[derive(serde::Deserialize)]
+        struct Profile => ProfileStates {
+            /// Shown next to the avatar
+            #[serde(rename = "displayName")]
+            name: String,
+            #[serde(default)]
+            age: u32,
+        }
+    }
+
+    #[test]
+    fn test_field_attributes_are_kept() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let profile: Profile = serde_json::from_str(r#"{"displayName":"ferris"}"#).unwrap();
+        let profile = ctx.use_model(profile);
+
+        assert_eq!("ferris", *profile.name.get());
+        assert_eq!(0, *profile.age.get());
+    }
+}
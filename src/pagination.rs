@@ -0,0 +1,112 @@
+use crate::{closures::Closure, computed::ComputedState, states::State};
+
+/// States for paginating a list of `total` items, with closures to move between pages.
+///
+/// Created with [`Context::use_pagination`](crate::context::Context::use_pagination).
+#[derive(Clone, Copy)]
+pub struct Pagination {
+    pub(crate) page: State<usize>,
+    pub(crate) total_pages: ComputedState<usize>,
+    pub(crate) offset: ComputedState<usize>,
+    pub(crate) prev: Closure,
+    pub(crate) next: Closure,
+}
+
+impl Pagination {
+    /// The current page, starting at 0.
+    ///
+    /// It can be set directly, like for links to a specific page, but it's not clamped then.
+    pub fn page(&self) -> State<usize> {
+        self.page
+    }
+
+    /// The amount of pages. An empty list still has one page.
+    pub fn total_pages(&self) -> ComputedState<usize> {
+        self.total_pages
+    }
+
+    /// The index of the first item of the current page.
+    ///
+    /// If the current page is past the last one, like after items are removed, this is the offset of the last page.
+    pub fn offset(&self) -> ComputedState<usize> {
+        self.offset
+    }
+
+    /// Goes to the previous page, staying on the first one if it's already there
+    pub fn prev(&self) -> Closure {
+        self.prev
+    }
+
+    /// Goes to the next page, staying on the last one if it's already there
+    pub fn next(&self) -> Closure {
+        self.next
+    }
+}
+
+/// Returns the amount of pages needed for `total` items
+pub(crate) fn page_count(total: usize, page_size: usize) -> usize {
+    total.div_ceil(page_size).max(1)
+}
+
+/// Returns `page`, moved back onto the last page if it's past it
+pub(crate) fn clamp_page(page: usize, total: usize, page_size: usize) -> usize {
+    page.min(page_count(total, page_size) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{request::Parts, Request};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::context::Context;
+
+    fn parts() -> Parts {
+        let (parts, _) = Request::new(()).into_parts();
+        parts
+    }
+
+    async fn click(ctx: &mut Context, closure: Closure) {
+        ctx.closures.run(closure.id, Value::Null, &parts(), &());
+        ctx.closures.join_set.join_next().await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_page_count() {
+        assert_eq!(1, page_count(0, 10));
+        assert_eq!(1, page_count(10, 10));
+        assert_eq!(2, page_count(11, 10));
+        assert_eq!(3, clamp_page(7, 35, 10));
+        assert_eq!(0, clamp_page(2, 0, 10));
+    }
+
+    #[tokio::test]
+    async fn test_prev_and_next_clamp_at_bounds() {
+        let mut ctx = Context::<()>::new(0, true);
+        let total = ctx.use_state(25usize);
+        let pagination = ctx.use_pagination(total, 10);
+
+        assert_eq!(3, *pagination.total_pages().get());
+
+        click(&mut ctx, pagination.prev()).await;
+        assert_eq!(0, *pagination.page().get());
+
+        for expected in [1, 2, 2] {
+            click(&mut ctx, pagination.next()).await;
+            assert_eq!(expected, *pagination.page().get());
+        }
+
+        ctx.computed_states
+            .recompute_dependents(pagination.page().id);
+        assert_eq!(20, *pagination.offset().get());
+
+        // after removing items, the offset stays on the last page, and prev goes back from there
+        total.set(12);
+        ctx.computed_states.recompute_dependents(total.id);
+        assert_eq!(2, *pagination.total_pages().get());
+        assert_eq!(10, *pagination.offset().get());
+
+        click(&mut ctx, pagination.prev()).await;
+        assert_eq!(0, *pagination.page().get());
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::broadcast;
+
+/// How many changes a subscriber can fall behind on before missing some
+const CHANGES_CAPACITY: usize = 64;
+
+/// Tracks which users have a live connection, for showing who's online in collaborative apps.
+///
+/// Connections are assigned to a user with the function given to [`Config::with_presence`](crate::config::Config::with_presence).
+/// A user is online while they have at least one connection, so having the page open in
+/// two tabs doesn't count them twice. Clones share the same users.
+#[derive(Clone)]
+pub struct Presence {
+    /// Amount of connections of each online user
+    users: Arc<Mutex<HashMap<String, usize>>>,
+    changes: broadcast::Sender<PresenceChange>,
+}
+
+/// A user coming online or going offline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresenceChange {
+    Joined(String),
+    Left(String),
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Self {
+            users: Default::default(),
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
+        }
+    }
+}
+
+impl Presence {
+    /// Returns the users that are online
+    pub fn users(&self) -> HashSet<String> {
+        self.users.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns whether `user` is online
+    pub fn is_online(&self, user: &str) -> bool {
+        self.users.lock().unwrap().contains_key(user)
+    }
+
+    /// Subscribes to users coming online and going offline.
+    ///
+    /// Only changes that happen after subscribing are received, so get the current users
+    /// with [`Presence::users`] afterwards.
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceChange> {
+        self.changes.subscribe()
+    }
+
+    /// Counts a connection of `user`, until the returned guard is dropped
+    pub(crate) fn join(&self, user: String) -> PresenceGuard {
+        let mut users = self.users.lock().unwrap();
+        let connections = users.entry(user.clone()).or_default();
+        *connections += 1;
+        if *connections == 1 {
+            // there's no error to handle when no one is subscribed
+            let _ = self.changes.send(PresenceChange::Joined(user.clone()));
+        }
+
+        PresenceGuard {
+            presence: self.clone(),
+            user,
+        }
+    }
+}
+
+pub(crate) struct PresenceGuard {
+    presence: Presence,
+    user: String,
+}
+
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        let mut users = self.presence.users.lock().unwrap();
+        let Some(connections) = users.get_mut(&self.user) else {
+            return;
+        };
+
+        *connections -= 1;
+        if *connections == 0 {
+            users.remove(&self.user);
+            let _ = self
+                .presence
+                .changes
+                .send(PresenceChange::Left(self.user.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_is_online_while_connected() {
+        let presence = Presence::default();
+        let mut changes = presence.subscribe();
+
+        let first = presence.join("ada".to_string());
+        let second = presence.join("ada".to_string());
+        let _other = presence.join("grace".to_string());
+        assert!(presence.is_online("ada"));
+        assert_eq!(2, presence.users().len());
+
+        drop(first);
+        assert!(presence.is_online("ada"));
+        drop(second);
+        assert!(!presence.is_online("ada"));
+
+        assert_eq!(
+            PresenceChange::Joined("ada".to_string()),
+            changes.try_recv().unwrap()
+        );
+        assert_eq!(
+            PresenceChange::Joined("grace".to_string()),
+            changes.try_recv().unwrap()
+        );
+        assert_eq!(
+            PresenceChange::Left("ada".to_string()),
+            changes.try_recv().unwrap()
+        );
+        assert!(changes.try_recv().is_err());
+    }
+}
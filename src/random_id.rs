@@ -1,35 +1,39 @@
 use std::{
     array::TryFromSliceError,
+    collections::HashSet,
     fmt::{Debug, Display, Write},
 };
 
-use rand::{distributions::Alphanumeric, Rng};
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
 use serde::{de::Deserializer, Deserialize};
 
-const RANDOM_ID_LENGTH: usize = 8;
+/// Length of the ids used for elements, states and closures
+pub(crate) const RANDOM_ID_LENGTH: usize = 8;
 
+/// An id of `N` alphanumeric characters.
+///
+/// Shorter ids make pages smaller, at the cost of being more likely to collide.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct RandomId([u8; RANDOM_ID_LENGTH]);
+pub struct RandomId<const N: usize = RANDOM_ID_LENGTH>([u8; N]);
 
-impl RandomId {
+impl<const N: usize> RandomId<N> {
     pub fn from_rng<RNG: Rng>(rng: &mut RNG) -> Self {
-        let array = [(); RANDOM_ID_LENGTH].map(|_| rng.sample(Alphanumeric));
+        let array = [(); N].map(|_| rng.sample(Alphanumeric));
 
         Self(array)
     }
 
     #[allow(dead_code)]
     pub(crate) fn from_str(string: &str) -> Self {
-        Self::try_from_str(string).unwrap_or_else(|_| {
-            panic!(
-                "provided string was less than {} characters long",
-                RANDOM_ID_LENGTH
-            )
-        })
+        Self::try_from_str(string)
+            .unwrap_or_else(|_| panic!("provided string was less than {N} characters long"))
     }
 
+    /// Takes the first `N` bytes of `string`, failing if it's shorter
     pub(crate) fn try_from_str(string: &str) -> Result<Self, TryFromSliceError> {
-        let array: [u8; RANDOM_ID_LENGTH] = string.as_bytes()[..RANDOM_ID_LENGTH].try_into()?;
+        // slicing a shorter string would panic, so give `try_into` the whole string for it to fail
+        let bytes = string.as_bytes();
+        let array: [u8; N] = bytes.get(..N).unwrap_or(bytes).try_into()?;
         Ok(Self(array))
     }
 
@@ -42,7 +46,100 @@ impl RandomId {
     }
 }
 
-impl Debug for RandomId {
+/// Where an element is in the tree, which its id is derived from.
+///
+/// Elements are identified by the tag names of their ancestors, and their position among the
+/// siblings with the same tag, hashed with the seed of the page. That way, an element gets the
+/// same id no matter how many ids were generated before it, or whether a sibling with another
+/// tag is rendered or not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct IdPath(u64);
+
+impl IdPath {
+    /// The root of a tree. Trees rendered for the same page need different `name`s
+    pub(crate) fn root(seed: u64, name: &str) -> Self {
+        Self(0).with(|hasher| {
+            hasher.write_u64(seed);
+            hasher.write_str(name);
+        })
+    }
+
+    /// The path of the `occurrence`th child element with tag `name`
+    pub(crate) fn child(self, name: &str, occurrence: usize) -> Self {
+        self.with(|hasher| {
+            hasher.write_str(name);
+            hasher.write_u64(occurrence as u64);
+        })
+    }
+
+    /// A branch of content that is rendered in the same place, like the sides of an if
+    pub(crate) fn branch(self, branch: &str) -> Self {
+        self.with(|hasher| {
+            hasher.write_str("#");
+            hasher.write_str(branch);
+        })
+    }
+
+    pub(crate) fn id(self) -> RandomId {
+        RandomId::from_rng(&mut StdRng::seed_from_u64(self.0))
+    }
+
+    /// Like [`IdPath::id`], but re-rolls the id while it's already in `used`, adding it once it isn't
+    pub(crate) fn unique_id(self, used: &mut HashSet<RandomId>) -> RandomId {
+        let mut path = self;
+        loop {
+            let id = path.id();
+            if used.insert(id) {
+                return id;
+            }
+
+            // deterministic, so the same tree still gets the same ids
+            path = path.branch("reroll");
+        }
+    }
+
+    fn with(self, f: impl FnOnce(&mut Fnv1a)) -> Self {
+        let mut hasher = Fnv1a::new();
+        hasher.write_u64(self.0);
+        f(&mut hasher);
+        Self(hasher.0)
+    }
+}
+
+/// The 64-bit FNV-1a hash, which paths are hashed with.
+///
+/// Unlike std's `DefaultHasher`, its output is specified, so the same path is hashed to the same
+/// value no matter which version of Rust or which platform the server is built with.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    /// Writes the string followed by a byte that can't be in UTF-8, so consecutive strings can't
+    /// run into each other
+    fn write_str(&mut self, value: &str) {
+        self.write(value.as_bytes());
+        self.write(&[0xff]);
+    }
+}
+
+impl<const N: usize> Debug for RandomId<N> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("RandomId(")?;
 
@@ -54,30 +151,26 @@ impl Debug for RandomId {
     }
 }
 
-impl Display for RandomId {
+impl<const N: usize> Display for RandomId<N> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         Self::fmt(self, f)
     }
 }
 
-impl<'de> Deserialize<'de> for RandomId {
+impl<'de, const N: usize> Deserialize<'de> for RandomId<N> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         use serde::de::{self, Visitor};
 
-        struct RandomIdVisitor;
+        struct RandomIdVisitor<const N: usize>;
 
-        impl<'de> Visitor<'de> for RandomIdVisitor {
-            type Value = RandomId;
+        impl<'de, const N: usize> Visitor<'de> for RandomIdVisitor<N> {
+            type Value = RandomId<N>;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(
-                    formatter,
-                    "a string no more than {} bytes long",
-                    RANDOM_ID_LENGTH
-                )
+                write!(formatter, "a string at least {N} bytes long")
             }
 
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -98,6 +191,80 @@ impl<'de> Deserialize<'de> for RandomId {
             }
         }
 
-        deserializer.deserialize_str(RandomIdVisitor)
+        deserializer.deserialize_str(RandomIdVisitor::<N>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html::{p, Content, ContentValue, StateDescriptor};
+
+    use super::*;
+
+    #[test]
+    fn test_paths_are_hashed_with_fnv1a() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = Fnv1a::new();
+            hasher.write(bytes);
+            hasher.0
+        };
+        assert_eq!(0xcbf2_9ce4_8422_2325, hash(b""));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, hash(b"a"));
+        assert_eq!(0x8594_4171_f739_67e8, hash(b"foobar"));
+
+        // pinned, so ids don't change between builds without noticing
+        assert_eq!(
+            IdPath(0xad22_f625_82b0_5745),
+            IdPath::root(0, "").child("p", 1)
+        );
+    }
+
+    #[test]
+    fn test_collision_rerolls_id() {
+        let path = IdPath::root(0, "");
+        let mut used = HashSet::from([path.id()]);
+
+        let id = path.unique_id(&mut used);
+        assert_ne!(path.id(), id);
+        assert!(used.contains(&id));
+        // the re-roll is deterministic
+        assert_eq!(id, path.unique_id(&mut HashSet::from([path.id()])));
+    }
+
+    #[test]
+    fn test_collision_in_tree_rerolls_id() {
+        let path = IdPath::root(0, "");
+        // the state makes the element need an id
+        let state = StateDescriptor {
+            display: "0".to_string(),
+            state_id: "aaaabbbb".to_string(),
+            format: None,
+        };
+        let mut element = p(
+            Content::Value(ContentValue::State(state)),
+            Default::default(),
+        );
+
+        let taken = path.id();
+        element.give_ids(path, &mut HashSet::from([taken]));
+        assert!(element.id.is_some());
+        assert_ne!(Some(taken), element.id);
+    }
+
+    #[test]
+    fn test_length_is_configurable() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let id = RandomId::<4>::from_rng(&mut rng);
+        assert_eq!(4, id.to_string().len());
+
+        assert_eq!(
+            RandomId::<4>::from_str("abcdefgh"),
+            RandomId::from_str("abcd")
+        );
+        assert!(RandomId::<4>::try_from_str("abc").is_err());
+
+        let id: RandomId<4> = serde_json::from_str("\"wxyz\"").unwrap();
+        assert_eq!("wxyz", id.to_string());
+        assert!(serde_json::from_str::<RandomId<4>>("\"xyz\"").is_err());
     }
 }
@@ -1,22 +1,116 @@
-use std::{borrow::Cow, collections::HashMap, fmt::Write};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+};
+
+use crate::{
+    html::{Element, StateDescriptor},
+    random_id::{IdPath, RandomId},
+};
+
+/// Like [`element_reactivity_script_with_states`], for when the used states aren't needed
+#[cfg(test)]
+pub(crate) fn element_reactivity_script(element: &mut Element, seed: u64) -> String {
+    element_reactivity_script_with_states(element, IdPath::root(seed, ""), &mut HashSet::new())
+}
+
+/// Prepares `element` for rendering, and returns the script that makes it reactive.
+/// The ids of the states the element depends on are added to `used_states`.
+///
+/// This optimizes the element tree and gives ids to reactive elements, with `path` being the
+/// path of `element`, so it must be called before rendering.
+pub(crate) fn element_reactivity_script_with_states(
+    element: &mut Element,
+    path: IdPath,
+    used_states: &mut HashSet<String>,
+) -> String {
+    element.optimize();
+    element.give_ids(path, &mut HashSet::new());
+
+    let mut reactivity = Reactivity::default();
+    element.reactivity(&mut reactivity);
+    used_states.extend(
+        reactivity
+            .state_field_initial_values
+            .keys()
+            .map(|id| id.to_string()),
+    );
+    reactivity.script()
+}
+
+/// Prepares an element computed by `Context::use_computed_element`, and returns the JSON the client
+/// replaces the previous one with, holding its HTML and the script that makes it reactive
+pub(crate) fn computed_element_json(element: &mut Element, path: IdPath) -> String {
+    let script = element_reactivity_script_with_states(element, path, &mut HashSet::new());
+    let mut html = String::with_capacity(element.estimated_size());
+    element.render(&mut html);
 
-use crate::{html::StateDescriptor, random_id::RandomId};
+    serde_json::json!({ "html": html, "script": script }).to_string()
+}
 
 #[derive(Default)]
 pub(crate) struct Reactivity<'a> {
     descriptors: Vec<ReactivityDescriptor<'a>>,
 
-    state_field_initial_values: HashMap<&'a str, &'a str>,
+    state_field_initial_values: BTreeMap<&'a str, &'a str>,
+    /// States whose listeners run once the initial values have been set
+    refresh_on_load: Vec<&'a str>,
+    /// Reactivity of computed elements, whose listeners are tagged with their scope
+    scoped: Vec<(&'a str, Reactivity<'a>)>,
 }
 
 impl<'a> Reactivity<'a> {
     pub(crate) fn add(&mut self, descriptor: ReactivityDescriptor<'a>) {
         for state_descriptor in &descriptor.state_descriptors {
             self.register_state(state_descriptor);
+
+            // the page is rendered with the unformatted value, so it needs formatting once the script runs
+            if state_descriptor.format.is_some()
+                && !self
+                    .refresh_on_load
+                    .contains(&state_descriptor.state_id.as_str())
+            {
+                self.refresh_on_load.push(&state_descriptor.state_id);
+            }
         }
         self.descriptors.push(descriptor);
     }
 
+    pub(crate) fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    /// Returns the ids of the states used by the descriptors added after the first `start` ones
+    pub(crate) fn state_ids_since(&self, start: usize) -> impl Iterator<Item = &'a str> + '_ {
+        let mut ids = self.descriptors[start..]
+            .iter()
+            .flat_map(|descriptor| descriptor.state_descriptors.iter())
+            .map(|state_descriptor| state_descriptor.state_id.as_str())
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+
+        ids.into_iter()
+    }
+
+    pub(crate) fn refresh_on_load(&mut self, state_id: &'a str) {
+        self.refresh_on_load.push(state_id);
+    }
+
+    /// Adds the reactivity of a computed element, whose listeners are removed on the client
+    /// when it's replaced. Its states are set and refreshed along with the rest.
+    pub(crate) fn add_scoped(&mut self, scope: &'a str, nested: Reactivity<'a>) {
+        self.state_field_initial_values
+            .extend(&nested.state_field_initial_values);
+        for state_id in &nested.refresh_on_load {
+            if !self.refresh_on_load.contains(state_id) {
+                self.refresh_on_load.push(state_id);
+            }
+        }
+        self.scoped.push((scope, nested));
+    }
+
     fn register_state(&mut self, state_descriptor: &'a StateDescriptor) {
         self.state_field_initial_values
             .insert(&state_descriptor.state_id, &state_descriptor.display);
@@ -25,18 +119,42 @@ impl<'a> Reactivity<'a> {
     pub(crate) fn script(&self) -> String {
         let mut output = String::new();
 
-        for descriptor in &self.descriptors {
-            descriptor.script(&mut output);
-        }
-
+        self.listeners_script(&mut output);
         self.state_field_initial_values_script(&mut output);
 
+        if !self.refresh_on_load.is_empty() {
+            write!(
+                output,
+                "window.Coaxial.refresh(['{}']);",
+                self.refresh_on_load.join("','")
+            )
+            .unwrap();
+        }
+
         output
     }
 
+    fn listeners_script(&self, output: &mut String) {
+        for descriptor in &self.descriptors {
+            descriptor.script(output);
+        }
+
+        for (scope, nested) in &self.scoped {
+            write!(output, "window.Coaxial.scoped('{scope}', () => {{ ").unwrap();
+            nested.listeners_script(output);
+            output.push_str("});");
+        }
+    }
+
     fn state_field_initial_values_script(&self, output: &mut String) {
         for (key, value) in &self.state_field_initial_values {
-            write!(output, "window.Coaxial.state['{key}'] = '{value}';").unwrap()
+            // values can hold quotes and newlines, like the JSON of computed elements
+            write!(
+                output,
+                "window.Coaxial.state['{key}'] = {};",
+                serde_json::Value::from(*value)
+            )
+            .unwrap()
         }
     }
 }
@@ -55,6 +173,27 @@ pub(crate) struct ReactivityDescriptor<'a> {
 }
 
 impl<'a> ReactivityDescriptor<'a> {
+    /// Sets the attribute, or removes it when the value is empty, so optional attributes like
+    /// `hidden` or `aria-*` don't linger as `key=""`
+    fn set_attribute_script(key: &str, content: &[Content<'a>], output: &mut String) {
+        if let [Content::Var(idx)] = content {
+            write!(
+                output,
+                "el[v{idx} === '' ? 'removeAttribute' : 'setAttribute']('{key}', v{idx})"
+            )
+            .unwrap();
+        } else {
+            // the value is passed to an arrow function, so it's only evaluated once
+            write!(
+                output,
+                "(v => el[v === '' ? 'removeAttribute' : 'setAttribute']('{key}', v))("
+            )
+            .unwrap();
+            Content::list_script(content, output);
+            output.push(')');
+        }
+    }
+
     fn script(&self, output: &mut String) {
         output.push_str("window.Coaxial.onStateChange(['");
 
@@ -80,7 +219,19 @@ impl<'a> ReactivityDescriptor<'a> {
             }
         }
 
-        output.push_str(") => { if (el = document.querySelector('[coax-id=\"");
+        output.push_str(") => { ");
+
+        for (i, state_desc) in self.state_descriptors.iter().enumerate() {
+            if let Some(format) = &state_desc.format {
+                write!(
+                    output,
+                    "v{i} = window.Coaxial.formatNumber(v{i}, {format}); "
+                )
+                .unwrap();
+            }
+        }
+
+        output.push_str("if (el = document.querySelector('[coax-id=\"");
         self.element_id.fmt(output).unwrap();
         output.push_str("\"]')) ");
 
@@ -88,30 +239,82 @@ impl<'a> ReactivityDescriptor<'a> {
             write!(output, "if (el = el.childNodes[{}]) ", child_node_idx).unwrap();
         }
 
-        match self.target {
-            Target::TextContent => output.push_str("el.textContent = "),
-            Target::Attribute(key) => {
-                output.push_str("el.setAttribute('");
+        match &self.target {
+            Target::TextContent => {
+                output.push_str("el.textContent = ");
+                Content::list_script(&self.content, output);
+            }
+            Target::Attribute(key) => Self::set_attribute_script(key, &self.content, output),
+            Target::Property("checked") => {
+                output.push_str("el.checked = ");
+                Content::list_script(&self.content, output);
+                output.push_str(" === 'true'");
+            }
+            Target::Property(key) => {
+                output.push_str("if (el['");
                 output.push_str(key);
-                output.push_str("', ");
+                output.push_str("'] !== ");
+                Content::list_script(&self.content, output);
+                output.push_str(") el['");
+                output.push_str(key);
+                output.push_str("'] = ");
+                Content::list_script(&self.content, output);
             }
-        }
-
-        if self.content.len() == 1 {
-            self.content.first().unwrap().script(output);
-        } else {
-            output.push('[');
-            for (i, item) in self.content.iter().enumerate() {
-                item.script(output);
-                if i + 1 != self.content.len() {
-                    output.push(',');
+            Target::InnerHtml { refresh } => {
+                output.push_str("el.innerHTML = ");
+                Content::list_script(&self.content, output);
+
+                if !refresh.is_empty() {
+                    output.push_str(", window.Coaxial.refresh(['");
+                    output.push_str(&refresh.join("','"));
+                    output.push_str("'])");
+                }
+            }
+            Target::Html { scope } => {
+                output.push_str("window.Coaxial.setHtml(el, ");
+                Content::list_script(&self.content, output);
+                write!(output, ", '{scope}')").unwrap();
+            }
+            Target::Show { html, refresh } => {
+                // a JSON string, since the html can have newlines, which end quoted strings
+                write!(
+                    output,
+                    "window.Coaxial.show(el, v0 === 'true', {}, [",
+                    serde_json::Value::from(html.as_ref())
+                )
+                .unwrap();
+                if !refresh.is_empty() {
+                    output.push('\'');
+                    output.push_str(&refresh.join("','"));
+                    output.push('\'');
+                }
+                output.push_str("])");
+            }
+            Target::Method { name, args } => {
+                output.push_str("el[");
+                name.script(output);
+                output.push_str("](");
+                for (i, arg) in args.iter().enumerate() {
+                    if i != 0 {
+                        output.push_str(", ");
+                    }
+                    arg.script(output);
+                }
+                output.push(')');
+            }
+            Target::ClassToggle(class) => {
+                write!(output, "el.classList.toggle('{class}', ").unwrap();
+                Content::list_script(&self.content, output);
+                output.push_str(" === 'true')");
+            }
+            Target::Attributes(attributes) => {
+                for (i, (key, content)) in attributes.iter().enumerate() {
+                    if i != 0 {
+                        output.push_str(", ");
+                    }
+                    Self::set_attribute_script(key, content, output);
                 }
             }
-            output.push_str("].join('')");
-        }
-
-        if matches!(self.target, Target::Attribute(_)) {
-            output.push(')');
         }
         output.push_str("; });");
 
@@ -123,20 +326,110 @@ impl<'a> ReactivityDescriptor<'a> {
 pub(crate) enum Target<'a> {
     TextContent,
     Attribute(&'a str),
+    /// Sets a property of the element, like `value`, instead of its attribute.
+    /// Booleans like `checked` are set to whether the content is `'true'`.
+    Property(&'a str),
+    /// Replaces the children of the element.
+    ///
+    /// `refresh` contains the ids of the states whose listeners need to run again after replacing.
+    InnerHtml {
+        refresh: Vec<&'a str>,
+    },
+    /// Replaces the children of the element with a computed element, whose content is the JSON
+    /// with its HTML and script. The listeners tagged with `scope` are replaced with the new ones.
+    Html {
+        scope: &'a str,
+    },
+    /// Adds the class while the content is `'true'`, and removes it otherwise,
+    /// without changing the rest of the classes
+    ClassToggle(&'a str),
+    /// Sets multiple attributes at once. Each one has its own content,
+    /// so the descriptor's `content` is ignored.
+    Attributes(Vec<(&'a str, Vec<Content<'a>>)>),
+    /// Adds or removes the element depending on the first state, keeping a `<template>` in its place
+    /// while it's removed. `html` is the rendered element, unescaped, and the states in `refresh` are
+    /// re-applied after adding it.
+    Show {
+        html: Cow<'a, str>,
+        refresh: Vec<&'a str>,
+    },
+    /// Calls a method of the element, like `el[name](...args)`.
+    /// The descriptor's `content` is ignored.
+    Method {
+        name: Content<'a>,
+        args: Vec<Content<'a>>,
+    },
 }
 
 pub(crate) enum Content<'a> {
     /// Plain text
     Text(Cow<'a, str>),
+    /// Rendered HTML, unescaped. It's written as a JSON string, since it can have newlines
+    Html(Cow<'a, str>),
     /// Index into the state_ids array
     Var(usize),
+    /// Picks `then` if the state in index `var` is `'true'`, and `otherwise` if it isn't
+    If {
+        var: usize,
+        then: Box<Content<'a>>,
+        otherwise: Box<Content<'a>>,
+    },
+    /// Evaluates a JS template literal, where `$0`, `$1`... are the states in the indices in `vars`
+    Template { vars: Vec<usize>, template: &'a str },
 }
 
 impl<'a> Content<'a> {
+    /// Writes the script for a list of content, joining them if there is more than one
+    fn list_script(content: &[Content<'a>], output: &mut String) {
+        if content.len() == 1 {
+            content[0].script(output);
+        } else {
+            output.push('[');
+            for (i, item) in content.iter().enumerate() {
+                item.script(output);
+                if i + 1 != content.len() {
+                    output.push(',');
+                }
+            }
+            output.push_str("].join('')");
+        }
+    }
+
     fn script(&self, output: &mut String) {
         match self {
             Content::Text(text) => write!(output, "'{}'", text).unwrap(),
+            Content::Html(html) => {
+                write!(output, "{}", serde_json::Value::from(html.as_ref())).unwrap()
+            }
             Content::Var(idx) => write!(output, "v{}", idx).unwrap(),
+            Content::If {
+                var,
+                then,
+                otherwise,
+            } => {
+                write!(output, "(v{} === 'true' ? ", var).unwrap();
+                then.script(output);
+                output.push_str(" : ");
+                otherwise.script(output);
+                output.push(')');
+            }
+            Content::Template { vars, template } => {
+                output.push_str("((");
+                for i in 0..vars.len() {
+                    if i != 0 {
+                        output.push(',');
+                    }
+                    write!(output, "${i}").unwrap();
+                }
+                write!(output, ") => `{template}`)(").unwrap();
+                for (i, var) in vars.iter().enumerate() {
+                    if i != 0 {
+                        output.push(',');
+                    }
+                    write!(output, "v{var}").unwrap();
+                }
+                output.push(')');
+            }
         }
     }
 }
@@ -150,6 +443,7 @@ mod tests {
         let state_desc = StateDescriptor {
             display: "value".to_string(),
             state_id: "state1".to_string(),
+            format: None,
         };
         let desc = ReactivityDescriptor {
             element_id: RandomId::from_str("aaaabbbb"),
@@ -170,6 +464,7 @@ mod tests {
         let state_desc = StateDescriptor {
             display: "value".to_string(),
             state_id: "state1".to_string(),
+            format: None,
         };
         let desc = ReactivityDescriptor {
             element_id: RandomId::from_str("aaaabbbb"),
@@ -182,7 +477,7 @@ mod tests {
         let mut output = String::new();
         desc.script(&mut output);
 
-        assert_eq!("window.Coaxial.onStateChange(['state1'], (v0) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) el.setAttribute('my-attr', v0); });\n", output);
+        assert_eq!("window.Coaxial.onStateChange(['state1'], (v0) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) el[v0 === '' ? 'removeAttribute' : 'setAttribute']('my-attr', v0); });\n", output);
     }
 
     #[test]
@@ -190,6 +485,7 @@ mod tests {
         let state_desc = StateDescriptor {
             display: "value".to_string(),
             state_id: "state1".to_string(),
+            format: None,
         };
         let desc = ReactivityDescriptor {
             element_id: RandomId::from_str("aaaabbbb"),
@@ -210,6 +506,7 @@ mod tests {
         let state_desc = StateDescriptor {
             display: "value".to_string(),
             state_id: "state1".to_string(),
+            format: None,
         };
         let desc = ReactivityDescriptor {
             element_id: RandomId::from_str("aaaabbbb"),
@@ -233,6 +530,7 @@ mod tests {
         let state_desc = StateDescriptor {
             display: "value".to_string(),
             state_id: "state1".to_string(),
+            format: None,
         };
         let desc = ReactivityDescriptor {
             element_id: RandomId::from_str("aaaabbbb"),
@@ -249,7 +547,107 @@ mod tests {
         let mut output = String::new();
         desc.script(&mut output);
 
-        assert_eq!("window.Coaxial.onStateChange(['state1'], (v0) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) el.setAttribute('my-attr', ['hey',v0,'world'].join('')); });\n", output);
+        assert_eq!("window.Coaxial.onStateChange(['state1'], (v0) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) (v => el[v === '' ? 'removeAttribute' : 'setAttribute']('my-attr', v))(['hey',v0,'world'].join('')); });\n", output);
+    }
+
+    #[test]
+    fn test_empty_attribute_is_removed() {
+        let state_desc = StateDescriptor {
+            display: "false".to_string(),
+            state_id: "state1".to_string(),
+            format: None,
+        };
+        let desc = ReactivityDescriptor {
+            element_id: RandomId::from_str("aaaabbbb"),
+            child_node_idx: None,
+            state_descriptors: vec![&state_desc],
+            content: vec![Content::If {
+                var: 0,
+                then: Box::new(Content::Text("true".into())),
+                otherwise: Box::new(Content::Text("".into())),
+            }],
+            target: Target::Attribute("aria-expanded"),
+        };
+
+        let mut output = String::new();
+        desc.script(&mut output);
+
+        assert_eq!("window.Coaxial.onStateChange(['state1'], (v0) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) (v => el[v === '' ? 'removeAttribute' : 'setAttribute']('aria-expanded', v))((v0 === 'true' ? 'true' : '')); });\n", output);
+    }
+
+    #[test]
+    fn test_inner_html_if() {
+        let state_desc = StateDescriptor {
+            display: "true".to_string(),
+            state_id: "state1".to_string(),
+            format: None,
+        };
+        let desc = ReactivityDescriptor {
+            element_id: RandomId::from_str("aaaabbbb"),
+            child_node_idx: None,
+            state_descriptors: vec![&state_desc],
+            content: vec![Content::If {
+                var: 0,
+                then: Box::new(Content::Text("yes".into())),
+                otherwise: Box::new(Content::Text("no".into())),
+            }],
+            target: Target::InnerHtml {
+                refresh: vec!["state2"],
+            },
+        };
+
+        let mut output = String::new();
+        desc.script(&mut output);
+
+        assert_eq!("window.Coaxial.onStateChange(['state1'], (v0) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) el.innerHTML = (v0 === 'true' ? 'yes' : 'no'), window.Coaxial.refresh(['state2']); });\n", output);
+    }
+
+    #[test]
+    fn test_method_without_args() {
+        let state_desc = StateDescriptor {
+            display: "value".to_string(),
+            state_id: "state1".to_string(),
+            format: None,
+        };
+        let desc = ReactivityDescriptor {
+            element_id: RandomId::from_str("aaaabbbb"),
+            child_node_idx: None,
+            state_descriptors: vec![&state_desc],
+            content: vec![],
+            target: Target::Method {
+                name: Content::Text("focus".into()),
+                args: vec![],
+            },
+        };
+
+        let mut output = String::new();
+        desc.script(&mut output);
+
+        assert_eq!("window.Coaxial.onStateChange(['state1'], (v0) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) el['focus'](); });\n", output);
+    }
+
+    #[test]
+    fn test_method_with_args() {
+        let state_desc = StateDescriptor {
+            display: "value".to_string(),
+            state_id: "state1".to_string(),
+            format: None,
+        };
+        let desc = ReactivityDescriptor {
+            element_id: RandomId::from_str("aaaabbbb"),
+            child_node_idx: None,
+            state_descriptors: vec![&state_desc],
+            content: vec![],
+            target: Target::Method {
+                name: Content::Text("toggleAttribute".into()),
+                args: vec![Content::Text("hidden".into()), Content::Var(0)],
+            },
+        };
+
+        let mut output = String::new();
+        desc.script(&mut output);
+
+        assert_eq!("window.Coaxial.onStateChange(['state1'], (v0) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) el['toggleAttribute']('hidden', v0); });\n", output);
     }
 
     #[test]
@@ -257,10 +655,12 @@ mod tests {
         let state_desc_1 = StateDescriptor {
             display: "value1".to_string(),
             state_id: "state1".to_string(),
+            format: None,
         };
         let state_desc_2 = StateDescriptor {
             display: "value2".to_string(),
             state_id: "state2".to_string(),
+            format: None,
         };
         let desc = ReactivityDescriptor {
             element_id: RandomId::from_str("aaaabbbb"),
@@ -284,3 +684,200 @@ mod tests {
         assert_eq!("window.Coaxial.onStateChange(['state1','state2'], (v0,v1) => { if (el = document.querySelector('[coax-id=\"aaaabbbb\"]')) el.textContent = [v1,'um',v0,'wow',v1,v0,v1].join(''); });\n", output);
     }
 }
+
+/// Tests that run a full element tree through the reactivity pipeline
+#[cfg(test)]
+mod element_tree_tests {
+    use crate::{
+        attrs,
+        context::Context,
+        html::{button, div, p, strong, Content, ContentValue, Element},
+    };
+
+    use super::element_reactivity_script;
+
+    /// Builds the element from the counter example, and checks the full generated script
+    #[test]
+    fn test_element_tree_script() {
+        let mut ctx = Context::<()>::new(0, false);
+
+        let counter = ctx.use_state(0i32);
+        let clicks = ctx.use_state(0u32);
+        let add = ctx.use_closure(move || async move {});
+        let counter_plus_1 = ctx.use_computed(counter, |counter| *counter + 1);
+
+        let mut element = div(
+            Content::List(vec![
+                div(
+                    Content::List(vec![
+                        button(
+                            "increment counter",
+                            attrs!(
+                                "onclick" => add,
+                                "title" => ("go from ", counter, " to ", counter_plus_1)
+                            ),
+                        )
+                        .into(),
+                        button("click for fun :3", Default::default()).into(),
+                    ]),
+                    attrs!("class" => "buttons", "data-clicks" => clicks),
+                )
+                .into(),
+                p(
+                    Content::List(vec![
+                        "counter is ".into(),
+                        counter.into(),
+                        ". ".into(),
+                        strong("Wow!", Default::default()).into(),
+                        " and there are ".into(),
+                        clicks.into(),
+                        " total clicks.".into(),
+                    ]),
+                    Default::default(),
+                )
+                .into(),
+            ]),
+            attrs!("class" => "container"),
+        );
+
+        let script = element_reactivity_script(&mut element, 0);
+
+        let id = |element: &Element| element.id.unwrap().to_string();
+        let Content::List(children) = &element.content else {
+            panic!("content should be a list");
+        };
+        let [ContentValue::Element(buttons), ContentValue::Element(paragraph)] = &children[..]
+        else {
+            panic!("content should be two elements");
+        };
+        let Content::List(button_list) = &buttons.content else {
+            panic!("content should be a list");
+        };
+        let ContentValue::Element(increment) = &button_list[0] else {
+            panic!("first child should be a button");
+        };
+
+        assert!(element.id.is_none());
+        assert!(!button_list[1].is_reactive());
+
+        let (counter, clicks, counter_plus_1) = (
+            counter.id.to_string(),
+            clicks.id.to_string(),
+            counter_plus_1.0.id.to_string(),
+        );
+        let (buttons, paragraph, increment) = (id(buttons), id(paragraph), id(increment));
+
+        let mut initial_values = [(&counter, "0"), (&clicks, "0"), (&counter_plus_1, "1")];
+        initial_values.sort();
+        let initial_values = initial_values
+            .iter()
+            .map(|(key, value)| format!("window.Coaxial.state['{key}'] = \"{value}\";"))
+            .collect::<String>();
+
+        assert_eq!(
+            format!(
+                "window.Coaxial.onStateChange(['{counter}','{counter_plus_1}'], (v0,v1) => {{ if (el = document.querySelector('[coax-id=\"{increment}\"]')) (v => el[v === '' ? 'removeAttribute' : 'setAttribute']('title', v))(['go from ',v0,' to ',v1].join('')); }});\n\
+                 window.Coaxial.onStateChange(['{clicks}'], (v0) => {{ if (el = document.querySelector('[coax-id=\"{buttons}\"]')) el[v0 === '' ? 'removeAttribute' : 'setAttribute']('data-clicks', v0); }});\n\
+                 window.Coaxial.onStateChange(['{counter}'], (v0) => {{ if (el = document.querySelector('[coax-id=\"{paragraph}\"]')) if (el = el.childNodes[0]) el.textContent = ['counter is ',v0,'. '].join(''); }});\n\
+                 window.Coaxial.onStateChange(['{clicks}'], (v0) => {{ if (el = document.querySelector('[coax-id=\"{paragraph}\"]')) if (el = el.childNodes[2]) el.textContent = [' and there are ',v0,' total clicks.'].join(''); }});\n\
+                 {initial_values}"
+            ),
+            script
+        );
+    }
+
+    #[test]
+    fn test_dialog_script() {
+        use crate::html::dialog;
+
+        let mut ctx = Context::<()>::new(0, false);
+        let open = ctx.use_state(true);
+
+        let mut element = dialog("hi", ctx.use_dialog(open));
+        let script = element_reactivity_script(&mut element, 0);
+
+        let mut output = String::new();
+        element.render(&mut output);
+
+        let open = open.id;
+        let dialog = element.id.unwrap();
+        assert_eq!(
+            format!("<dialog onclose=\"window.Coaxial.setState('{open}', false)\" coax-id=\"{dialog}\">hi</dialog>"),
+            output
+        );
+        assert_eq!(
+            format!(
+                "window.Coaxial.onStateChange(['{open}'], (v0) => {{ if (el = document.querySelector('[coax-id=\"{dialog}\"]')) el[(v0 === 'true' ? 'showModal' : 'close')](); }});\n\
+                 window.Coaxial.state['{open}'] = \"true\";\
+                 window.Coaxial.refresh(['{open}']);"
+            ),
+            script
+        );
+    }
+
+    #[test]
+    fn test_closure_switch_script() {
+        use crate::html::button;
+
+        let mut ctx = Context::<()>::new(0, false);
+        let save = ctx.use_closure(|| async move {});
+        let edit = ctx.use_closure(|| async move {});
+        let editing = ctx.use_state(true);
+        let on_click =
+            ctx.use_closure_switch(editing, move |editing| if *editing { save } else { edit });
+
+        let mut element = button("go", attrs!("onclick" => on_click));
+        let script = element_reactivity_script(&mut element, 0);
+
+        let mut output = String::new();
+        element.render(&mut output);
+
+        let active = on_click.active.0.id;
+        let (save, button) = (save.id, element.id.unwrap());
+        assert_eq!(
+            format!("<button onclick=\"window.Coaxial.callClosure('{save}')\" coax-id=\"{button}\">go</button>"),
+            output
+        );
+        assert_eq!(
+            format!(
+                "window.Coaxial.onStateChange(['{active}'], (v0) => {{ if (el = document.querySelector('[coax-id=\"{button}\"]')) (v => el[v === '' ? 'removeAttribute' : 'setAttribute']('onclick', v))(['window.Coaxial.callClosure(\\'',v0,'\\')'].join('')); }});\n\
+                 window.Coaxial.state['{active}'] = \"{save}\";"
+            ),
+            script
+        );
+    }
+
+    #[test]
+    fn test_formatted_state_script() {
+        use crate::NumberFormat;
+
+        let mut ctx = Context::<()>::new(0, false);
+        let count = ctx.use_state_formatted(
+            1234567u32,
+            NumberFormat::new()
+                .with_locale("en-US")
+                .with_option("maximumFractionDigits", 0),
+        );
+
+        let mut element = p(count, Default::default());
+        let script = element_reactivity_script(&mut element, 0);
+
+        // the server renders the plain value, and the client formats it on load and on every change
+        let mut html = String::new();
+        element.render(&mut html);
+        let element_id = element.id.unwrap();
+        assert_eq!(format!("<p coax-id=\"{element_id}\">1234567</p>"), html);
+
+        let count = count.id;
+        assert_eq!(
+            format!(
+                "window.Coaxial.onStateChange(['{count}'], (v0) => {{ \
+                v0 = window.Coaxial.formatNumber(v0, \"en-US\", {{\"maximumFractionDigits\":0}}); \
+                if (el = document.querySelector('[coax-id=\"{element_id}\"]')) el.textContent = v0; }});\n\
+                window.Coaxial.state['{count}'] = \"1234567\";\
+                window.Coaxial.refresh(['{count}']);"
+            ),
+            script
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use generational_box::{AnyStorage, BorrowError, BorrowMutError, GenerationalBox, SyncStorage};
 use serde::de::DeserializeOwned;
-use serde_json::Value;
-use std::{collections::HashMap, fmt::Display, sync::Arc};
+use serde_json::{Map, Value};
+use std::{cell::RefCell, collections::HashMap, sync::Arc};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 use crate::random_id::RandomId;
@@ -9,8 +9,139 @@ use crate::random_id::RandomId;
 pub(crate) struct States {
     states: HashMap<RandomId, Arc<dyn AnyState>>,
 
-    pub(crate) changes_rx: UnboundedReceiver<(RandomId, String)>,
-    pub(crate) changes_tx: UnboundedSender<(RandomId, String)>,
+    pub(crate) changes_rx: UnboundedReceiver<(RandomId, StateChange)>,
+    pub(crate) changes_tx: UnboundedSender<(RandomId, StateChange)>,
+}
+
+/// Value sent through the changes channel when a state is set
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StateChange {
+    /// `Display` output of the value, used for DOM updates
+    pub(crate) display: String,
+    /// Serialized value, only present for states created with `use_typed_state`
+    pub(crate) value: Option<Value>,
+    /// Patch that turns the previous text into `display`, sent instead of it.
+    /// Only present for states created with `use_state_diffed`
+    pub(crate) patch: Option<TextPatch>,
+    /// Whether another change of the same [`batch`] follows this one in the channel
+    pub(crate) more: bool,
+}
+
+type Changes = UnboundedSender<(RandomId, StateChange)>;
+
+thread_local! {
+    /// Changes made inside of `batch`, which are sent once it ends
+    static BATCH: RefCell<Option<Vec<(Changes, RandomId, StateChange)>>> = const { RefCell::new(None) };
+}
+
+/// Sets the states inside of `f` all at once.
+///
+/// Normally, each change is handled on its own, so a computed state that depends on several of
+/// the states set in `f` would be recomputed once per state, and the client could see the
+/// values in between. Changes made inside of `f` are held until it returns, and then handled
+/// together: computed states are recomputed once, and states set more than once only send
+/// their last value.
+///
+/// ```ignore
+/// let save = ctx.use_closure(move || async move {
+///     coaxial::batch(|| {
+///         first_name.set("Ada".to_string());
+///         last_name.set("Lovelace".to_string());
+///     });
+/// });
+/// ```
+///
+/// Calling it inside of another `batch` adds to the outer one.
+pub fn batch<R>(f: impl FnOnce() -> R) -> R {
+    let started = BATCH.with(|batch| {
+        let mut batch = batch.borrow_mut();
+        batch.is_none() && batch.replace(Vec::new()).is_none()
+    });
+    if !started {
+        return f();
+    }
+
+    /// Sends the changes when dropped, so they're sent even if `f` panics
+    struct Flush;
+    impl Drop for Flush {
+        fn drop(&mut self) {
+            let changes = BATCH.with(|batch| batch.borrow_mut().take().unwrap_or_default());
+            send_batch(changes);
+        }
+    }
+
+    let _flush = Flush;
+    f()
+}
+
+/// Sends the changes of a batch, keeping only the last change of each state
+fn send_batch(changes: Vec<(Changes, RandomId, StateChange)>) {
+    let mut deduped: Vec<(Changes, RandomId, StateChange)> = Vec::with_capacity(changes.len());
+    for (tx, id, change) in changes {
+        match deduped.iter_mut().find(|(_, other, _)| *other == id) {
+            Some((_, _, previous)) => {
+                // the client never gets the previous change, so a patch against it can't be applied
+                *previous = StateChange {
+                    patch: None,
+                    ..change
+                };
+            }
+            None => deduped.push((tx, id, change)),
+        }
+    }
+
+    for i in 0..deduped.len() {
+        let more = deduped[i + 1..]
+            .iter()
+            .any(|(other, _, _)| other.same_channel(&deduped[i].0));
+        let (tx, id, change) = &deduped[i];
+        let change = StateChange {
+            more,
+            ..change.clone()
+        };
+        // the connection could have ended already
+        let _ = tx.send((*id, change));
+    }
+}
+
+/// Replaces the text between `start` and `end` with `text`.
+///
+/// Offsets are in UTF-16 code units, which is how JS indexes strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TextPatch {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) text: String,
+}
+
+impl TextPatch {
+    /// Returns the patch that turns `previous` into `next`, which replaces everything between
+    /// their common prefix and suffix
+    pub(crate) fn between(previous: &str, next: &str) -> Self {
+        let prefix = previous
+            .char_indices()
+            .zip(next.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or(previous.len().min(next.len()));
+
+        let (previous_rest, next_rest) = (&previous[prefix..], &next[prefix..]);
+        let suffix: usize = previous_rest
+            .chars()
+            .rev()
+            .zip(next_rest.chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum();
+
+        let start = previous[..prefix].encode_utf16().count();
+        let removed = &previous_rest[..previous_rest.len() - suffix];
+        TextPatch {
+            start,
+            end: start + removed.encode_utf16().count(),
+            text: next_rest[..next_rest.len() - suffix].to_string(),
+        }
+    }
 }
 
 impl States {
@@ -18,12 +149,44 @@ impl States {
         self.states.insert(id, state);
     }
 
-    pub(crate) fn set(&self, id: RandomId, value: Value) {
+    pub(crate) fn ids(&self) -> impl Iterator<Item = RandomId> + '_ {
+        self.states.keys().copied()
+    }
+
+    pub(crate) fn set(&self, id: RandomId, value: Value) -> Result<(), SetStateError> {
         let Some(state) = self.states.get(&id) else {
-            // TODO return an error
-            panic!("state not found");
+            return Err(SetStateError::NotFound(id));
         };
-        state.set_value(value);
+        state
+            .set_value(value)
+            .map_err(|error| SetStateError::InvalidValue(id, error.to_string()))
+    }
+
+    /// Returns the current value of each of the states in `ids`, as if they had just been set
+    pub(crate) fn snapshot(&self, ids: &[RandomId]) -> Vec<(RandomId, StateChange)> {
+        ids.iter()
+            .filter_map(|id| Some((*id, self.states.get(id)?.change())))
+            .collect()
+    }
+}
+
+/// Error returned when the client sets a state
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SetStateError {
+    /// There is no state with this id, like when a tab from a previous render is still open
+    NotFound(RandomId),
+    /// The value doesn't deserialize to the type of the state, with the error
+    InvalidValue(RandomId, String),
+}
+
+impl std::fmt::Display for SetStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetStateError::NotFound(id) => write!(f, "state {id} not found"),
+            SetStateError::InvalidValue(id, error) => {
+                write!(f, "invalid value for state {id}: {error}")
+            }
+        }
     }
 }
 
@@ -52,9 +215,92 @@ impl<T: 'static> Clone for State<T> {
 }
 impl<T: 'static> Copy for State<T> {}
 
+/// Returns whether two values of a state are equal, for states created with `use_state_with_eq`
+type Comparator<T> = dyn Fn(&T, &T) -> bool + Send + Sync;
+
 pub(crate) struct StateInner<T: 'static> {
     pub(crate) value: T,
-    pub(crate) changes_tx: UnboundedSender<(RandomId, String)>,
+    pub(crate) changes_tx: UnboundedSender<(RandomId, StateChange)>,
+    /// If set, changes will also carry the serialized value
+    pub(crate) serialize: Option<fn(&T) -> Option<Value>>,
+    /// Returns the text used for DOM updates, which is the `Display` output for most states
+    pub(crate) display: fn(&T) -> String,
+    /// Script for the formatter applied by the client before showing the value
+    pub(crate) format: Option<Arc<str>>,
+    /// If set, changes carry a patch against the last text, instead of the whole text
+    pub(crate) diffed: bool,
+    /// Last text of a diffed state, which the next change is diffed against
+    pub(crate) last_display: Option<String>,
+    /// If set, values it considers equal to the current one are ignored
+    pub(crate) eq: Option<Box<Comparator<T>>>,
+}
+
+impl<T: 'static> StateInner<T> {
+    /// Returns the change that sends the current value to the client
+    fn change(&mut self) -> StateChange {
+        let display = (self.display)(&self.value);
+        let serialized = self.serialize.and_then(|serialize| serialize(&self.value));
+
+        let patch = if self.diffed {
+            let patch = self
+                .last_display
+                .as_deref()
+                .map(|last| TextPatch::between(last, &display))
+                // small changes are sent whole, since the patch wouldn't be any shorter
+                .filter(|patch| patch.text.len() < display.len());
+            self.last_display = Some(display.clone());
+            patch
+        } else {
+            None
+        };
+
+        StateChange {
+            display,
+            value: serialized,
+            patch,
+            more: false,
+        }
+    }
+}
+
+/// Options for formatting a number on the client with `Intl.NumberFormat`.
+///
+/// See [`Context::use_state_formatted`](crate::context::Context::use_state_formatted).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NumberFormat {
+    locale: Option<String>,
+    options: Map<String, Value>,
+}
+
+impl NumberFormat {
+    /// Formats numbers using the browser's locale and the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the locale used for formatting, like `"en-US"`. Defaults to the browser's locale.
+    pub fn with_locale(mut self, locale: impl ToString) -> Self {
+        self.locale = Some(locale.to_string());
+        self
+    }
+
+    /// Sets an option of `Intl.NumberFormat`, like `maximumFractionDigits` or `style`.
+    ///
+    /// See the [MDN docs](https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Global_Objects/Intl/NumberFormat/NumberFormat#options)
+    /// for the available options.
+    pub fn with_option(mut self, name: impl ToString, value: impl Into<Value>) -> Self {
+        self.options.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Returns the arguments for `window.Coaxial.formatNumber`, after the value
+    pub(crate) fn script(&self) -> String {
+        let locale = match &self.locale {
+            Some(locale) => Value::String(locale.clone()),
+            None => Value::Null,
+        };
+        format!("{locale}, {}", Value::Object(self.options.clone()))
+    }
 }
 
 /// Type returned by State::get
@@ -62,6 +308,10 @@ pub type StateGet<'a, T> = <SyncStorage as AnyStorage>::Ref<'a, T>;
 
 impl<T: Send + Sync + 'static> State<T> {
     // TODO these types should be wrapped so it's not in our public interface
+    /// Borrows the value, without cloning it.
+    ///
+    /// Holding on to the returned guard while setting the state on the same thread will deadlock,
+    /// so use [`State::with`] or [`State::cloned`] when the value is only needed briefly.
     pub fn get(&self) -> StateGet<'_, T> {
         self.try_get().unwrap()
     }
@@ -71,30 +321,94 @@ impl<T: Send + Sync + 'static> State<T> {
 
         Ok(SyncStorage::map(inner, |v| &v.value))
     }
+
+    /// Runs `f` with a reference to the value, without cloning it.
+    ///
+    /// The value is only borrowed while `f` runs.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.get())
+    }
+
+    /// Returns the text the value is rendered as
+    pub(crate) fn display(&self) -> String {
+        let inner = self.inner.read();
+        (inner.display)(&inner.value)
+    }
+
+    /// Returns the script for the client-side formatter of this state, if it has one
+    pub(crate) fn format(&self) -> Option<Arc<str>> {
+        self.inner.read().format.clone()
+    }
 }
 
-impl<T: Display + Send + Sync + 'static> State<T> {
+impl<T: Clone + Send + Sync + 'static> State<T> {
+    /// Returns a clone of the value
+    pub fn cloned(&self) -> T {
+        self.get().clone()
+    }
+}
+
+impl<T: Send + Sync + 'static> State<T> {
     pub fn set(&self, value: T) {
         self.try_set(value).unwrap()
     }
 
     pub fn try_set(&self, value: T) -> Result<(), BorrowMutError> {
-        let string = value.to_string();
-
         let mut w = self.inner.try_write()?;
+        if w.eq.as_ref().is_some_and(|eq| eq(&w.value, &value)) {
+            return Ok(());
+        }
+
         w.value = value;
+        let change = w.change();
+        drop(w);
+
+        self.send(change);
 
+        Ok(())
+    }
+
+    /// Changes the value in place with `f`, without replacing it, like to push to a `Vec`.
+    ///
+    /// The change is sent even if `f` leaves the value as it was, since there's no old value
+    /// to compare it to, so the comparator of states created with
+    /// [`Context::use_state_with_eq`](crate::context::Context::use_state_with_eq) isn't used.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        self.try_update(f).unwrap()
+    }
+
+    pub fn try_update(&self, f: impl FnOnce(&mut T)) -> Result<(), BorrowMutError> {
+        let mut w = self.inner.try_write()?;
+        f(&mut w.value);
+        let change = w.change();
         drop(w);
 
-        let w = self.inner.read();
-        w.changes_tx.send((self.id, string)).unwrap();
+        self.send(change);
 
         Ok(())
     }
 
+    /// Sends `change`, or adds it to the current [`batch`] if there is one
+    fn send(&self, change: StateChange) {
+        let inner = self.inner.read();
+        let change = BATCH.with(|batch| match batch.borrow_mut().as_mut() {
+            Some(batch) => {
+                batch.push((inner.changes_tx.clone(), self.id, change));
+                None
+            }
+            None => Some(change),
+        });
+        if let Some(change) = change {
+            inner.changes_tx.send((self.id, change)).unwrap();
+        }
+    }
+
     pub fn try_modify(&self, f: impl Fn(&T) -> T) -> Result<(), ModifyError> {
-        let value = self.try_get().map_err(ModifyError::BorrowError)?;
-        let value = f(&*value);
+        // the read guard has to be dropped before setting, or the write borrow never succeeds
+        let value = {
+            let current = self.try_get().map_err(ModifyError::BorrowError)?;
+            f(&*current)
+        };
         self.try_set(value).map_err(ModifyError::BorrowMutError)?;
 
         Ok(())
@@ -112,22 +426,294 @@ pub enum ModifyError {
 }
 
 pub trait AnyState: Send + Sync + 'static {
-    fn set_value(&self, value: serde_json::Value);
+    /// Sets the state to `value`, failing if it isn't a valid `T`
+    fn set_value(&self, value: serde_json::Value) -> Result<(), serde_json::Error>;
+    /// Returns the change that would be sent if the state was set to its current value
+    fn change(&self) -> StateChange;
 }
 
-impl<T: DeserializeOwned + Display + Send + Sync + 'static> AnyState for State<T> {
-    fn set_value(&self, value: serde_json::Value) {
+impl<T: DeserializeOwned + Send + Sync + 'static> AnyState for State<T> {
+    fn set_value(&self, value: serde_json::Value) -> Result<(), serde_json::Error> {
         // numbers arrive as strings, so the from_value later doesn't work
         // we manually test inside the string.
         // if it succeeds we set the value, and if it fails we ignore and try the normal deserialize
         if let serde_json::Value::String(s) = &value {
             if let Ok(value) = serde_json::from_str::<T>(s) {
                 self.set(value);
-                return;
+                return Ok(());
             }
         }
 
-        let value: T = serde_json::from_value(value).unwrap();
+        let value: T = serde_json::from_value(value)?;
         self.set(value);
+        Ok(())
+    }
+
+    fn change(&self) -> StateChange {
+        let inner = self.inner.read();
+
+        StateChange {
+            display: (inner.display)(&inner.value),
+            value: inner
+                .serialize
+                .and_then(|serialize| serialize(&inner.value)),
+            patch: None,
+            more: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{SetStateError, StateChange, TextPatch};
+    use crate::context::Context;
+
+    #[test]
+    fn test_state_change_only_has_display() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(1u32);
+        state.set(2);
+
+        let (id, change) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!(state.id, id);
+        assert_eq!("2", change.display);
+        assert_eq!(None, change.value);
+    }
+
+    #[test]
+    fn test_invalid_value_is_an_error() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(1u32);
+        let Err(SetStateError::InvalidValue(id, _)) = ctx.states.set(state.id, json!("abc")) else {
+            panic!("expected an invalid value error");
+        };
+        assert_eq!(state.id, id);
+        assert_eq!(1, *state.get());
+        assert!(ctx.states.changes_rx.try_recv().is_err());
+
+        // numbers are still parsed from strings
+        ctx.states.set(state.id, json!("5")).unwrap();
+        assert_eq!(5, *state.get());
+    }
+
+    #[test]
+    fn test_modify_sets_the_new_value() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(1u32);
+        state.modify(|value| value + 1);
+
+        assert_eq!(2, *state.get());
+        let (_, change) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!("2", change.display);
+    }
+
+    #[test]
+    fn test_update_mutates_in_place() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_json_state(vec![1, 2]);
+        state.update(|values| values.push(3));
+
+        assert_eq!(vec![1, 2, 3], *state.get());
+        let (id, change) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!(state.id, id);
+        assert_eq!("[1,2,3]", change.display);
+    }
+
+    #[test]
+    fn test_typed_state_change_has_value() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_typed_state(1u32);
+        state.set(2);
+
+        let (id, change) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!(state.id, id);
+        assert_eq!("2", change.display);
+        assert_eq!(Some(json!(2)), change.value);
+    }
+
+    #[test]
+    fn test_with_borrows_value() {
+        // not Clone, so it can only be read by borrowing
+        #[derive(serde::Deserialize)]
+        struct Big(Vec<u32>);
+        impl std::fmt::Display for Big {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{} items", self.0.len())
+            }
+        }
+
+        let mut ctx = Context::<()>::new(0, true);
+        let state = ctx.use_state(Big(vec![1, 2, 3]));
+
+        assert_eq!(6, state.with(|big| big.0.iter().sum::<u32>()));
+        // the borrow has ended, so the state can be set
+        state.set(Big(vec![]));
+        assert!(state.with(|big| big.0.is_empty()));
+    }
+
+    #[test]
+    fn test_get_guard_borrows_value() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state("hello".to_string());
+        let guard = state.get();
+        assert_eq!("hello", *guard);
+        assert_eq!(5, guard.len());
+        // the guard has to be dropped before setting the state
+        drop(guard);
+
+        state.set("bye".to_string());
+        assert_eq!("bye", *state.get());
+    }
+
+    #[test]
+    fn test_cloned() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state("hello".to_string());
+        let value: String = state.cloned();
+        state.set("bye".to_string());
+
+        assert_eq!("hello", value);
+        assert_eq!("bye", *state.get());
+    }
+
+    #[test]
+    fn test_snapshot_has_current_values() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let state = ctx.use_state(1u32);
+        let typed = ctx.use_typed_state(2u32);
+        state.set(3);
+
+        let snapshot = ctx.states.snapshot(&[state.id, typed.id]);
+        assert_eq!(
+            vec![
+                (
+                    state.id,
+                    StateChange {
+                        display: "3".to_string(),
+                        value: None,
+                        patch: None,
+                        more: false,
+                    }
+                ),
+                (
+                    typed.id,
+                    StateChange {
+                        display: "2".to_string(),
+                        value: Some(json!(2)),
+                        patch: None,
+                        more: false,
+                    }
+                ),
+            ],
+            snapshot
+        );
+    }
+
+    #[test]
+    fn test_diffed_state_sends_patches_after_the_first_change() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let log = ctx.use_state_diffed(String::new());
+        log.set("first line\n".to_string());
+        log.modify(|log| format!("{log}second line\n"));
+        log.set("x".to_string());
+
+        let (_, first) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!(None, first.patch);
+
+        let (_, second) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!("first line\nsecond line\n", second.display);
+        assert_eq!(
+            Some(TextPatch {
+                start: 11,
+                end: 11,
+                text: "second line\n".to_string(),
+            }),
+            second.patch
+        );
+
+        // replacing all of it is sent whole
+        let (_, third) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!(None, third.patch);
+    }
+
+    #[test]
+    fn test_text_patch_offsets_are_utf16() {
+        assert_eq!(
+            TextPatch {
+                start: 3,
+                end: 4,
+                text: "c".to_string(),
+            },
+            TextPatch::between("a😀b", "a😀c")
+        );
+        assert_eq!(
+            TextPatch {
+                start: 1,
+                end: 3,
+                text: String::new(),
+            },
+            TextPatch::between("a😀b", "ab")
+        );
+        assert_eq!(
+            TextPatch {
+                start: 2,
+                end: 2,
+                text: "a".to_string(),
+            },
+            TextPatch::between("aa", "aaa")
+        );
+    }
+
+    #[test]
+    fn test_values_equal_by_eq_are_ignored() {
+        let mut ctx = Context::<()>::new(0, true);
+
+        let temperature =
+            ctx.use_state_with_eq(20.0, |old: &f64, new: &f64| (old - new).abs() < 0.1);
+        temperature.set(20.05);
+        assert_eq!(20.0, *temperature.get());
+        assert!(ctx.states.changes_rx.try_recv().is_err());
+
+        temperature.set(20.5);
+        assert_eq!(20.5, *temperature.get());
+        let (_, change) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!("20.5", change.display);
+
+        // compared against the current value, not the one that was ignored
+        temperature.modify(|t| t - 0.09);
+        temperature.modify(|t| t - 0.09);
+        assert_eq!(20.5, *temperature.get());
+        assert!(ctx.states.changes_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_json_state_round_trips() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        let mut ctx = Context::<()>::new(0, true);
+
+        let point = ctx.use_json_state(Point { x: 1, y: 2 });
+        point.set(Point { x: 3, y: 4 });
+        let (_, change) = ctx.states.changes_rx.try_recv().unwrap();
+        assert_eq!(r#"{"x":3,"y":4}"#, change.display);
+
+        // the client sends it back as it got it
+        ctx.states.set(point.id, json!(change.display)).unwrap();
+        assert_eq!(Point { x: 3, y: 4 }, *point.get());
     }
 }
@@ -0,0 +1,83 @@
+//! Helpers for unit testing closures, enabled with the `test` feature.
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn test_search() {
+//!     let mut ctx = testing::context();
+//!     let results = ctx.use_state(0usize);
+//!     let search = ctx.use_closure(move |Query(q): Query<Search>| async move {
+//!         results.set(q.term.len());
+//!     });
+//!
+//!     let parts = testing::parts(Request::get("/?term=cats"));
+//!     testing::call_closure_with(&mut ctx, search, parts, ()).await;
+//!     assert_eq!(4, *results.get());
+//! }
+//! ```
+
+use axum::http::{request::Builder, request::Parts};
+use serde_json::Value;
+
+use crate::{closures::Closure, context::Context};
+
+/// Creates a context as it is once the websocket has connected
+pub fn context<S>() -> Context<S> {
+    Context::new(0, true)
+}
+
+/// Builds the parts of a request, which closures extract from.
+///
+/// The request a closure gets is the websocket upgrade, which has the same path and query as the page.
+pub fn parts(request: Builder) -> Parts {
+    request
+        .body(())
+        .expect("request builder is valid")
+        .into_parts()
+        .0
+}
+
+/// Runs `closure` until it finishes, extracting its arguments from `parts` and `state`.
+///
+/// Unlike [`Closure::call`], this doesn't need the websocket to be connected. Extractors that
+/// reject the request skip the closure, like they do when it's called by the client.
+/// Panics in the closure are propagated, so they fail the test.
+pub async fn call_closure_with<S>(ctx: &mut Context<S>, closure: Closure, parts: Parts, state: S) {
+    let closure = ctx
+        .closures
+        .get(closure.id)
+        .expect("closure was created with this context");
+    closure.call(Value::Null, parts, state).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::{Query, State},
+        http::Request,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_closure_with_query_and_state() {
+        #[derive(serde::Deserialize)]
+        struct Search {
+            term: String,
+        }
+
+        let mut ctx = context::<u32>();
+        let results = ctx.use_state(String::new());
+        let search = ctx.use_closure(
+            move |Query(search): Query<Search>, State(limit): State<u32>| async move {
+                results.set(format!("{} up to {limit}", search.term));
+            },
+        );
+
+        call_closure_with(&mut ctx, search, parts(Request::get("/?term=cats")), 10).await;
+        assert_eq!("cats up to 10", *results.get());
+
+        // a rejected extractor skips the closure
+        call_closure_with(&mut ctx, search, parts(Request::get("/")), 10).await;
+        assert_eq!("cats up to 10", *results.get());
+    }
+}